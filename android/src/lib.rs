@@ -4,7 +4,11 @@ pub mod jni_exts;
 pub mod mantle_error;
 #[cfg(feature = "mqtt")]
 pub mod mqtt;
+#[cfg(feature = "poll_manager")]
+pub mod poll_manager;
 pub mod result;
+#[cfg(feature = "storage")]
+pub mod storage;
 
 #[cfg(feature = "http")]
 pub mod http;
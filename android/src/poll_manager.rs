@@ -0,0 +1,16 @@
+use jni::sys::jboolean;
+use mantle_utilities::poll_manager::lifecycle;
+
+/// Pauses polls that don't `run_in_background` while the app is in the background.
+#[no_mangle]
+#[allow(improper_ctypes_definitions, clippy::missing_safety_doc)]
+pub unsafe extern "C" fn android_poll_manager_set_app_in_background(in_background: jboolean) {
+    lifecycle::set_app_in_background(in_background != 0);
+}
+
+/// Pauses polls that don't `run_in_background` while there's no network.
+#[no_mangle]
+#[allow(improper_ctypes_definitions, clippy::missing_safety_doc)]
+pub unsafe extern "C" fn android_poll_manager_set_network_available(available: jboolean) {
+    lifecycle::set_network_available(available != 0);
+}
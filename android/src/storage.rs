@@ -0,0 +1,134 @@
+use jni::objects::{JObject, JString, JValue};
+use jni::sys::{jboolean, jbyteArray, jlong, JNI_TRUE};
+use jni::JNIEnv;
+use log::error;
+use mantle_utilities::error::MantleResultError;
+use mantle_utilities::storage::storage_ffi_wrapper;
+use mantle_utilities::storage::{StorageChange, SubscriptionId};
+
+use crate::callback::holder::CallbackStruct;
+use crate::callback::invoke_callback;
+
+/// `(dir, key, oldValue, newValue)`. Values are JSON, `null` for a missing value.
+const STORAGE_CHANGE_INVOKE_SIG: &str =
+    "(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;)V";
+
+#[no_mangle]
+#[allow(improper_ctypes_definitions, clippy::missing_safety_doc)]
+pub unsafe extern "C" fn android_storage_init(
+    env: JNIEnv,
+    os_dir: JString,
+) -> Result<(), Box<dyn MantleResultError>> {
+    let os_dir = env
+        .get_string(os_dir)
+        .expect("couldn't get java string")
+        .into();
+    storage_ffi_wrapper::init(os_dir)
+}
+
+/// Invokes the Kotlin `callback` on every change of the value for the `key` in the `dir`.
+/// Returns the id to unsubscribe with.
+#[no_mangle]
+#[allow(improper_ctypes_definitions, clippy::missing_safety_doc)]
+pub unsafe extern "C" fn android_storage_subscribe(
+    env: JNIEnv,
+    dir: JString,
+    key: JString,
+    callback: JObject,
+) -> Result<jlong, Box<dyn MantleResultError>> {
+    let dir = env
+        .get_string(dir)
+        .expect("couldn't get java string")
+        .into();
+    let key = env
+        .get_string(key)
+        .expect("couldn't get java string")
+        .into();
+    let callback = CallbackStruct::with_callback(env, callback);
+    let id = storage_ffi_wrapper::subscribe(dir, key, move |change| {
+        invoke_storage_change_callback(&callback, change)
+    })?;
+    Ok(id.0 as jlong)
+}
+
+#[no_mangle]
+#[allow(improper_ctypes_definitions, clippy::missing_safety_doc)]
+pub unsafe extern "C" fn android_storage_unsubscribe(
+    id: jlong,
+) -> Result<jboolean, Box<dyn MantleResultError>> {
+    storage_ffi_wrapper::unsubscribe(SubscriptionId(id as u64)).map(|removed| removed as jboolean)
+}
+
+/// Encrypts the `dir` with the 32 bytes `key` kept in the Android Keystore.
+/// Has to be called every time the storage is initialized, before the dir is used.
+#[no_mangle]
+#[allow(improper_ctypes_definitions, clippy::missing_safety_doc)]
+pub unsafe extern "C" fn android_storage_set_encryption_key(
+    env: JNIEnv,
+    dir: JString,
+    key: jbyteArray,
+) -> Result<(), Box<dyn MantleResultError>> {
+    let dir = env
+        .get_string(dir)
+        .expect("couldn't get java string")
+        .into();
+    let key = env
+        .convert_byte_array(key)
+        .expect("couldn't get java byte array");
+    storage_ffi_wrapper::set_encryption_key(dir, key)
+}
+
+/// Writes an archive of the storage db to the file at `path`.
+#[no_mangle]
+#[allow(improper_ctypes_definitions, clippy::missing_safety_doc)]
+pub unsafe extern "C" fn android_storage_export_db(
+    env: JNIEnv,
+    path: JString,
+) -> Result<(), Box<dyn MantleResultError>> {
+    let path = env
+        .get_string(path)
+        .expect("couldn't get java string")
+        .into();
+    storage_ffi_wrapper::export_db(path)
+}
+
+/// Restores the storage db from the archive file at `path`, replacing the existing data if `replace` is set.
+#[no_mangle]
+#[allow(improper_ctypes_definitions, clippy::missing_safety_doc)]
+pub unsafe extern "C" fn android_storage_import_db(
+    env: JNIEnv,
+    path: JString,
+    replace: jboolean,
+) -> Result<(), Box<dyn MantleResultError>> {
+    let path = env
+        .get_string(path)
+        .expect("couldn't get java string")
+        .into();
+    storage_ffi_wrapper::import_db(path, replace == JNI_TRUE)
+}
+
+fn invoke_storage_change_callback(callback: &CallbackStruct, change: &StorageChange) {
+    let Some((env, callback)) = callback.get_callback_ref() else {
+        error!("Storage change callback isn't set for '{}'", change.key);
+        return;
+    };
+    let args = [
+        change.dir.to_string_lossy().into_owned(),
+        change.key.clone(),
+        change.old_value.to_string(),
+        change.new_value.to_string(),
+    ]
+    .map(|arg| {
+        env.new_string(arg).unwrap_or_else(|err| {
+            error!("Error creating java string for storage change: {:?}", err);
+            env.exception_describe().unwrap();
+            panic!();
+        })
+    });
+    invoke_callback(
+        env,
+        callback,
+        STORAGE_CHANGE_INVOKE_SIG.to_string(),
+        &args.map(|arg| JValue::Object(arg.into())),
+    );
+}
@@ -20,10 +20,20 @@ pub struct Encrypted {
 ///
 /// Used by the `confenc!` macro.
 pub fn encrypt<'msg, 'aad>(data: impl Into<Payload<'msg, 'aad>>) -> CrydecResult<Encrypted> {
+    let key = Aes256Gcm::generate_key(&mut OsRng);
+    encrypt_with_key(&key.into(), data)
+}
+
+/// Encrypts data using AES-GCM with the given 256-bit key and a random 96-bit nonce.
+///
+/// Used when the key is managed outside of this crate, e.g. stored by the platform.
+pub fn encrypt_with_key<'msg, 'aad>(
+    key: &[u8; 32],
+    data: impl Into<Payload<'msg, 'aad>>,
+) -> CrydecResult<Encrypted> {
     let mut rng = rand::thread_rng();
     let nonce = Nonce::from(rng.gen::<[u8; 12]>());
-    let key = Aes256Gcm::generate_key(&mut OsRng);
-    let cipher = Aes256Gcm::new(&key);
+    let cipher = Aes256Gcm::new(key.into());
     let encrypted = cipher
         .encrypt(&nonce, data)
         .map_err(CrydecError::EncryptionError)?;
@@ -35,7 +45,7 @@ pub fn encrypt<'msg, 'aad>(data: impl Into<Payload<'msg, 'aad>>) -> CrydecResult
     Ok(Encrypted {
         data: encrypted,
         nonce: nonce.into(),
-        key: key.into(),
+        key: *key,
     })
 }
 
@@ -69,6 +79,13 @@ mod tests {
             prop_assert_eq!(data, decrypted);
         }
 
+        #[test]
+        fn data_encrypted_with_key_should_be_decrypted_with_the_same_key(data in ".*", key in any::<[u8; 32]>()) {
+            let encrypted = encrypt_with_key(&key, data.as_bytes()).unwrap();
+            let decrypted = decrypt(&key, &encrypted.nonce, &encrypted.data).unwrap();
+            prop_assert_eq!(data, decrypted);
+        }
+
         #[test]
         fn encrypted_data_should_be_different_from_the_original_data(data in ".*") {
             let encrypted = encrypt(data.as_bytes()).unwrap();
@@ -2,7 +2,11 @@ pub mod error;
 pub mod list;
 #[cfg(feature = "mqtt")]
 pub mod mqtt;
+#[cfg(feature = "poll_manager")]
+pub mod poll_manager;
 pub mod result;
+#[cfg(feature = "storage")]
+pub mod storage;
 
 #[cfg(feature = "http")]
 pub mod http;
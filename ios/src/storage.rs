@@ -0,0 +1,113 @@
+use crate::list::MantleList;
+use mantle_utilities::storage::storage_ffi_wrapper;
+use mantle_utilities::storage::{StorageChange, SubscriptionId};
+use mantle_utilities::{error::MantleResultError, string::MantleStringPointer};
+use std::ffi::{c_void, CString};
+use std::os::raw::c_char;
+
+/// Called with the `context` passed to [ios_storage_subscribe], the dir, the key and the old and new values.
+/// Values are JSON, `null` for a missing value. The strings are freed after the call returns, so they have to be copied.
+pub type StorageChangeCallback = extern "C" fn(
+    context: *const c_void,
+    dir: *const c_char,
+    key: *const c_char,
+    old_value: *const c_char,
+    new_value: *const c_char,
+);
+
+/// The Swift side keeps the context alive and thread safe until it unsubscribes.
+struct CallbackContext(*const c_void);
+
+unsafe impl Send for CallbackContext {}
+unsafe impl Sync for CallbackContext {}
+
+#[no_mangle]
+#[allow(improper_ctypes_definitions, clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ios_storage_init(
+    os_dir: *const c_char,
+) -> Result<(), Box<dyn MantleResultError>> {
+    let os_dir = MantleStringPointer(os_dir).to_string();
+    storage_ffi_wrapper::init(os_dir)
+}
+
+/// Calls the `callback` on every change of the value for the `key` in the `dir`.
+/// Returns the id to unsubscribe with.
+#[no_mangle]
+#[allow(improper_ctypes_definitions, clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ios_storage_subscribe(
+    dir: *const c_char,
+    key: *const c_char,
+    context: *const c_void,
+    callback: StorageChangeCallback,
+) -> Result<u64, Box<dyn MantleResultError>> {
+    let dir = MantleStringPointer(dir).to_string();
+    let key = MantleStringPointer(key).to_string();
+    let context = CallbackContext(context);
+    let id = storage_ffi_wrapper::subscribe(dir, key, move |change| {
+        invoke_storage_change_callback(callback, &context, change)
+    })?;
+    Ok(id.0)
+}
+
+#[no_mangle]
+#[allow(improper_ctypes_definitions, clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ios_storage_unsubscribe(
+    id: u64,
+) -> Result<bool, Box<dyn MantleResultError>> {
+    storage_ffi_wrapper::unsubscribe(SubscriptionId(id))
+}
+
+/// Encrypts the `dir` with the 32 bytes `key` kept in the iOS Keychain.
+/// Has to be called every time the storage is initialized, before the dir is used.
+#[no_mangle]
+#[allow(improper_ctypes_definitions, clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ios_storage_set_encryption_key(
+    dir: *const c_char,
+    key: *const MantleList<u8>,
+) -> Result<(), Box<dyn MantleResultError>> {
+    let dir = MantleStringPointer(dir).to_string();
+    let key = MantleList::copy_to_vec_ptr(key);
+    storage_ffi_wrapper::set_encryption_key(dir, key)
+}
+
+/// Writes an archive of the storage db to the file at `path`.
+#[no_mangle]
+#[allow(improper_ctypes_definitions, clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ios_storage_export_db(
+    path: *const c_char,
+) -> Result<(), Box<dyn MantleResultError>> {
+    let path = MantleStringPointer(path).to_string();
+    storage_ffi_wrapper::export_db(path)
+}
+
+/// Restores the storage db from the archive file at `path`, replacing the existing data if `replace` is set.
+#[no_mangle]
+#[allow(improper_ctypes_definitions, clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ios_storage_import_db(
+    path: *const c_char,
+    replace: bool,
+) -> Result<(), Box<dyn MantleResultError>> {
+    let path = MantleStringPointer(path).to_string();
+    storage_ffi_wrapper::import_db(path, replace)
+}
+
+fn invoke_storage_change_callback(
+    callback: StorageChangeCallback,
+    context: &CallbackContext,
+    change: &StorageChange,
+) {
+    let [dir, key, old_value, new_value] = [
+        change.dir.to_string_lossy().into_owned(),
+        change.key.clone(),
+        change.old_value.to_string(),
+        change.new_value.to_string(),
+    ]
+    .map(|arg| CString::new(arg).unwrap_or_default());
+    callback(
+        context.0,
+        dir.as_ptr(),
+        key.as_ptr(),
+        old_value.as_ptr(),
+        new_value.as_ptr(),
+    );
+}
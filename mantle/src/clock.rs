@@ -0,0 +1,120 @@
+//! Time sources for timers. [SystemClock] is the real time, and [MockClock] lets tests advance time by hand.
+use std::fmt::{Debug, Formatter};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Called when a [MockClock] is advanced. Returns false when the listener isn't needed anymore, so it's removed.
+pub type ClockListener = Box<dyn Fn() -> bool + Send>;
+
+/// A source of monotonic and wall-clock time, e.g. for [crate::poll_manager::PollManager::with_clock].
+pub trait Clock: Debug + Send + Sync {
+    /// Monotonic time for delays and timeouts.
+    fn now(&self) -> Instant;
+
+    /// Wall-clock time for wall-clock schedules and timestamps.
+    fn system_time(&self) -> SystemTime;
+
+    /// Returns how long to really wait for the `duration` of this clock to pass.
+    /// None waits until a listener is called, which is how virtual clocks end waits.
+    fn real_duration(&self, duration: Duration) -> Option<Duration> {
+        Some(duration)
+    }
+
+    /// Calls the `listener` whenever the clock jumps. Real clocks don't jump, so they drop it.
+    fn listen(&self, _listener: ClockListener) {}
+}
+
+/// The real time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Virtual time that only moves by [MockClock::advance], so timing can be tested instantly and deterministically.
+///
+/// # Examples
+///
+/// ```
+/// use mantle_utilities::clock::{Clock, MockClock};
+/// use std::time::Duration;
+///
+/// let clock = MockClock::new();
+/// let start = clock.now();
+/// clock.advance(Duration::from_secs(60));
+/// assert_eq!(clock.now() - start, Duration::from_secs(60));
+/// ```
+pub struct MockClock {
+    start: Instant,
+    start_system_time: SystemTime,
+    elapsed: Mutex<Duration>,
+    listeners: Mutex<Vec<ClockListener>>,
+}
+
+impl MockClock {
+    /// Starts at the current system time.
+    pub fn new() -> Self {
+        MockClock::at(SystemTime::now())
+    }
+
+    /// Starts at the `system_time`.
+    pub fn at(system_time: SystemTime) -> Self {
+        MockClock {
+            start: Instant::now(),
+            start_system_time: system_time,
+            elapsed: Default::default(),
+            listeners: Default::default(),
+        }
+    }
+
+    /// Moves the time forward and wakes everything waiting for it.
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+        self.listeners.lock().unwrap().retain(|listener| listener());
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.start_system_time + self.elapsed()
+    }
+
+    fn real_duration(&self, _duration: Duration) -> Option<Duration> {
+        None
+    }
+
+    fn listen(&self, listener: ClockListener) {
+        self.listeners.lock().unwrap().push(listener);
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl Debug for MockClock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockClock")
+            .field("start_system_time", &self.start_system_time)
+            .field("elapsed", &self.elapsed())
+            .field("listeners", &self.listeners.lock().unwrap().len())
+            .finish()
+    }
+}
@@ -1,12 +1,26 @@
+use crate::db::index::{is_index_bucket, IndexDef, IndexRegistry};
+use crate::error::MantleResultError;
 use log::warn;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::error::Error;
 use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::marker::PhantomData;
+use std::path::Path;
 use std::sync::Arc;
 
+mod archive;
+mod index;
+pub mod memory_db;
 pub mod sled_db;
+#[cfg(feature = "sqlite-impl")]
+pub mod sqlite_db;
+
+pub use archive::ImportMode;
+pub use index::Index;
 
 /// A trait for a db that can create typed buckets.
 pub trait DbEngine: Debug + Send + Sync + 'static {
@@ -15,6 +29,47 @@ pub trait DbEngine: Debug + Send + Sync + 'static {
 
     /// Removes [Bucket] from the disk.
     fn delete_bucket(&self, id: &str) -> DbResult<bool>;
+
+    /// Returns ids of all buckets in the db.
+    ///
+    /// Needed by [Db::stats], [Db::export] and [ImportMode::Replace].
+    /// The default implementation fails, engines that can list their buckets should override it.
+    fn bucket_ids(&self) -> DbResult<Vec<String>> {
+        Err(DbError::DbEngineError(
+            "the engine doesn't support listing buckets".into(),
+        ))
+    }
+
+    /// Runs `f` over the buckets with the `ids` (opening them if needed) as a single atomic operation. The `ids` must be unique.
+    /// If `f` returns an error, none of its changes are applied. `f` can be called several times if the engine retries on conflicts.
+    ///
+    /// The default implementation isn't atomic: `f` runs directly on the buckets.
+    /// Engines that support transactions should override it.
+    fn transaction(
+        &self,
+        ids: &[&str],
+        f: &dyn Fn(&[&dyn BucketTransaction]) -> DbResult<()>,
+    ) -> DbResult<()> {
+        let buckets = ids
+            .iter()
+            .map(|id| self.open_bucket(id))
+            .collect::<DbResult<Vec<_>>>()?;
+        let buckets: Vec<&dyn BucketTransaction> = buckets
+            .iter()
+            .map(|bucket| bucket as &dyn BucketTransaction)
+            .collect();
+        f(&buckets)
+    }
+
+    /// Returns the space taken by the db on the disk in bytes, or None if the engine doesn't store data on the disk.
+    fn size_on_disk(&self) -> DbResult<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Writes all buffered changes to the disk. Does nothing by default.
+    fn flush(&self) -> DbResult<()> {
+        Ok(())
+    }
 }
 
 /// A trait for a key-value bucket that only supports bytes.
@@ -29,19 +84,33 @@ pub trait BucketEngine: Debug + Send + Sync + 'static {
     fn remove(&self, key: &[u8]) -> DbResult<Option<Bytes>>;
 
     /// Returns an iterator over all key-value pairs in the bucket.
-    /// Order is arbitrary.
+    /// Pairs are ordered by key bytes.
     fn iter(&self) -> DbResult<Box<dyn Iterator<Item = DbResult<Pair>>>>;
 
     /// Returns an iterator over all keys in the bucket.
+    /// Keys are ordered by their bytes.
     fn keys(&self) -> DbResult<Box<dyn Iterator<Item = DbResult<Bytes>>>>;
 
     /// Returns an iterator over all values in the bucket.
+    /// Values are ordered by their key bytes.
     fn values(&self) -> DbResult<Box<dyn Iterator<Item = DbResult<Bytes>>>>;
 
     /// Removes all values from the bucket.
     fn clear(&self) -> DbResult<()>;
 }
 
+/// A bucket inside a [DbEngine::transaction]. Only supports point operations.
+pub trait BucketTransaction {
+    /// Retrieves a value from the bucket if it exists.
+    fn get(&self, key: &[u8]) -> DbResult<Option<Bytes>>;
+
+    /// Inserts a key-value pair to the bucket, returning the old value if it was set.
+    fn insert(&self, key: &[u8], value: &[u8]) -> DbResult<Option<Bytes>>;
+
+    /// Removes a key from the bucket, returning the value at the key if the key was previously in the bucket.
+    fn remove(&self, key: &[u8]) -> DbResult<Option<Bytes>>;
+}
+
 // Avoid an unnecessary memory copy of bytes.
 pub type Bytes = Box<dyn AsRef<[u8]>>;
 pub type DbResult<T> = Result<T, DbError>;
@@ -75,26 +144,52 @@ pub type Pair = (Bytes, Bytes);
 #[derive(Debug, Clone)]
 pub struct Db {
     engine: Arc<dyn DbEngine>,
+    indexes: IndexRegistry,
 }
 
 /// A bucket that supports typed key/value pairs.
 /// A bucket represents a single logical keyspace.
-/// Key-value pairs stored in a [BucketEngine] as bytes. A [Bucket] uses bincode to (de)serialize them.
+/// Key-value pairs stored in a [BucketEngine] as bytes. A [Bucket] uses bincode to (de)serialize keys
+/// and the [ValueCodec] `C` for values, [Bincode] by default.
 ///
 /// # Supported Types
 ///
 /// The bucket can store any type that implements the [serde::Serialize] and [serde::Deserialize] traits with one exception.
-/// Types that use deserialize_any aren't supported (e.g. untagged enums).
+/// Types that use deserialize_any aren't supported by bincode (e.g. untagged enums or [serde_json::Value]).
+/// Values of such types can be stored with the [Json] codec, see [Db::open_bucket_with_codec].
+///
+/// # Secondary Indexes
+///
+/// Records can be looked up by a field other than the key with an [Index] declared by [Bucket::add_index].
+/// Indexes are kept up to date on every write, atomically if the [DbEngine] supports transactions.
 #[derive(Debug, Clone)]
-pub struct Bucket<K, V> {
+pub struct Bucket<K, V, C = Bincode> {
+    id: String,
     engine: Arc<dyn BucketEngine>,
-    _marker: PhantomData<(K, V)>,
+    db_engine: Arc<dyn DbEngine>,
+    indexes: IndexRegistry,
+    _marker: PhantomData<(K, V, C)>,
+}
+
+/// Encodes the values of a [Bucket].
+pub trait ValueCodec: Debug + Clone + Send + Sync + 'static {
+    fn encode<V: Serialize>(value: &V) -> DbResult<Vec<u8>>;
+
+    fn decode<V: DeserializeOwned>(bytes: &[u8]) -> DbResult<V>;
 }
 
+/// The default [ValueCodec]. Compact, but can't decode types that use deserialize_any.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+/// A [ValueCodec] that stores values as JSON text, e.g. for [serde_json::Value].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
 /// Iterator over key-value pairs in a [Bucket].
-pub struct Iter<K, V> {
+pub struct Iter<K, V, C = Bincode> {
     engine_iter: Box<dyn Iterator<Item = DbResult<Pair>>>,
-    _marker: PhantomData<(K, V)>,
+    _marker: PhantomData<(K, V, C)>,
 }
 
 /// Iterator over keys in a [Bucket].
@@ -104,9 +199,30 @@ pub struct KeysIter<K> {
 }
 
 /// Iterator over values in a [Bucket].
-pub struct ValuesIter<V> {
+pub struct ValuesIter<V, C = Bincode> {
     engine_iter: Box<dyn Iterator<Item = DbResult<Bytes>>>,
-    _marker: PhantomData<V>,
+    _marker: PhantomData<(V, C)>,
+}
+
+/// Usage statistics of a [Db] returned by [Db::stats].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DbStats {
+    /// Space taken on the disk in bytes. None if the engine doesn't store data on the disk.
+    pub size_on_disk: Option<u64>,
+    /// Statistics of every bucket, ordered by bucket id.
+    pub buckets: Vec<BucketStats>,
+}
+
+/// Usage statistics of a single bucket.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BucketStats {
+    pub id: String,
+    /// Number of key-value pairs.
+    pub entries: u64,
+    /// Total size of encoded keys in bytes.
+    pub key_bytes: u64,
+    /// Total size of encoded values in bytes.
+    pub value_bytes: u64,
 }
 
 /// An Error type encapsulates all possible errors in a [DbEngine].
@@ -118,6 +234,18 @@ pub enum DbError {
     /// Implementation specific error.
     #[error(transparent)]
     DbEngineError(#[from] Box<dyn Error + Send + Sync + 'static>),
+    /// Reading or writing an export archive failed.
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+    /// The imported archive is corrupted or has an unsupported format.
+    #[error("invalid archive: {0}")]
+    ArchiveError(String),
+    /// The index wasn't declared on the bucket.
+    #[error("index not found: {0}")]
+    IndexNotFound(String),
+    /// The bucket id uses the prefix reserved for index buckets.
+    #[error("bucket id is reserved: {0}")]
+    ReservedBucketId(String),
 }
 
 impl Db {
@@ -125,6 +253,7 @@ impl Db {
     pub fn new(engine: Box<dyn DbEngine>) -> Self {
         Db {
             engine: engine.into(),
+            indexes: Default::default(),
         }
     }
 
@@ -134,27 +263,196 @@ impl Db {
         K: Serialize + DeserializeOwned,
         V: Serialize + DeserializeOwned,
     {
-        let bucket_engine = self.engine.open_bucket(id.as_ref())?;
-        Ok(Bucket::new(bucket_engine))
+        self.open_bucket_with_codec(id)
     }
 
-    /// Removes [Bucket] from the disk.
+    /// Opens a [Bucket] like [Db::open_bucket], encoding its values with the codec `C`.
+    /// A bucket has to be opened with the same codec every time.
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use mantle_utilities::db::{Bucket, Db, Json};
+    /// use mantle_utilities::db::memory_db::MemoryDb;
+    /// use serde_json::{json, Value};
+    ///
+    /// let db = Db::new(Box::new(MemoryDb::new()));
+    /// let bucket: Bucket<String, Value, Json> = db.open_bucket_with_codec("settings")?;
+    /// bucket.insert(&"theme".to_string(), &json!({ "dark": true }))?;
+    /// assert_eq!(bucket.get(&"theme".to_string())?, Some(json!({ "dark": true })));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn open_bucket_with_codec<K, V, C>(&self, id: impl AsRef<str>) -> DbResult<Bucket<K, V, C>>
+    where
+        K: Serialize + DeserializeOwned,
+        V: Serialize + DeserializeOwned,
+        C: ValueCodec,
+    {
+        let id = id.as_ref();
+        if is_index_bucket(id) {
+            return Err(DbError::ReservedBucketId(id.to_string()));
+        }
+        let bucket_engine = self.engine.open_bucket(id)?;
+        Ok(Bucket {
+            id: id.to_string(),
+            engine: bucket_engine.into(),
+            db_engine: Arc::clone(&self.engine),
+            indexes: Arc::clone(&self.indexes),
+            _marker: Default::default(),
+        })
+    }
+
+    /// Removes [Bucket] and its indexes from the disk.
     pub fn delete_bucket(&self, id: impl AsRef<str>) -> DbResult<bool> {
-        self.engine.delete_bucket(id.as_ref())
+        let id = id.as_ref();
+        if is_index_bucket(id) {
+            return Err(DbError::ReservedBucketId(id.to_string()));
+        }
+        let indexes = self.indexes.write().unwrap().remove(id);
+        for def in indexes.into_iter().flatten() {
+            self.engine.delete_bucket(&def.bucket_id)?;
+        }
+        self.engine.delete_bucket(id)
+    }
+
+    /// Returns ids of all buckets in the db. Buckets storing indexes aren't included.
+    pub fn bucket_ids(&self) -> DbResult<Vec<String>> {
+        let mut ids = self.engine.bucket_ids()?;
+        ids.retain(|id| !is_index_bucket(id));
+        Ok(ids)
+    }
+
+    /// Returns the space taken by the db on the disk in bytes, or None if the engine doesn't store data on the disk.
+    pub fn size_on_disk(&self) -> DbResult<Option<u64>> {
+        self.engine.size_on_disk()
+    }
+
+    /// Collects the on-disk size and per-bucket usage of the db.
+    /// Every bucket is scanned, so it's meant for diagnostics rather than hot paths.
+    /// Index buckets are left out like in [Db::bucket_ids], their size only counts towards [DbStats::size_on_disk].
+    pub fn stats(&self) -> DbResult<DbStats> {
+        let mut ids = self.bucket_ids()?;
+        ids.sort();
+        let buckets = ids
+            .into_iter()
+            .map(|id| self.bucket_stats(id))
+            .collect::<DbResult<_>>()?;
+
+        Ok(DbStats {
+            size_on_disk: self.engine.size_on_disk()?,
+            buckets,
+        })
+    }
+
+    /// Collects the usage of one bucket by scanning it.
+    pub fn bucket_stats(&self, id: impl AsRef<str>) -> DbResult<BucketStats> {
+        let mut stats = BucketStats {
+            id: id.as_ref().to_string(),
+            entries: 0,
+            key_bytes: 0,
+            value_bytes: 0,
+        };
+        for pair in self.engine.open_bucket(&stats.id)?.iter()? {
+            let (key, value) = pair?;
+            stats.entries += 1;
+            stats.key_bytes += key.as_ref().as_ref().len() as u64;
+            stats.value_bytes += value.as_ref().as_ref().len() as u64;
+        }
+        Ok(stats)
+    }
+
+    /// Writes all buffered changes to the disk.
+    pub fn flush(&self) -> DbResult<()> {
+        self.engine.flush()
+    }
+
+    /// Writes all buckets and their raw key-value pairs to the `writer`.
+    /// The archive is versioned and checksummed, so it can be restored by [Db::import] on any [DbEngine] implementation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use mantle_utilities::db::{Bucket, Db, ImportMode};
+    /// use mantle_utilities::db::memory_db::MemoryDb;
+    ///
+    /// let db = Db::new(Box::new(MemoryDb::new()));
+    /// let bucket: Bucket<String, i32> = db.open_bucket("bucket_id")?;
+    /// bucket.insert(&"MyKey".to_string(), &42)?;
+    ///
+    /// let mut archive = Vec::new();
+    /// db.export(&mut archive)?;
+    ///
+    /// let restored_db = Db::new(Box::new(MemoryDb::new()));
+    /// restored_db.import(archive.as_slice(), ImportMode::Replace)?;
+    /// let restored_bucket: Bucket<String, i32> = restored_db.open_bucket("bucket_id")?;
+    /// assert_eq!(Some(42), restored_bucket.get(&"MyKey".to_string())?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn export(&self, writer: impl Write) -> DbResult<()> {
+        archive::export(self.engine.as_ref(), writer)
+    }
+
+    /// Restores buckets from an archive created by [Db::export].
+    /// The archive is validated before any data is changed. The import itself isn't atomic.
+    /// Declared indexes are rebuilt from the imported records.
+    pub fn import(&self, reader: impl Read, mode: ImportMode) -> DbResult<()> {
+        let indexes = self.indexes.write().unwrap();
+        archive::import(self.engine.as_ref(), reader, mode)?;
+        for (bucket_id, defs) in indexes.iter() {
+            let bucket = self.engine.open_bucket(bucket_id)?;
+            for def in defs {
+                def.rebuild(self.engine.as_ref(), bucket.as_ref())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes an archive created by [Db::export] to the file at `path`. The file is created or truncated.
+    /// Used by the mobile bindings, where readers and writers can't be passed.
+    pub fn export_to_file(&self, path: impl AsRef<Path>) -> DbResult<()> {
+        let file = File::create(path)?;
+        self.export(BufWriter::new(file))
+    }
+
+    /// Restores buckets from the archive file at `path`. See [Db::import].
+    pub fn import_from_file(&self, path: impl AsRef<Path>, mode: ImportMode) -> DbResult<()> {
+        let file = File::open(path)?;
+        self.import(BufReader::new(file), mode)
+    }
+}
+
+impl MantleResultError for DbError {
+    fn error_type(&self) -> String {
+        match self {
+            DbError::SerializationError(_) => "SerializationError",
+            DbError::DbEngineError(_) => "DbEngineError",
+            DbError::IoError(_) => "IoError",
+            DbError::ArchiveError(_) => "ArchiveError",
+            DbError::IndexNotFound(_) => "IndexNotFound",
+            DbError::ReservedBucketId(_) => "ReservedBucketId",
+        }
+        .to_owned()
+    }
+
+    fn error_description(&self) -> String {
+        self.to_string()
     }
 }
 
-impl<K, V> Bucket<K, V>
+impl<K, V, C> Bucket<K, V, C>
 where
     K: Serialize + DeserializeOwned,
     V: Serialize + DeserializeOwned,
+    C: ValueCodec,
 {
     /// Retrieves a value from the [Bucket] if it exists.
     pub fn get(&self, key: &K) -> DbResult<Option<V>> {
         let key_encoded = bincode::serialize(key)?;
         let value_encoded = self.engine.get(&key_encoded)?;
         if let Some(value_enc) = value_encoded {
-            let value = bincode::deserialize(value_enc.as_ref().as_ref())?;
+            let value = C::decode(value_enc.as_ref().as_ref())?;
             return Ok(Some(value));
         }
 
@@ -164,21 +462,75 @@ where
     /// Inserts a key-value pair to the [Bucket], returning the old value if it was set.
     pub fn insert(&self, key: &K, value: &V) -> DbResult<Option<V>> {
         let key_encoded = bincode::serialize(key)?;
-        let value_encoded = bincode::serialize(value)?;
-        let old_value = self.engine.insert(&key_encoded, &value_encoded)?;
+        let value_encoded = C::encode(value)?;
+        let old_value = self.write(&key_encoded, Some(&value_encoded))?;
         Ok(self.deserialize_old_value(old_value))
     }
 
     /// Removes a key from the map, returning the value at the key if the key was previously in the [Bucket].
     pub fn remove(&self, key: &K) -> DbResult<Option<V>> {
         let key_encoded = bincode::serialize(key)?;
-        let old_value = self.engine.remove(&key_encoded)?;
+        let old_value = self.write(&key_encoded, None)?;
         Ok(self.deserialize_old_value(old_value))
     }
 
+    /// Declares a secondary index `name` on the [Bucket]. `extractor` returns the indexed field of a value.
+    /// The index is shared by all buckets with the same id opened from the same [Db].
+    /// Extractors aren't persisted, so indexes should be declared each time the db is opened, before the bucket is written to.
+    ///
+    /// The index is rebuilt from the existing records. A previously declared index with the same name is replaced.
+    pub fn add_index<I, F>(&self, name: &str, extractor: F) -> DbResult<()>
+    where
+        I: Serialize,
+        F: Fn(&V) -> I + Send + Sync + 'static,
+    {
+        let def = Arc::new(IndexDef::new::<V, C, I>(&self.id, name, extractor));
+        let mut indexes = self.indexes.write().unwrap();
+        def.rebuild(self.db_engine.as_ref(), self.engine.as_ref())?;
+
+        let bucket_indexes = indexes.entry(self.id.clone()).or_default();
+        bucket_indexes.retain(|declared| declared.name != name);
+        bucket_indexes.push(def);
+        Ok(())
+    }
+
+    /// Returns the secondary index `name` declared by [Bucket::add_index].
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use mantle_utilities::db::{Bucket, Db};
+    /// # use mantle_utilities::db::memory_db::MemoryDb;
+    /// # let db = Db::new(Box::new(MemoryDb::new()));
+    /// # let bucket: Bucket<String, (String, u32)> = db.open_bucket("devices")?;
+    /// bucket.add_index("by_model", |(model, _): &(String, u32)| model.clone())?;
+    /// bucket.insert(&"DSN".to_string(), &("RV2000".to_string(), 1))?;
+    ///
+    /// let devices = bucket.index("by_model").get(&"RV2000".to_string())?;
+    /// assert_eq!(devices, vec![("RV2000".to_string(), 1)]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn index<I>(&self, name: &str) -> Index<K, V, I, C>
+    where
+        I: Serialize,
+    {
+        let def = self
+            .bucket_indexes()
+            .into_iter()
+            .find(|def| def.name == name);
+        let bucket = Bucket {
+            id: self.id.clone(),
+            engine: Arc::clone(&self.engine),
+            db_engine: Arc::clone(&self.db_engine),
+            indexes: Arc::clone(&self.indexes),
+            _marker: Default::default(),
+        };
+        Index::new(name, def, bucket)
+    }
+
     /// Returns an iterator over all key-value pairs in the [Bucket].
-    /// Order is arbitrary.
-    pub fn iter(&self) -> DbResult<Iter<K, V>> {
+    /// Pairs are ordered by the bytes of their encoded keys, which for most key types isn't the order of the keys.
+    pub fn iter(&self) -> DbResult<Iter<K, V, C>> {
         let engine_iter = self.engine.iter()?;
         Ok(Iter {
             engine_iter,
@@ -186,8 +538,7 @@ where
         })
     }
 
-    /// Returns an iterator over all keys in the [Bucket].
-    /// Order is arbitrary.
+    /// Returns an iterator over all keys in the [Bucket], ordered like [Bucket::iter].
     pub fn keys(&self) -> DbResult<KeysIter<K>> {
         let engine_iter = self.engine.keys()?;
         Ok(KeysIter {
@@ -196,9 +547,8 @@ where
         })
     }
 
-    /// Returns an iterator over all values in the [Bucket].
-    /// Order is arbitrary.
-    pub fn values(&self) -> DbResult<ValuesIter<V>> {
+    /// Returns an iterator over all values in the [Bucket], ordered by their keys like [Bucket::iter].
+    pub fn values(&self) -> DbResult<ValuesIter<V, C>> {
         let engine_iter = self.engine.values()?;
         Ok(ValuesIter {
             engine_iter,
@@ -206,21 +556,57 @@ where
         })
     }
 
-    /// Removes all values from the [Bucket].
+    /// Removes all values from the [Bucket] and its indexes.
     pub fn clear(&self) -> DbResult<()> {
+        for def in self.bucket_indexes() {
+            self.db_engine.open_bucket(&def.bucket_id)?.clear()?;
+        }
         self.engine.clear().map_err(DbError::from)
     }
 
-    /// Creates a new bucket with the provided implementation.
-    fn new(engine: Box<dyn BucketEngine>) -> Self {
-        Bucket {
-            engine: engine.into(),
-            _marker: Default::default(),
+    fn bucket_indexes(&self) -> Vec<Arc<IndexDef>> {
+        let indexes = self.indexes.read().unwrap();
+        indexes.get(&self.id).cloned().unwrap_or_default()
+    }
+
+    /// Inserts the `value` at the `key` or removes the key if the `value` is None, returning the old value.
+    /// Updates the indexes in the same transaction.
+    ///
+    /// The index registry stays read-locked until the write is done, so [Bucket::add_index] can't rebuild an index in between.
+    fn write(&self, key: &[u8], value: Option<&[u8]>) -> DbResult<Option<Bytes>> {
+        let registry = self.indexes.read().unwrap();
+        let indexes = registry
+            .get(&self.id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        if indexes.is_empty() {
+            return match value {
+                Some(value) => self.engine.insert(key, value),
+                None => self.engine.remove(key),
+            };
         }
+
+        let mut ids = vec![self.id.as_str()];
+        ids.extend(indexes.iter().map(|def| def.bucket_id.as_str()));
+        let old_value = RefCell::new(None);
+        self.db_engine.transaction(&ids, &|buckets| {
+            let old = match value {
+                Some(value) => buckets[0].insert(key, value)?,
+                None => buckets[0].remove(key)?,
+            }
+            .map(|old| old.as_ref().as_ref().to_vec());
+            for (def, index) in indexes.iter().zip(&buckets[1..]) {
+                def.update(*index, key, old.as_deref(), value)?;
+            }
+            *old_value.borrow_mut() = old;
+            Ok(())
+        })?;
+
+        Ok(old_value.into_inner().map(|old| Box::new(old) as Bytes))
     }
 
     fn deserialize_old_value(&self, old_value: Option<Bytes>) -> Option<V> {
-        match old_value.map(|old| bincode::deserialize(old.as_ref().as_ref())) {
+        match old_value.map(|old| C::decode(old.as_ref().as_ref())) {
             Some(Ok(value)) => Some(value),
             Some(Err(err)) => {
                 warn!("failed to deserialize the old value: {err}");
@@ -231,10 +617,25 @@ where
     }
 }
 
-impl<K, V> Iterator for Iter<K, V>
+impl BucketTransaction for Box<dyn BucketEngine> {
+    fn get(&self, key: &[u8]) -> DbResult<Option<Bytes>> {
+        self.as_ref().get(key)
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> DbResult<Option<Bytes>> {
+        self.as_ref().insert(key, value)
+    }
+
+    fn remove(&self, key: &[u8]) -> DbResult<Option<Bytes>> {
+        self.as_ref().remove(key)
+    }
+}
+
+impl<K, V, C> Iterator for Iter<K, V, C>
 where
     K: DeserializeOwned,
     V: DeserializeOwned,
+    C: ValueCodec,
 {
     type Item = DbResult<(K, V)>;
 
@@ -242,7 +643,7 @@ where
         self.engine_iter.next().map(|result| {
             let (enc_key, enc_value) = result?;
             let key = bincode::deserialize(enc_key.as_ref().as_ref())?;
-            let value = bincode::deserialize(enc_value.as_ref().as_ref())?;
+            let value = C::decode(enc_value.as_ref().as_ref())?;
             Ok((key, value))
         })
     }
@@ -263,17 +664,42 @@ where
     }
 }
 
-impl<V> Iterator for ValuesIter<V>
+impl<V, C> Iterator for ValuesIter<V, C>
 where
     V: DeserializeOwned,
+    C: ValueCodec,
 {
     type Item = DbResult<V>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.engine_iter.next().map(|result| {
-            let enc_key = result?;
-            let key = bincode::deserialize(enc_key.as_ref().as_ref())?;
-            Ok(key)
+            let enc_value = result?;
+            let value = C::decode(enc_value.as_ref().as_ref())?;
+            Ok(value)
         })
     }
 }
+
+impl ValueCodec for Bincode {
+    fn encode<V: Serialize>(value: &V) -> DbResult<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<V: DeserializeOwned>(bytes: &[u8]) -> DbResult<V> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+impl ValueCodec for Json {
+    fn encode<V: Serialize>(value: &V) -> DbResult<Vec<u8>> {
+        serde_json::to_vec(value).map_err(json_error)
+    }
+
+    fn decode<V: DeserializeOwned>(bytes: &[u8]) -> DbResult<V> {
+        serde_json::from_slice(bytes).map_err(json_error)
+    }
+}
+
+fn json_error(err: serde_json::Error) -> DbError {
+    DbError::SerializationError(bincode::ErrorKind::Custom(err.to_string()).into())
+}
@@ -0,0 +1,174 @@
+use crate::db::index::is_index_bucket;
+use crate::db::{DbEngine, DbError, DbResult};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+const ARCHIVE_MAGIC: &[u8; 4] = b"MTDB";
+const ARCHIVE_VERSION: u32 = 1;
+const CHECKSUM_LEN: usize = 4;
+
+/// Defines how an archive is applied to a [Db](crate::db::Db) on import.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImportMode {
+    /// Archived pairs are added to the existing data. Keys that already exist are overwritten.
+    Merge,
+    /// All existing buckets are deleted before the archive is imported.
+    Replace,
+}
+
+/// Archive layout:
+/// magic bytes, version (u32 LE), bincode encoded records, CRC-32 of the records (u32 LE).
+#[derive(Debug, Serialize, Deserialize)]
+enum Record {
+    /// Starts a bucket. All following pairs belong to it.
+    Bucket(String),
+    Pair(Vec<u8>, Vec<u8>),
+    End,
+}
+
+/// Writes all buckets of the `engine` to the `writer`. Index buckets are skipped, they are rebuilt on import.
+pub(crate) fn export(engine: &dyn DbEngine, writer: impl Write) -> DbResult<()> {
+    let mut writer = ChecksumWriter::new(writer);
+    writer.inner.write_all(ARCHIVE_MAGIC)?;
+    writer.inner.write_all(&ARCHIVE_VERSION.to_le_bytes())?;
+
+    for id in engine.bucket_ids()? {
+        if is_index_bucket(&id) {
+            continue;
+        }
+        let bucket = engine.open_bucket(&id)?;
+        bincode::serialize_into(&mut writer, &Record::Bucket(id))?;
+        for pair in bucket.iter()? {
+            let (key, value) = pair?;
+            let record = Record::Pair(
+                key.as_ref().as_ref().to_vec(),
+                value.as_ref().as_ref().to_vec(),
+            );
+            bincode::serialize_into(&mut writer, &record)?;
+        }
+    }
+    bincode::serialize_into(&mut writer, &Record::End)?;
+
+    let checksum = writer.checksum();
+    writer.inner.write_all(&checksum.to_le_bytes())?;
+    writer.inner.flush()?;
+    Ok(())
+}
+
+/// Reads an archive from the `reader` and writes it to the `engine`.
+/// The whole archive is validated before any data is changed.
+pub(crate) fn import(
+    engine: &dyn DbEngine,
+    mut reader: impl Read,
+    mode: ImportMode,
+) -> DbResult<()> {
+    let mut header = [0; 8];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != ARCHIVE_MAGIC {
+        return Err(DbError::ArchiveError("not a db archive".to_string()));
+    }
+    let version = u32::from_le_bytes(header[4..].try_into().unwrap());
+    if version != ARCHIVE_VERSION {
+        return Err(DbError::ArchiveError(format!(
+            "unsupported archive version: {version}"
+        )));
+    }
+
+    let mut body = Vec::new();
+    reader.read_to_end(&mut body)?;
+    if body.len() < CHECKSUM_LEN {
+        return Err(DbError::ArchiveError("archive is truncated".to_string()));
+    }
+    let (records, checksum) = body.split_at(body.len() - CHECKSUM_LEN);
+    if crc32(0, records) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+        return Err(DbError::ArchiveError("checksum mismatch".to_string()));
+    }
+
+    let buckets = parse_records(records)?;
+
+    if mode == ImportMode::Replace {
+        for id in engine.bucket_ids()? {
+            engine.delete_bucket(&id)?;
+        }
+    }
+    for (id, pairs) in buckets {
+        let bucket = engine.open_bucket(&id)?;
+        for (key, value) in pairs {
+            bucket.insert(&key, &value)?;
+        }
+    }
+    Ok(())
+}
+
+type ArchivedBucket = (String, Vec<(Vec<u8>, Vec<u8>)>);
+
+fn parse_records(mut records: &[u8]) -> DbResult<Vec<ArchivedBucket>> {
+    let mut buckets: Vec<ArchivedBucket> = Vec::new();
+    loop {
+        match bincode::deserialize_from(&mut records)? {
+            Record::Bucket(id) => buckets.push((id, Vec::new())),
+            Record::Pair(key, value) => match buckets.last_mut() {
+                Some((_, pairs)) => pairs.push((key, value)),
+                None => {
+                    return Err(DbError::ArchiveError(
+                        "pair doesn't belong to any bucket".to_string(),
+                    ))
+                }
+            },
+            Record::End if records.is_empty() => return Ok(buckets),
+            Record::End => {
+                return Err(DbError::ArchiveError(
+                    "unexpected data after the end".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+/// Calculates the checksum of everything written through it.
+struct ChecksumWriter<W> {
+    inner: W,
+    checksum: u32,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> Self {
+        ChecksumWriter { inner, checksum: 0 }
+    }
+
+    fn checksum(&self) -> u32 {
+        self.checksum
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.checksum = crc32(self.checksum, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// CRC-32 (IEEE). `crc` is the checksum of the preceding data, 0 for the first chunk.
+fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[test]
+fn crc32_matches_reference_value() {
+    let (first, second) = b"123456789".split_at(4);
+    assert_eq!(0xCBF4_3926, crc32(0, b"123456789"));
+    assert_eq!(0xCBF4_3926, crc32(crc32(0, first), second));
+}
@@ -0,0 +1,204 @@
+use crate::db::{
+    Bincode, Bucket, BucketEngine, BucketTransaction, DbEngine, DbError, DbResult, ValueCodec,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::sync::{Arc, RwLock};
+
+const INDEX_BUCKET_PREFIX: &str = "__index__";
+
+/// Indexes declared on the buckets of a [Db](crate::db::Db), by bucket id.
+pub(crate) type IndexRegistry = Arc<RwLock<HashMap<String, Vec<Arc<IndexDef>>>>>;
+
+type Extractor = Box<dyn Fn(&[u8]) -> DbResult<Vec<u8>> + Send + Sync>;
+
+/// A secondary index working on encoded values, so it can be shared by all typed buckets with the same id.
+pub(crate) struct IndexDef {
+    pub name: String,
+    /// Id of the bucket that stores the index.
+    pub bucket_id: String,
+    /// Returns the encoded index value for an encoded bucket value.
+    extract: Extractor,
+}
+
+/// A secondary index of a [Bucket].
+/// Maps index values to the keys of the records that have them.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use mantle_utilities::db::{Bucket, Db};
+/// use mantle_utilities::db::memory_db::MemoryDb;
+///
+/// let db = Db::new(Box::new(MemoryDb::new()));
+/// // Devices by DSN. The value is a model.
+/// let bucket: Bucket<String, String> = db.open_bucket("devices")?;
+/// bucket.add_index("by_model", |model: &String| model.clone())?;
+/// bucket.insert(&"DSN1".to_string(), &"RV2000".to_string())?;
+/// bucket.insert(&"DSN2".to_string(), &"RV1000".to_string())?;
+///
+/// let keys = bucket.index("by_model").keys(&"RV2000".to_string())?;
+/// assert_eq!(keys, vec!["DSN1".to_string()]);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Index<K, V, I, C = Bincode> {
+    name: String,
+    def: Option<Arc<IndexDef>>,
+    bucket: Bucket<K, V, C>,
+    _marker: PhantomData<I>,
+}
+
+impl IndexDef {
+    pub fn new<V, C, I>(
+        bucket_id: &str,
+        name: &str,
+        extractor: impl Fn(&V) -> I + Send + Sync + 'static,
+    ) -> Self
+    where
+        V: DeserializeOwned,
+        C: ValueCodec,
+        I: Serialize,
+    {
+        IndexDef {
+            name: name.to_string(),
+            bucket_id: index_bucket_id(bucket_id, name),
+            extract: Box::new(move |value| {
+                let value = C::decode(value)?;
+                Ok(bincode::serialize(&extractor(&value))?)
+            }),
+        }
+    }
+
+    /// Replaces the index entries with ones built from all records of the `bucket`.
+    pub fn rebuild(&self, engine: &dyn DbEngine, bucket: &dyn BucketEngine) -> DbResult<()> {
+        let index = engine.open_bucket(&self.bucket_id)?;
+        index.clear()?;
+        for pair in bucket.iter()? {
+            let (key, value) = pair?;
+            self.update(
+                &index,
+                key.as_ref().as_ref(),
+                None,
+                Some(value.as_ref().as_ref()),
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn extract(&self, value: &[u8]) -> DbResult<Vec<u8>> {
+        (self.extract)(value)
+    }
+
+    /// Updates the index entries of `key` after its value changed from `old_value` to `new_value`.
+    pub fn update(
+        &self,
+        index: &dyn BucketTransaction,
+        key: &[u8],
+        old_value: Option<&[u8]>,
+        new_value: Option<&[u8]>,
+    ) -> DbResult<()> {
+        let old_index_value = old_value.map(|value| self.extract(value)).transpose()?;
+        let new_index_value = new_value.map(|value| self.extract(value)).transpose()?;
+        if old_index_value == new_index_value {
+            return Ok(());
+        }
+
+        if let Some(index_value) = old_index_value {
+            let mut keys = read_keys(index, &index_value)?;
+            keys.retain(|k| k != key);
+            if keys.is_empty() {
+                index.remove(&index_value)?;
+            } else {
+                index.insert(&index_value, &bincode::serialize(&keys)?)?;
+            }
+        }
+        if let Some(index_value) = new_index_value {
+            let mut keys = read_keys(index, &index_value)?;
+            if let Err(position) = keys.binary_search_by(|k| k.as_slice().cmp(key)) {
+                keys.insert(position, key.to_vec());
+            }
+            index.insert(&index_value, &bincode::serialize(&keys)?)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for IndexDef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IndexDef")
+            .field("name", &self.name)
+            .field("bucket_id", &self.bucket_id)
+            .finish()
+    }
+}
+
+impl<K, V, I, C> Index<K, V, I, C>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    I: Serialize,
+    C: ValueCodec,
+{
+    pub(crate) fn new(name: &str, def: Option<Arc<IndexDef>>, bucket: Bucket<K, V, C>) -> Self {
+        Index {
+            name: name.to_string(),
+            def,
+            bucket,
+            _marker: Default::default(),
+        }
+    }
+
+    /// Returns keys of the records with the `index_value`, ordered by their encoded bytes.
+    /// Fails with [DbError::IndexNotFound] if the index wasn't declared by [Bucket::add_index].
+    pub fn keys(&self, index_value: &I) -> DbResult<Vec<K>> {
+        let Some(def) = &self.def else {
+            return Err(DbError::IndexNotFound(self.name.clone()));
+        };
+        let index = self.bucket.db_engine.open_bucket(&def.bucket_id)?;
+        let keys = match index.get(&bincode::serialize(index_value)?)? {
+            Some(keys) => bincode::deserialize::<Vec<Vec<u8>>>(keys.as_ref().as_ref())?,
+            None => return Ok(Vec::new()),
+        };
+        keys.iter()
+            .map(|key| bincode::deserialize(key).map_err(DbError::from))
+            .collect()
+    }
+
+    /// Returns values of the records with the `index_value`, ordered by their encoded keys.
+    /// Fails with [DbError::IndexNotFound] if the index wasn't declared by [Bucket::add_index].
+    pub fn get(&self, index_value: &I) -> DbResult<Vec<V>> {
+        let mut values = Vec::new();
+        for key in self.keys(index_value)? {
+            if let Some(value) = self.bucket.get(&key)? {
+                values.push(value);
+            }
+        }
+        Ok(values)
+    }
+}
+
+/// Index buckets are internal: they can't be opened as [Bucket]s and aren't exported.
+pub(crate) fn is_index_bucket(id: &str) -> bool {
+    id.starts_with(INDEX_BUCKET_PREFIX)
+}
+
+/// The length of the bucket id ends it unambiguously, so e.g. bucket `a__b` with index `c`
+/// and bucket `a` with index `b__c` don't share an index bucket.
+pub(crate) fn index_bucket_id(bucket_id: &str, name: &str) -> String {
+    format!(
+        "{INDEX_BUCKET_PREFIX}{}:{bucket_id}:{name}",
+        bucket_id.len()
+    )
+}
+
+fn read_keys(index: &dyn BucketTransaction, index_value: &[u8]) -> DbResult<Vec<Vec<u8>>> {
+    match index.get(index_value)? {
+        Some(keys) => Ok(bincode::deserialize(keys.as_ref().as_ref())?),
+        None => Ok(Vec::new()),
+    }
+}
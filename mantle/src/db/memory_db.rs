@@ -0,0 +1,207 @@
+use crate::db::{BucketEngine, BucketTransaction, Bytes, DbEngine, DbResult, Pair};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+type Tree = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// A key-value db that keeps all data in memory.
+/// Useful for tests and ephemeral state that shouldn't touch the disk. The data is lost when the last clone is dropped.
+/// Cloned db will point to the same data.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use mantle_utilities::db::{Bucket, Db};
+/// use mantle_utilities::db::memory_db::MemoryDb;
+///
+/// let db = Db::new(Box::new(MemoryDb::new()));
+/// let bucket: Bucket<String, i32> = db.open_bucket("bucket_id")?;
+/// let key = "MyKey".to_string();
+/// bucket.insert(&key, &42)?;
+/// assert_eq!(Some(42), bucket.get(&key)?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemoryDb {
+    trees: Arc<RwLock<BTreeMap<String, Arc<RwLock<Tree>>>>>,
+}
+
+/// [BucketEngine] implementation that keeps data in an ordered map.
+#[derive(Debug, Clone)]
+pub struct MemoryBucketEngine {
+    tree: Arc<RwLock<Tree>>,
+}
+
+/// [BucketTransaction] implementation that stages writes until the transaction succeeds.
+struct MemoryBucketTransaction<'a> {
+    tree: &'a Tree,
+    /// Staged writes. None removes the key.
+    writes: RefCell<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+}
+
+impl MemoryDb {
+    /// Creates a new empty db.
+    pub fn new() -> Self {
+        MemoryDb::default()
+    }
+}
+
+impl DbEngine for MemoryDb {
+    fn open_bucket(&self, id: &str) -> DbResult<Box<dyn BucketEngine>> {
+        let mut trees = self.trees.write().unwrap();
+        let tree = trees.entry(id.to_string()).or_default();
+        Ok(Box::new(MemoryBucketEngine {
+            tree: Arc::clone(tree),
+        }))
+    }
+
+    fn delete_bucket(&self, id: &str) -> DbResult<bool> {
+        let removed = self.trees.write().unwrap().remove(id);
+        // Buckets opened before the deletion shouldn't keep the data alive.
+        if let Some(tree) = &removed {
+            tree.write().unwrap().clear();
+        }
+        Ok(removed.is_some())
+    }
+
+    fn bucket_ids(&self) -> DbResult<Vec<String>> {
+        Ok(self.trees.read().unwrap().keys().cloned().collect())
+    }
+
+    fn transaction(
+        &self,
+        ids: &[&str],
+        f: &dyn Fn(&[&dyn BucketTransaction]) -> DbResult<()>,
+    ) -> DbResult<()> {
+        let trees = {
+            let mut trees = self.trees.write().unwrap();
+            ids.iter()
+                .map(|id| Arc::clone(trees.entry(id.to_string()).or_default()))
+                .collect::<Vec<_>>()
+        };
+        // Lock the trees in the same order in every transaction to avoid deadlocks.
+        let mut lock_order: Vec<usize> = (0..ids.len()).collect();
+        lock_order.sort_by_key(|&idx| ids[idx]);
+        let mut guards: Vec<Option<RwLockWriteGuard<Tree>>> = ids.iter().map(|_| None).collect();
+        for idx in lock_order {
+            guards[idx] = Some(trees[idx].write().unwrap());
+        }
+        let mut guards: Vec<RwLockWriteGuard<Tree>> = guards.into_iter().flatten().collect();
+
+        let writes = {
+            let buckets: Vec<MemoryBucketTransaction> = guards
+                .iter()
+                .map(|guard| MemoryBucketTransaction {
+                    tree: guard,
+                    writes: Default::default(),
+                })
+                .collect();
+            let bucket_refs: Vec<&dyn BucketTransaction> = buckets
+                .iter()
+                .map(|bucket| bucket as &dyn BucketTransaction)
+                .collect();
+            f(&bucket_refs)?;
+            buckets
+                .into_iter()
+                .map(|bucket| bucket.writes.into_inner())
+                .collect::<Vec<_>>()
+        };
+
+        for (guard, writes) in guards.iter_mut().zip(writes) {
+            for (key, value) in writes {
+                match value {
+                    Some(value) => guard.insert(key, value),
+                    None => guard.remove(&key),
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MemoryBucketEngine {
+    fn read(&self) -> RwLockReadGuard<'_, Tree> {
+        self.tree.read().unwrap()
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Tree> {
+        self.tree.write().unwrap()
+    }
+}
+
+impl BucketTransaction for MemoryBucketTransaction<'_> {
+    fn get(&self, key: &[u8]) -> DbResult<Option<Bytes>> {
+        let bytes = match self.writes.borrow().get(key) {
+            Some(staged) => staged.clone(),
+            None => self.tree.get(key).cloned(),
+        };
+        Ok(bytes.map(|bytes| Box::new(bytes) as Bytes))
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> DbResult<Option<Bytes>> {
+        let old_value = self.get(key)?;
+        self.writes
+            .borrow_mut()
+            .insert(key.to_vec(), Some(value.to_vec()));
+        Ok(old_value)
+    }
+
+    fn remove(&self, key: &[u8]) -> DbResult<Option<Bytes>> {
+        let old_value = self.get(key)?;
+        self.writes.borrow_mut().insert(key.to_vec(), None);
+        Ok(old_value)
+    }
+}
+
+impl BucketEngine for MemoryBucketEngine {
+    fn get(&self, key: &[u8]) -> DbResult<Option<Bytes>> {
+        let bytes = self.read().get(key).cloned();
+        Ok(bytes.map(|bytes| Box::new(bytes) as Bytes))
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> DbResult<Option<Bytes>> {
+        let bytes = self.write().insert(key.to_vec(), value.to_vec());
+        Ok(bytes.map(|bytes| Box::new(bytes) as Bytes))
+    }
+
+    fn remove(&self, key: &[u8]) -> DbResult<Option<Bytes>> {
+        let bytes = self.write().remove(key);
+        Ok(bytes.map(|bytes| Box::new(bytes) as Bytes))
+    }
+
+    // Iterators work over a snapshot of the bucket, so they don't hold the lock.
+    fn iter(&self) -> DbResult<Box<dyn Iterator<Item = DbResult<Pair>>>> {
+        let pairs: Vec<_> = self
+            .read()
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let iter = pairs
+            .into_iter()
+            .map(|(key, value)| Ok((Box::new(key) as Bytes, Box::new(value) as Bytes)));
+
+        Ok(Box::new(iter))
+    }
+
+    fn keys(&self) -> DbResult<Box<dyn Iterator<Item = DbResult<Bytes>>>> {
+        let keys: Vec<_> = self.read().keys().cloned().collect();
+        let iter = keys.into_iter().map(|bytes| Ok(Box::new(bytes) as Bytes));
+
+        Ok(Box::new(iter))
+    }
+
+    fn values(&self) -> DbResult<Box<dyn Iterator<Item = DbResult<Bytes>>>> {
+        let values: Vec<_> = self.read().values().cloned().collect();
+        let iter = values.into_iter().map(|bytes| Ok(Box::new(bytes) as Bytes));
+
+        Ok(Box::new(iter))
+    }
+
+    fn clear(&self) -> DbResult<()> {
+        self.write().clear();
+        Ok(())
+    }
+}
@@ -1,6 +1,16 @@
-use crate::db::{BucketEngine, Bytes, DbEngine, DbError, DbResult, Pair};
-use sled::Tree;
-use std::path::Path;
+use crate::db::{BucketEngine, BucketTransaction, Bytes, DbEngine, DbError, DbResult, Pair};
+use sled::transaction::{
+    ConflictableTransactionError, TransactionError, Transactional, TransactionalTree,
+    UnabortableTransactionError,
+};
+use sled::{Mode, Tree};
+use std::cell::Cell;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const COMPACTING_SUFFIX: &str = ".compacting";
+const COMPACTED_SUFFIX: &str = ".old";
 
 /// A key-value db using sled as its storage engine.
 #[derive(Debug, Clone)]
@@ -8,6 +18,20 @@ pub struct SledDb {
     db: sled::Db,
 }
 
+/// Tuning options for [SledDb::open_with_config].
+/// Unset options keep sled defaults.
+#[derive(Debug, Clone, Default)]
+pub struct SledDbConfig {
+    /// Maximum size of the page cache in bytes. Sled defaults to 1 GB, which is too much for low-end phones.
+    pub cache_capacity: Option<u64>,
+    /// How often buffered writes are flushed to the disk in ms. Sled defaults to 500 ms.
+    pub flush_every_ms: Option<u64>,
+    /// Favors less disk space over write throughput. Sled rewrites data more often to reduce fragmentation.
+    pub low_space: bool,
+    /// The db is compacted on open if its directory takes more bytes than this. See [SledDb::compact].
+    pub size_limit: Option<u64>,
+}
+
 /// [BucketEngine] implementation using sled.
 /// The data is flushed on drop.
 #[derive(Debug, Clone)]
@@ -15,6 +39,13 @@ pub struct SledBucketEngine {
     tree: Tree,
 }
 
+/// [BucketTransaction] implementation using a sled transactional tree.
+struct SledBucketTransaction<'a> {
+    tree: &'a TransactionalTree,
+    /// Set when sled reports a conflict, so the transaction is retried instead of aborted.
+    conflict: &'a Cell<bool>,
+}
+
 impl SledDb {
     /// Opens a Db at the specified path. This will create a new storage directory at the specified path if it does not already exist.
     /// The same directory shouldn't be used for multiple DBs at the same time. If you want multiple instances of the same DB, you should clone it.
@@ -23,6 +54,74 @@ impl SledDb {
 
         Ok(SledDb { db })
     }
+
+    /// Opens a Db at the specified path like [SledDb::open], using the `config`.
+    /// If the directory exceeds [SledDbConfig::size_limit], it is compacted first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let _ = std::fs::remove_dir_all("sled_config_db");
+    /// use mantle_utilities::db::sled_db::{SledDb, SledDbConfig};
+    ///
+    /// let config = SledDbConfig {
+    ///     cache_capacity: Some(8 * 1024 * 1024),
+    ///     low_space: true,
+    ///     size_limit: Some(64 * 1024 * 1024),
+    ///     ..Default::default()
+    /// };
+    /// let db = SledDb::open_with_config("sled_config_db", &config)?;
+    /// # drop(db);
+    /// # let _ = std::fs::remove_dir_all("sled_config_db");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn open_with_config(path: impl AsRef<Path>, config: &SledDbConfig) -> DbResult<Self> {
+        let path = path.as_ref();
+        recover_compaction(path)?;
+        if let Some(size_limit) = config.size_limit {
+            if dir_size(path)? > size_limit {
+                SledDb::compact(path, config)?;
+            }
+        }
+        let db = sled_config(path, config).open()?;
+
+        Ok(SledDb { db })
+    }
+
+    /// Rewrites the db at `path` into a new directory, so space taken by old versions of the data is reclaimed.
+    /// The db mustn't be open while it's compacted. If the process dies during compaction,
+    /// the next [SledDb::open_with_config] recovers the original data.
+    pub fn compact(path: impl AsRef<Path>, config: &SledDbConfig) -> DbResult<()> {
+        let path = path.as_ref();
+        recover_compaction(path)?;
+        let compacting_path = with_suffix(path, COMPACTING_SUFFIX);
+        if compacting_path.exists() {
+            fs::remove_dir_all(&compacting_path)?;
+        }
+
+        {
+            let db = sled_config(path, config).open()?;
+            let compacted = sled_config(&compacting_path, config).open()?;
+            for name in db.tree_names() {
+                let tree = db.open_tree(&name)?;
+                let compacted_tree = compacted.open_tree(&name)?;
+                for pair in tree.iter() {
+                    let (key, value) = pair?;
+                    compacted_tree.insert(key, value)?;
+                }
+            }
+            compacted.flush()?;
+        }
+
+        // The original directory is kept until the compacted one is in place.
+        let compacted_path = with_suffix(path, COMPACTED_SUFFIX);
+        fs::rename(path, &compacted_path)?;
+        fs::rename(&compacting_path, path)?;
+        fs::remove_dir_all(&compacted_path)?;
+        Ok(())
+    }
 }
 
 impl DbEngine for SledDb {
@@ -34,6 +133,68 @@ impl DbEngine for SledDb {
     fn delete_bucket(&self, id: &str) -> DbResult<bool> {
         self.db.drop_tree(id.as_bytes()).map_err(DbError::from)
     }
+
+    fn bucket_ids(&self) -> DbResult<Vec<String>> {
+        // The default tree isn't a bucket.
+        let default_tree = self.db.name();
+        self.db
+            .tree_names()
+            .into_iter()
+            .filter(|name| name != &default_tree)
+            .map(|name| {
+                String::from_utf8(name.to_vec())
+                    .map_err(|err| DbError::DbEngineError(Box::new(err)))
+            })
+            .collect()
+    }
+
+    fn transaction(
+        &self,
+        ids: &[&str],
+        f: &dyn Fn(&[&dyn BucketTransaction]) -> DbResult<()>,
+    ) -> DbResult<()> {
+        let trees = ids
+            .iter()
+            .map(|id| self.db.open_tree(id.as_bytes()))
+            .collect::<Result<Vec<Tree>, _>>()?;
+
+        let result = trees[..].transaction(|tx_trees| {
+            let conflict = Cell::new(false);
+            let buckets: Vec<SledBucketTransaction> = tx_trees
+                .iter()
+                .map(|tree| SledBucketTransaction {
+                    tree,
+                    conflict: &conflict,
+                })
+                .collect();
+            let buckets: Vec<&dyn BucketTransaction> = buckets
+                .iter()
+                .map(|bucket| bucket as &dyn BucketTransaction)
+                .collect();
+            f(&buckets).map_err(|err| {
+                if conflict.get() {
+                    ConflictableTransactionError::Conflict
+                } else {
+                    ConflictableTransactionError::Abort(err)
+                }
+            })
+        });
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(TransactionError::Abort(err)) => Err(err),
+            Err(TransactionError::Storage(err)) => Err(DbError::from(err)),
+        }
+    }
+
+    fn size_on_disk(&self) -> DbResult<Option<u64>> {
+        Ok(Some(self.db.size_on_disk()?))
+    }
+
+    fn flush(&self) -> DbResult<()> {
+        self.db.flush()?;
+        Ok(())
+    }
 }
 
 impl BucketEngine for SledBucketEngine {
@@ -89,6 +250,87 @@ impl BucketEngine for SledBucketEngine {
     }
 }
 
+impl SledBucketTransaction<'_> {
+    fn map_result(
+        &self,
+        result: Result<Option<sled::IVec>, UnabortableTransactionError>,
+    ) -> DbResult<Option<Bytes>> {
+        match result {
+            Ok(bytes) => Ok(bytes.map(|bytes| Box::new(bytes) as Bytes)),
+            Err(UnabortableTransactionError::Conflict) => {
+                self.conflict.set(true);
+                Err(DbError::DbEngineError("transaction conflict".into()))
+            }
+            Err(UnabortableTransactionError::Storage(err)) => Err(DbError::from(err)),
+        }
+    }
+}
+
+impl BucketTransaction for SledBucketTransaction<'_> {
+    fn get(&self, key: &[u8]) -> DbResult<Option<Bytes>> {
+        self.map_result(self.tree.get(key))
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> DbResult<Option<Bytes>> {
+        self.map_result(self.tree.insert(key, value))
+    }
+
+    fn remove(&self, key: &[u8]) -> DbResult<Option<Bytes>> {
+        self.map_result(self.tree.remove(key))
+    }
+}
+
+fn sled_config(path: &Path, config: &SledDbConfig) -> sled::Config {
+    let mut sled_config = sled::Config::new().path(path);
+    if let Some(cache_capacity) = config.cache_capacity {
+        sled_config = sled_config.cache_capacity(cache_capacity);
+    }
+    if let Some(flush_every_ms) = config.flush_every_ms {
+        sled_config = sled_config.flush_every_ms(Some(flush_every_ms));
+    }
+    if config.low_space {
+        sled_config = sled_config.mode(Mode::LowSpace);
+    }
+    sled_config
+}
+
+/// Finishes or rolls back a compaction that was interrupted.
+fn recover_compaction(path: &Path) -> DbResult<()> {
+    let compacted_path = with_suffix(path, COMPACTED_SUFFIX);
+    if compacted_path.exists() {
+        if path.exists() {
+            // The compacted directory was already moved in place.
+            fs::remove_dir_all(&compacted_path)?;
+        } else {
+            fs::rename(&compacted_path, path)?;
+        }
+    }
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn dir_size(path: &Path) -> DbResult<u64> {
+    if !path.exists() {
+        return Ok(0);
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
 impl From<sled::Error> for DbError {
     fn from(err: sled::Error) -> Self {
         DbError::DbEngineError(Box::new(err))
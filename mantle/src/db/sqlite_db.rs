@@ -0,0 +1,317 @@
+use crate::db::sled_db::SledDb;
+use crate::db::{BucketEngine, BucketTransaction, Bytes, DbEngine, DbError, DbResult, Pair};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs::create_dir_all;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+const BUCKET_TABLE_PREFIX: &str = "bucket_";
+
+/// A key-value db using SQLite as its storage engine.
+/// Each bucket is stored in its own table. The database runs in WAL mode.
+/// Cloned db will point to the same connection.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let _ = std::fs::remove_dir_all("sqlite_db");
+/// use mantle_utilities::db::{Bucket, Db};
+/// use mantle_utilities::db::sqlite_db::SqliteDb;
+///
+/// let db = Db::new(Box::new(SqliteDb::open("sqlite_db/db.sqlite")?));
+/// let bucket: Bucket<String, i32> = db.open_bucket("bucket_id")?;
+/// let key = "MyKey".to_string();
+/// bucket.insert(&key, &42)?;
+/// assert_eq!(Some(42), bucket.get(&key)?);
+///
+/// # let _ = std::fs::remove_dir_all("sqlite_db");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SqliteDb {
+    conn: Arc<Mutex<Connection>>,
+}
+
+/// [BucketEngine] implementation using a single SQLite table.
+#[derive(Debug, Clone)]
+pub struct SqliteBucketEngine {
+    conn: Arc<Mutex<Connection>>,
+    /// Quoted table name.
+    table: String,
+}
+
+/// [BucketTransaction] implementation running inside a SQLite transaction.
+struct SqliteBucketTransaction<'a> {
+    conn: &'a Connection,
+    /// Quoted table name.
+    table: String,
+}
+
+impl SqliteDb {
+    /// Opens a Db in the database file at the specified path. This will create the file and its parent directories if they do not already exist.
+    /// The same file shouldn't be used for multiple DBs at the same time. If you want multiple instances of the same DB, you should clone it.
+    pub fn open(path: impl AsRef<Path>) -> DbResult<Self> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir).map_err(|err| DbError::DbEngineError(Box::new(err)))?;
+        }
+
+        let conn = Connection::open(path)?;
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+
+        Ok(SqliteDb {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Copies all buckets from the sled db at `sled_path` into this db.
+    /// Keys that already exist are overwritten. The copy is done in a single transaction,
+    /// so either every bucket is migrated or none of them is.
+    /// Returns the number of migrated buckets.
+    pub fn migrate_from_sled(&self, sled_path: impl AsRef<Path>) -> DbResult<usize> {
+        let sled = SledDb::open(sled_path)?;
+        let bucket_ids = sled.bucket_ids()?;
+
+        let mut conn = self.lock_conn();
+        let tx = conn.transaction()?;
+        for id in bucket_ids.iter() {
+            let table = quote(&table_name(id));
+            create_table(&tx, &table)?;
+            let bucket = sled.open_bucket(id)?;
+            for pair in bucket.iter()? {
+                let (key, value) = pair?;
+                tx.execute(
+                    &format!("INSERT OR REPLACE INTO {table} (key, value) VALUES (?1, ?2)"),
+                    params![key.as_ref().as_ref(), value.as_ref().as_ref()],
+                )?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(bucket_ids.len())
+    }
+
+    fn lock_conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap()
+    }
+}
+
+impl DbEngine for SqliteDb {
+    fn open_bucket(&self, id: &str) -> DbResult<Box<dyn BucketEngine>> {
+        let table = quote(&table_name(id));
+        create_table(&self.lock_conn(), &table)?;
+        Ok(Box::new(SqliteBucketEngine {
+            conn: Arc::clone(&self.conn),
+            table,
+        }))
+    }
+
+    fn delete_bucket(&self, id: &str) -> DbResult<bool> {
+        let table = table_name(id);
+        let conn = self.lock_conn();
+        let exists = conn
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                params![table],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        conn.execute(&format!("DROP TABLE IF EXISTS {}", quote(&table)), [])?;
+
+        Ok(exists)
+    }
+
+    fn bucket_ids(&self) -> DbResult<Vec<String>> {
+        let conn = self.lock_conn();
+        let mut statement =
+            conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")?;
+        let names = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        let ids = names
+            .into_iter()
+            .filter_map(|name| name.strip_prefix(BUCKET_TABLE_PREFIX).map(str::to_string))
+            .collect();
+        Ok(ids)
+    }
+
+    fn transaction(
+        &self,
+        ids: &[&str],
+        f: &dyn Fn(&[&dyn BucketTransaction]) -> DbResult<()>,
+    ) -> DbResult<()> {
+        let mut conn = self.lock_conn();
+        // The transaction is rolled back on drop if `f` fails.
+        let tx = conn.transaction()?;
+        let buckets = ids
+            .iter()
+            .map(|id| {
+                let table = quote(&table_name(id));
+                create_table(&tx, &table)?;
+                Ok(SqliteBucketTransaction { conn: &tx, table })
+            })
+            .collect::<DbResult<Vec<_>>>()?;
+        let bucket_refs: Vec<&dyn BucketTransaction> = buckets
+            .iter()
+            .map(|bucket| bucket as &dyn BucketTransaction)
+            .collect();
+        f(&bucket_refs)?;
+        drop(buckets);
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Doesn't include the WAL file, which is truncated by `flush`.
+    fn size_on_disk(&self) -> DbResult<Option<u64>> {
+        let conn = self.lock_conn();
+        let page_count: u64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+        let page_size: u64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        Ok(Some(page_count * page_size))
+    }
+
+    // Commits are already durable. Checkpointing moves them from the WAL file to the db file.
+    fn flush(&self) -> DbResult<()> {
+        self.lock_conn()
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        Ok(())
+    }
+}
+
+impl SqliteBucketEngine {
+    fn lock_conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap()
+    }
+
+    // Rusqlite statements borrow the connection, so rows are collected before the lock is released.
+    fn select_rows<T>(
+        &self,
+        columns: &str,
+        map: impl Fn(&rusqlite::Row) -> rusqlite::Result<T>,
+    ) -> DbResult<Vec<T>> {
+        let conn = self.lock_conn();
+        let mut statement = conn.prepare(&format!(
+            "SELECT {columns} FROM {} ORDER BY key",
+            self.table
+        ))?;
+        let rows = statement
+            .query_map([], map)?
+            .collect::<rusqlite::Result<Vec<T>>>()?;
+        Ok(rows)
+    }
+}
+
+impl BucketEngine for SqliteBucketEngine {
+    fn get(&self, key: &[u8]) -> DbResult<Option<Bytes>> {
+        get(&self.lock_conn(), &self.table, key)
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> DbResult<Option<Bytes>> {
+        insert(&self.lock_conn(), &self.table, key, value)
+    }
+
+    fn remove(&self, key: &[u8]) -> DbResult<Option<Bytes>> {
+        remove(&self.lock_conn(), &self.table, key)
+    }
+
+    fn iter(&self) -> DbResult<Box<dyn Iterator<Item = DbResult<Pair>>>> {
+        let rows = self.select_rows("key, value", |row| {
+            Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?;
+        let iter = rows
+            .into_iter()
+            .map(|(key, value)| Ok((Box::new(key) as Bytes, Box::new(value) as Bytes)));
+
+        Ok(Box::new(iter))
+    }
+
+    fn keys(&self) -> DbResult<Box<dyn Iterator<Item = DbResult<Bytes>>>> {
+        let rows = self.select_rows("key", |row| row.get::<_, Vec<u8>>(0))?;
+        let iter = rows.into_iter().map(|bytes| Ok(Box::new(bytes) as Bytes));
+
+        Ok(Box::new(iter))
+    }
+
+    fn values(&self) -> DbResult<Box<dyn Iterator<Item = DbResult<Bytes>>>> {
+        let rows = self.select_rows("value", |row| row.get::<_, Vec<u8>>(0))?;
+        let iter = rows.into_iter().map(|bytes| Ok(Box::new(bytes) as Bytes));
+
+        Ok(Box::new(iter))
+    }
+
+    fn clear(&self) -> DbResult<()> {
+        self.lock_conn()
+            .execute(&format!("DELETE FROM {}", self.table), [])?;
+        Ok(())
+    }
+}
+
+impl BucketTransaction for SqliteBucketTransaction<'_> {
+    fn get(&self, key: &[u8]) -> DbResult<Option<Bytes>> {
+        get(self.conn, &self.table, key)
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> DbResult<Option<Bytes>> {
+        insert(self.conn, &self.table, key, value)
+    }
+
+    fn remove(&self, key: &[u8]) -> DbResult<Option<Bytes>> {
+        remove(self.conn, &self.table, key)
+    }
+}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(err: rusqlite::Error) -> Self {
+        DbError::DbEngineError(Box::new(err))
+    }
+}
+
+fn table_name(id: &str) -> String {
+    format!("{BUCKET_TABLE_PREFIX}{id}")
+}
+
+/// Quotes an identifier so it can be used in a statement. Bucket ids can contain any characters.
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn get(conn: &Connection, table: &str, key: &[u8]) -> DbResult<Option<Bytes>> {
+    let bytes: Option<Vec<u8>> = conn
+        .query_row(
+            &format!("SELECT value FROM {table} WHERE key = ?1"),
+            params![key],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(bytes.map(|bytes| Box::new(bytes) as Bytes))
+}
+
+fn insert(conn: &Connection, table: &str, key: &[u8], value: &[u8]) -> DbResult<Option<Bytes>> {
+    let old_value = get(conn, table, key)?;
+    conn.execute(
+        &format!("INSERT OR REPLACE INTO {table} (key, value) VALUES (?1, ?2)"),
+        params![key, value],
+    )?;
+    Ok(old_value)
+}
+
+fn remove(conn: &Connection, table: &str, key: &[u8]) -> DbResult<Option<Bytes>> {
+    let old_value = get(conn, table, key)?;
+    if old_value.is_some() {
+        conn.execute(&format!("DELETE FROM {table} WHERE key = ?1"), params![key])?;
+    }
+    Ok(old_value)
+}
+
+fn create_table(conn: &Connection, table: &str) -> DbResult<()> {
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {table} (key BLOB PRIMARY KEY NOT NULL, value BLOB NOT NULL) WITHOUT ROWID"
+        ),
+        [],
+    )?;
+    Ok(())
+}
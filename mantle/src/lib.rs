@@ -1,4 +1,5 @@
 pub mod api;
+pub mod clock;
 pub mod db;
 pub mod error;
 pub mod http;
@@ -16,4 +17,9 @@ pub mod javascript;
 
 mod threadpool;
 
-pub use crate::threadpool::{execute_and_join_jobs, execute_job, set_max_threads};
+pub use crate::threadpool::{
+    configure_pool, execute_and_join_jobs, execute_job, execute_job_in, set_max_threads, spawn,
+    spawn_cancellable, spawn_cancellable_in, spawn_in, try_execute_and_join_jobs,
+    CancellationToken, JobError, JobHandle, PoolConfig, PoolError, RejectionPolicy, DEFAULT_POOL,
+    POLL_POOL,
+};
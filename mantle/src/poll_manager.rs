@@ -1,6 +1,20 @@
+mod change_filter;
+mod cron;
+pub mod lifecycle;
 mod manager;
 mod poll;
 mod poll_config;
+mod poll_schedule;
+mod poll_stats;
+mod timers;
+mod wall_clock;
 
+pub use change_filter::ChangeFilter;
+pub use cron::{CronError, CronExpr};
 pub use manager::PollManager;
-pub use poll_config::PollConfig;
+pub use poll_config::{
+    FailurePolicy, FallibleRespondent, PollConfig, PollErrorCallback, PollResult,
+};
+pub use poll_schedule::PollSchedule;
+pub use poll_stats::PollStats;
+pub use wall_clock::WallClock;
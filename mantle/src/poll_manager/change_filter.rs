@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+/// A result kept for the callback until the debounce window ends. Sendable even if the result isn't.
+pub(crate) type Debounced<T> = Box<dyn FnOnce() -> T + Send>;
+
+/// Suppresses callbacks of poll results that didn't change since the previous callback.
+///
+/// # Examples
+///
+/// ```
+/// use mantle_utilities::poll_manager::{ChangeFilter, PollConfig};
+///
+/// let config = PollConfig {
+///     sleep_time: Some(1000),
+///     respondent: Some(Box::new(|| "idle".to_string())),
+///     callback: Some(Box::new(|state| println!("Device is {state}"))),
+///     change_filter: Some(ChangeFilter::on_change().debounce(3000)),
+///     ..Default::default()
+/// };
+/// ```
+pub struct ChangeFilter<T> {
+    results: Box<dyn KeptResults<T>>,
+    debounce: u64,
+    hold: fn(T) -> Debounced<T>,
+}
+
+/// What to do with a result after the [ChangeFilter].
+pub(crate) enum Filtered<T> {
+    Unchanged,
+    Changed(T),
+    Debounced(Debounced<T>, Duration),
+    /// Back to the called back result, so the debounced one isn't called back.
+    Reverted,
+}
+
+/// How a result compares to the results kept by a [ChangeFilter].
+enum Change {
+    Unchanged,
+    Changed,
+    Reverted,
+}
+
+/// The called back and the debounced result of a [ChangeFilter]. Boxed, so the filter is Send even if the result isn't.
+trait KeptResults<T>: Send {
+    /// Compares the result to the debounced one if there is one, then to the called back one.
+    fn compare(&self, result: &T) -> Change;
+    fn keep_delivered(&mut self, result: &T);
+    fn keep_debounced(&mut self, result: Option<&T>);
+    /// Keeps the debounced result as the called back one.
+    fn deliver_debounced(&mut self);
+}
+
+struct Results<T, F> {
+    is_changed: F,
+    delivered: Option<T>,
+    debounced: Option<T>,
+}
+
+impl<T, F> KeptResults<T> for Results<T, F>
+where
+    T: Clone + Send,
+    F: Fn(&T, &T) -> bool + Send,
+{
+    fn compare(&self, result: &T) -> Change {
+        if let Some(debounced) = self.debounced.as_ref() {
+            if !(self.is_changed)(debounced, result) {
+                return Change::Unchanged;
+            }
+        }
+        match self.delivered.as_ref() {
+            Some(delivered) if !(self.is_changed)(delivered, result) => {
+                if self.debounced.is_some() {
+                    Change::Reverted
+                } else {
+                    Change::Unchanged
+                }
+            }
+            _ => Change::Changed,
+        }
+    }
+
+    fn keep_delivered(&mut self, result: &T) {
+        self.delivered = Some(result.clone());
+    }
+
+    fn keep_debounced(&mut self, result: Option<&T>) {
+        self.debounced = result.cloned();
+    }
+
+    fn deliver_debounced(&mut self) {
+        if let Some(debounced) = self.debounced.take() {
+            self.delivered = Some(debounced);
+        }
+    }
+}
+
+impl<T: Clone + Send + 'static> ChangeFilter<T> {
+    /// Calls back results that `is_changed` compared to the previous called back result.
+    /// The first result is always called back.
+    pub fn new(is_changed: impl Fn(&T, &T) -> bool + Send + 'static) -> Self {
+        ChangeFilter {
+            results: Box::new(Results {
+                is_changed,
+                delivered: None,
+                debounced: None,
+            }),
+            debounce: 0,
+            hold: |result| Box::new(move || result),
+        }
+    }
+}
+
+impl<T: PartialEq + Clone + Send + 'static> ChangeFilter<T> {
+    /// Calls back results that aren't equal to the previous called back result.
+    pub fn on_change() -> Self {
+        ChangeFilter::new(|previous, result| previous != result)
+    }
+}
+
+impl<T> ChangeFilter<T> {
+    /// Waits until the result doesn't change for `debounce` milliseconds and calls back only the last one.
+    /// A result changing back to the previous called back one in the meantime isn't called back again.
+    pub fn debounce(mut self, debounce: u64) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    pub(crate) fn filter(&mut self, result: T) -> Filtered<T> {
+        match self.results.compare(&result) {
+            Change::Unchanged => Filtered::Unchanged,
+            Change::Reverted => {
+                self.results.keep_debounced(None);
+                Filtered::Reverted
+            }
+            Change::Changed if self.debounce == 0 => {
+                self.results.keep_delivered(&result);
+                Filtered::Changed(result)
+            }
+            Change::Changed => {
+                self.results.keep_debounced(Some(&result));
+                Filtered::Debounced((self.hold)(result), Duration::from_millis(self.debounce))
+            }
+        }
+    }
+
+    /// Records that the debounced result was called back, so later results are compared to it.
+    pub(crate) fn delivered(&mut self) {
+        self.results.deliver_debounced();
+    }
+}
+
+impl<T> std::fmt::Debug for ChangeFilter<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChangeFilter")
+            .field("debounce", &self.debounce)
+            .finish()
+    }
+}
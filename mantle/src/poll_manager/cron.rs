@@ -0,0 +1,180 @@
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Days to search for a matching date. A day of month and a day of week can match together only every 28 years.
+const SEARCH_DAYS: u32 = 366 * 28;
+
+/// A five-field cron expression: minute, hour, day of month, month and day of week.
+///
+/// Fields take `*`, values, ranges `a-b`, steps `*/n` or `a-b/n` and comma separated lists of them.
+/// Days of week count from Sunday, which is both 0 and 7. Like in cron, a date matches either day field
+/// when both are restricted.
+///
+/// # Examples
+///
+/// ```
+/// use chrono::{NaiveDate, TimeZone, Utc};
+/// use mantle_utilities::poll_manager::CronExpr;
+///
+/// let every_hour: CronExpr = "0 * * * *".parse().unwrap();
+/// let date = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+/// let time = Utc.from_utc_datetime(&date.and_hms_opt(10, 30, 0).unwrap());
+/// let next = Utc.from_utc_datetime(&date.and_hms_opt(11, 0, 0).unwrap());
+/// assert_eq!(every_hour.next_after(&time), Some(next));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CronError {
+    #[error("Cron expression needs 5 fields, got {0}")]
+    FieldCount(usize),
+    #[error("Invalid cron field '{0}'")]
+    InvalidField(String),
+}
+
+impl CronExpr {
+    /// Returns the first matching time after the `time`, in its time zone. Local times skipped by
+    /// a daylight saving change don't match, and repeated ones match only the first time.
+    /// Returns None if no date matches, e.g. for February 30.
+    pub fn next_after<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let tz = time.timezone();
+        let local = time.naive_local();
+        let mut date = local.date();
+        for _ in 0..SEARCH_DAYS {
+            if self.matches_date(date) {
+                for hour in (0..24).filter(|hour| self.hours & 1 << hour != 0) {
+                    for minute in (0..60).filter(|minute| self.minutes & 1 << minute != 0) {
+                        let candidate = date.and_hms_opt(hour, minute, 0)?;
+                        if candidate < local {
+                            continue;
+                        }
+                        match tz.from_local_datetime(&candidate).earliest() {
+                            Some(candidate) if candidate > *time => return Some(candidate),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if self.months & 1 << date.month() == 0 {
+            return false;
+        }
+        let day = self.days & 1 << date.day() != 0;
+        let weekday = self.weekdays & 1 << date.weekday().num_days_from_sunday() != 0;
+        if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        }
+    }
+}
+
+impl FromStr for CronExpr {
+    type Err = CronError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<_> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(CronError::FieldCount(fields.len()));
+        };
+        // Sunday is both 0 and 7.
+        let weekdays_bits = parse_field(weekdays, 0, 7)?;
+        Ok(CronExpr {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: (weekdays_bits | weekdays_bits >> 7) & 0x7f,
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+        })
+    }
+}
+
+/// Returns a bit for every value of the field.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, CronError> {
+    let invalid = || CronError::InvalidField(field.to_string());
+    let parse = |value: &str| value.parse::<u32>().map_err(|_| invalid());
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, parse(step)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            // `a/n` steps from `a` to the maximum.
+            None if part.contains('/') => (parse(range)?, max),
+            None => (parse(range)?, parse(range)?),
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+#[cfg(test)]
+fn utc(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<chrono::Utc> {
+    let date = NaiveDate::from_ymd_opt(2024, month, day).unwrap();
+    chrono::Utc.from_utc_datetime(&date.and_hms_opt(hour, minute, 0).unwrap())
+}
+
+#[test]
+fn finds_next_matching_times() {
+    let daily: CronExpr = "0 3 * * *".parse().unwrap();
+    assert_eq!(daily.next_after(&utc(5, 1, 2, 59)), Some(utc(5, 1, 3, 0)));
+    assert_eq!(daily.next_after(&utc(5, 1, 3, 0)), Some(utc(5, 2, 3, 0)));
+
+    // 2024-05-03 is a Friday.
+    let work_hours: CronExpr = "*/15 9-17 * * 1-5".parse().unwrap();
+    assert_eq!(
+        work_hours.next_after(&utc(5, 3, 9, 20)),
+        Some(utc(5, 3, 9, 30))
+    );
+    assert_eq!(
+        work_hours.next_after(&utc(5, 3, 17, 45)),
+        Some(utc(5, 6, 9, 0))
+    );
+}
+
+#[test]
+fn restricted_day_fields_match_either_day() {
+    // The 10th or Sundays, 2024-05-05 is a Sunday.
+    let cron: CronExpr = "0 0 10 * 7".parse().unwrap();
+    assert_eq!(cron.next_after(&utc(5, 1, 0, 0)), Some(utc(5, 5, 0, 0)));
+    assert_eq!(cron.next_after(&utc(5, 5, 0, 0)), Some(utc(5, 10, 0, 0)));
+
+    let never: CronExpr = "0 0 30 2 *".parse().unwrap();
+    assert_eq!(never.next_after(&utc(5, 1, 0, 0)), None);
+}
+
+#[test]
+fn rejects_invalid_expressions() {
+    assert_eq!("0 3 * *".parse::<CronExpr>(), Err(CronError::FieldCount(4)));
+    for field in ["60", "5-1", "*/0", "a", "1,"] {
+        assert_eq!(
+            format!("{field} * * * *").parse::<CronExpr>(),
+            Err(CronError::InvalidField(field.to_string()))
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use std::sync::Mutex;
+
+/// Called with true when the app pauses polling and with false when it resumes.
+/// Returns false when the listening manager is dropped, so the listener is removed.
+pub(crate) type LifecycleListener = Box<dyn Fn(bool) -> bool + Send>;
+
+/// App lifecycle and connectivity reported by the platform bindings.
+struct AppState {
+    in_background: bool,
+    network_lost: bool,
+    listeners: Vec<LifecycleListener>,
+}
+
+static APP_STATE: Mutex<AppState> = Mutex::new(AppState {
+    in_background: false,
+    network_lost: false,
+    listeners: Vec::new(),
+});
+
+impl AppState {
+    fn pauses_polling(&self) -> bool {
+        self.in_background || self.network_lost
+    }
+}
+
+/// Pauses polls of every [crate::poll_manager::PollManager] while the app is in the background.
+/// Polls that `run_in_background` keep running.
+pub fn set_app_in_background(in_background: bool) {
+    update_app_state(|state| state.in_background = in_background);
+}
+
+/// Pauses polls of every [crate::poll_manager::PollManager] while there's no network.
+/// Polls that `run_in_background` keep running.
+pub fn set_network_available(available: bool) {
+    update_app_state(|state| state.network_lost = !available);
+}
+
+/// Calls the `listener` with the current state right away and on every change.
+pub(crate) fn listen(listener: LifecycleListener) {
+    let mut state = APP_STATE.lock().unwrap();
+    if listener(state.pauses_polling()) {
+        state.listeners.push(listener);
+    }
+}
+
+fn update_app_state(f: impl FnOnce(&mut AppState)) {
+    let mut state = APP_STATE.lock().unwrap();
+    let paused = state.pauses_polling();
+    f(&mut state);
+    let pauses_polling = state.pauses_polling();
+    if paused != pauses_polling {
+        state.listeners.retain(|listener| listener(pauses_polling));
+    }
+}
@@ -1,15 +1,22 @@
+use crate::clock::{Clock, SystemClock};
+use crate::poll_manager::change_filter::Filtered;
+use crate::poll_manager::lifecycle;
 use crate::poll_manager::poll::Poll;
-use crate::poll_manager::PollConfig;
-use crate::threadpool::num_worker_threads;
-use log::{debug, error};
+use crate::poll_manager::timers::{TimerKind, Timers};
+use crate::poll_manager::{
+    FallibleRespondent, PollConfig, PollErrorCallback, PollSchedule, PollStats,
+};
+use crate::threadpool::{self, POLL_POOL};
+use log::{debug, error, warn};
 use std::collections::HashMap;
 use std::sync::atomic::Ordering::{Acquire, Release, SeqCst};
 use std::sync::atomic::{AtomicBool, AtomicU32};
-use std::sync::{Arc, Mutex, MutexGuard};
-use std::thread::sleep;
-use std::time::Duration;
-use threadpool::ThreadPool;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
 
+/// Runs respondents of polls on the [POLL_POOL] thread pool. A single scheduler thread waits for
+/// the next due poll, so the number of polls doesn't affect the number of threads.
 #[derive(Debug)]
 pub struct PollManager<T> {
     state: Arc<State<T>>,
@@ -19,20 +26,55 @@ pub struct PollManager<T> {
 struct State<T> {
     poll_map: Mutex<HashMap<u32, Poll<T>>>,
     polling: AtomicBool,
+    /// Pause flags are only written with the poll map locked, so polls are paused and resumed in order.
+    paused: AtomicBool,
+    app_paused: AtomicBool,
     next_poll_id: AtomicU32,
-    thread_pool: Mutex<ThreadPool>,
+    running_jobs: Arc<RunningJobs>,
+    timers: Arc<Timers>,
+    clock: Arc<dyn Clock>,
+}
+
+/// Counts jobs of the manager queued or running on the [POLL_POOL], for [PollManager::wait_until_idle].
+#[derive(Debug, Default)]
+struct RunningJobs {
+    count: Mutex<usize>,
+    finished: Condvar,
+}
+
+/// Counts a job as finished when dropped, also if the job panics.
+struct JobGuard(Arc<RunningJobs>);
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        let mut count = self.0.count.lock().unwrap();
+        *count -= 1;
+        self.0.finished.notify_all();
+    }
 }
 
 impl<T: 'static> PollManager<T> {
     pub fn new() -> Self {
-        Self {
+        PollManager::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Times the polls by the `clock`, e.g. a [crate::clock::MockClock] in tests.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        let manager = Self {
             state: Arc::new(State {
                 poll_map: Mutex::new(HashMap::new()),
                 polling: AtomicBool::from(false),
+                paused: AtomicBool::from(false),
+                app_paused: AtomicBool::from(false),
                 next_poll_id: AtomicU32::from(0),
-                thread_pool: Mutex::new(ThreadPool::new(num_worker_threads())),
+                running_jobs: Default::default(),
+                timers: Timers::new(Arc::clone(&clock)),
+                clock,
             }),
-        }
+        };
+        manager.spawn_scheduler();
+        manager.listen_to_lifecycle();
+        manager
     }
 
     pub fn start_polling(&self) {
@@ -43,7 +85,10 @@ impl<T: 'static> PollManager<T> {
             .is_ok()
         {
             for (_, poll) in self.lock_poll_map().iter_mut() {
-                self.start_poll(poll);
+                poll.set_on(true);
+                // A poll still running from before the stop restarts after its respondent returns.
+                let delay = poll.start_delay(self.state.clock.as_ref());
+                self.wake_up(poll, delay);
             }
         }
     }
@@ -55,26 +100,46 @@ impl<T: 'static> PollManager<T> {
         }
     }
 
+    /// Stops running polls until [PollManager::resume], except polls that `run_in_background`.
+    /// Polls keep their state and the time left until their next run.
+    /// The manager is also paused while the app is in the background or offline, see [lifecycle].
+    pub fn pause(&self) {
+        self.set_paused(&self.state.paused, true);
+    }
+
+    /// Resumes polls paused by [PollManager::pause]. They run after the time that was left when they were paused.
+    pub fn resume(&self) {
+        self.set_paused(&self.state.paused, false);
+    }
+
+    /// Returns true if the manager or the app lifecycle pauses the polls.
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Acquire) || self.state.app_paused.load(Acquire)
+    }
+
     pub fn add_poll(&self, config: PollConfig<T>) -> u32 {
         let poll_id = self.next_poll_id();
         let poll = Poll::new(poll_id, config);
 
         let mut poll_map = self.lock_poll_map();
         poll_map.insert(poll_id, poll);
-        let count = poll_map.len();
-        self.set_pool_count(count);
         if self.state.polling.load(Acquire) {
             if let Some(poll) = poll_map.get_mut(&poll_id) {
-                self.start_poll(poll);
+                poll.set_on(true);
+                let delay = poll.start_delay(self.state.clock.as_ref());
+                self.schedule(poll, delay);
             }
         }
         poll_id
     }
 
     /// Replaces the `Poll` config with `config` values that are Some.
+    /// A running poll runs with the new config right away, or at the next time of a wall-clock schedule.
     pub fn update_poll(&self, poll_id: u32, new_config: PollConfig<T>) {
         if let Some(poll) = self.lock_poll_map().get_mut(&poll_id) {
             poll.update(new_config);
+            let delay = poll.start_delay(self.state.clock.as_ref());
+            self.wake_up(poll, delay);
         }
     }
 
@@ -82,11 +147,45 @@ impl<T: 'static> PollManager<T> {
     /// Default values are:
     /// on - true
     /// sleep_time - 5000 milliseconds
+    /// schedule - fixed `sleep_time`
     /// callback - None
     /// respondent - None
+    /// error_callback - None
+    /// failure_policy - None
+    /// run_in_background - false
+    /// change_filter - None
+    /// A running poll runs with the new config right away, or at the next time of a wall-clock schedule.
     pub fn replace_poll_config(&self, poll_id: u32, new_config: PollConfig<T>) {
         if let Some(poll) = self.lock_poll_map().get_mut(&poll_id) {
             poll.replace(new_config);
+            let delay = poll.start_delay(self.state.clock.as_ref());
+            self.wake_up(poll, delay);
+        }
+    }
+
+    /// Calls the respondent of a running poll right away instead of waiting for the rest of its delay.
+    /// The next delay is counted from this call. Does nothing if the poll isn't running.
+    pub fn poll_now(&self, poll_id: u32) {
+        if let Some(poll) = self.lock_poll_map().get_mut(&poll_id) {
+            self.wake_up(poll, Duration::ZERO);
+        }
+    }
+
+    /// Blocks until no poll is due, running or being called back. Polls due later by the clock don't count,
+    /// so tests with a [crate::clock::MockClock] can check that nothing ran after advancing it.
+    #[doc(hidden)]
+    pub fn wait_until_idle(&self) {
+        let jobs = &self.state.running_jobs;
+        loop {
+            self.state.timers.wait_until_idle();
+            let mut count = jobs.count.lock().unwrap();
+            while *count > 0 {
+                count = jobs.finished.wait(count).unwrap();
+            }
+            // The finished jobs may have scheduled timers that are due already.
+            if self.state.timers.is_idle() {
+                return;
+            }
         }
     }
 
@@ -96,24 +195,45 @@ impl<T: 'static> PollManager<T> {
         }
     }
 
+    pub fn set_poll_error_callback(&self, poll_id: u32, error_callback: Option<PollErrorCallback>) {
+        if let Some(poll) = self.lock_poll_map().get_mut(&poll_id) {
+            poll.set_error_callback(error_callback);
+        }
+    }
+
     pub fn set_poll_respondent(&self, poll_id: u32, respondent: Option<Box<dyn Fn() -> T + Send>>) {
         if let Some(poll) = self.lock_poll_map().get_mut(&poll_id) {
-            poll.set_respondent(respondent);
+            poll.set_respondent(
+                respondent.map(|respondent| -> FallibleRespondent<T> {
+                    Box::new(move || Ok(respondent()))
+                }),
+            );
         }
     }
 
+    /// Returns results of the poll respondent so far, None if there's no such poll.
+    pub fn poll_stats(&self, poll_id: u32) -> Option<PollStats> {
+        self.lock_poll_map()
+            .get(&poll_id)
+            .map(|poll| poll.stats.clone())
+    }
+
     pub fn clear_poll(&self, poll_id: u32) {
-        let mut poll_map = self.lock_poll_map();
-        if poll_map.remove(&poll_id).is_some() {
+        if self.lock_poll_map().remove(&poll_id).is_some() {
             debug!("Removed poll ID: {}", poll_id);
-            self.set_pool_count(poll_map.len());
         }
     }
 
+    /// Stops the poll and removes it once its running respondent returns.
     pub fn remove_poll(&self, poll_id: u32) {
-        if let Some(poll) = self.lock_poll_map().get_mut(&poll_id) {
+        let mut poll_map = self.lock_poll_map();
+        if let Some(poll) = poll_map.get_mut(&poll_id) {
             poll.set_on(false);
             poll.set_remove(true);
+            if !poll.running {
+                poll_map.remove(&poll_id);
+                debug!("Removed poll ID: {}", poll_id);
+            }
         }
     }
 
@@ -121,108 +241,313 @@ impl<T: 'static> PollManager<T> {
         self.state.next_poll_id.fetch_add(1, Release)
     }
 
-    fn start_poll(&self, poll: &mut Poll<T>) {
-        poll.set_on(true);
-        let poll_id = poll.id;
-        let manager = self.clone();
+    fn lock_poll_map(&self) -> MutexGuard<HashMap<u32, Poll<T>>> {
+        self.state.poll_map.lock().unwrap()
+    }
 
-        self.execute_job(move || {
-            let mut on = false;
-            if let Some(poll) = manager.lock_poll_map().get_mut(&poll_id) {
-                if !poll.running {
-                    on = poll.on;
-                }
-            }
+    fn set_paused(&self, flag: &AtomicBool, paused: bool) {
+        let mut poll_map = self.lock_poll_map();
+        flag.store(paused, Release);
+        for poll in poll_map.values_mut() {
+            self.apply_pause(poll);
+        }
+    }
 
-            if on {
-                manager.poll_loop(poll_id);
+    /// Keeps the time left until the next run of a paused poll, or schedules a resumed poll.
+    /// Running polls are paused when they return, by [PollManager::schedule].
+    /// Wall-clock polls resume at their time, or right away if it passed while they were paused.
+    fn apply_pause(&self, poll: &mut Poll<T>) {
+        let scheduled = poll.on && self.state.polling.load(Acquire);
+        if self.is_paused() && !poll.run_in_background {
+            if scheduled && !poll.running && poll.paused_delay.is_none() {
+                // Polls without a due time never run, so they stay unscheduled.
+                if let Some(due) = poll.due {
+                    let now = self.state.clock.now();
+                    poll.paused_delay = Some(due.saturating_duration_since(now));
+                    poll.timer_seq += 1;
+                }
             }
-
-            let remove = if let Some(poll) = manager.lock_poll_map().get_mut(&poll_id) {
-                poll.running = false;
-                poll.remove
-            } else {
-                false
+        } else if let Some(delay) = poll.paused_delay.take() {
+            let delay = match (poll.schedule.as_ref(), poll.due) {
+                (PollSchedule::WallClock(_), Some(due)) => {
+                    due.saturating_duration_since(self.state.clock.now())
+                }
+                _ => delay,
             };
-            if remove {
-                manager.clear_poll(poll_id);
+            if scheduled {
+                self.schedule(poll, delay);
             }
-        });
+        }
     }
 
-    fn lock_poll_map(&self) -> MutexGuard<HashMap<u32, Poll<T>>> {
-        self.state.poll_map.lock().unwrap()
+    /// Pauses the manager while the app is in the background or offline.
+    fn listen_to_lifecycle(&self) {
+        let state = Arc::downgrade(&self.state);
+        lifecycle::listen(Box::new(move |paused| match Weak::upgrade(&state) {
+            Some(state) => {
+                let manager = PollManager { state };
+                manager.set_paused(&manager.state.app_paused, paused);
+                true
+            }
+            None => false,
+        }));
     }
 
-    fn poll_loop(&self, poll_id: u32) {
-        loop {
-            let (sleep_time, callback, respondent) =
-                if let Some(poll) = self.lock_poll_map().get_mut(&poll_id) {
-                    if !poll.on {
+    /// Dispatches due polls until the manager is dropped. Holds a weak reference to the state,
+    /// so the state is dropped with the last manager, which shuts the timers down.
+    fn spawn_scheduler(&self) {
+        let timers = Arc::clone(&self.state.timers);
+        let state = Arc::downgrade(&self.state);
+        let result = thread::Builder::new()
+            .name("mantle-poll-scheduler".to_string())
+            .spawn(move || {
+                while let Some((poll_id, seq, kind)) = timers.next_due() {
+                    let Some(state) = Weak::upgrade(&state) else {
                         break;
+                    };
+                    let manager = PollManager { state };
+                    match kind {
+                        TimerKind::Run => manager.dispatch(poll_id, seq),
+                        TimerKind::Deliver => manager.deliver(poll_id, seq),
                     }
+                }
+            });
+        if let Err(err) = result {
+            error!("Error spawning poll scheduler thread: {err}");
+        }
+    }
+
+    /// Schedules the next run of the poll after the `delay`, replacing its previous timer.
+    fn schedule(&self, poll: &mut Poll<T>, delay: Duration) {
+        self.schedule_from(poll, self.state.clock.now(), delay);
+    }
+
+    /// Schedules the next run of the poll the `delay` after the `start`, replacing its previous timer.
+    /// A paused poll keeps the time left until it's resumed.
+    fn schedule_from(&self, poll: &mut Poll<T>, start: Instant, delay: Duration) {
+        poll.timer_seq += 1;
+        // Delays too long for an Instant never end.
+        let due = start.checked_add(delay);
+        if self.is_paused() && !poll.run_in_background {
+            let now = self.state.clock.now();
+            poll.paused_delay = Some(due.map_or(delay, |due| due.saturating_duration_since(now)));
+            return;
+        }
+        poll.paused_delay = None;
+        let Some(due) = due else {
+            poll.due = None;
+            return;
+        };
+        poll.due = Some(due);
+        self.state
+            .timers
+            .schedule(due, poll.id, poll.timer_seq, TimerKind::Run);
+    }
+
+    /// Schedules the callback of the `debounced` result the `delay` after the `start`, replacing its previous timer.
+    fn schedule_delivery(&self, poll: &mut Poll<T>, start: Instant, delay: Duration) {
+        poll.debounce_seq += 1;
+        // Like runs, deliveries too far off for an Instant never happen.
+        let Some(due) = start.checked_add(delay) else {
+            return;
+        };
+        self.state
+            .timers
+            .schedule(due, poll.id, poll.debounce_seq, TimerKind::Deliver);
+    }
+
+    /// Runs the poll after the `delay`, or right after its running respondent returns.
+    fn wake_up(&self, poll: &mut Poll<T>, delay: Duration) {
+        if !poll.on || !self.state.polling.load(Acquire) {
+            return;
+        }
+        if poll.running {
+            poll.set_wake_up(true);
+        } else {
+            self.schedule(poll, delay);
+        }
+    }
+
+    /// Runs the respondent and the callback of a due poll on the thread pool.
+    /// Timers of stopped and rescheduled polls are ignored.
+    fn dispatch(&self, poll_id: u32, seq: u64) {
+        let (schedule, change_filter, callback, error_callback, respondent, mut failures) =
+            match self.lock_poll_map().get_mut(&poll_id) {
+                Some(poll) if poll.on && !poll.running && poll.timer_seq == seq => {
                     poll.running = true;
                     poll.should_store_callback_after_sleep = true;
+                    poll.should_store_error_callback_after_sleep = true;
                     poll.should_store_respondent_after_sleep = true;
                     (
-                        poll.sleep_time,
+                        Arc::clone(&poll.schedule),
+                        poll.change_filter.clone(),
                         poll.callback.take(),
+                        poll.error_callback.take(),
                         poll.respondent.take(),
+                        poll.failures,
                     )
-                } else {
-                    break;
-                };
-
-            if let Some(respondent) = respondent.as_ref() {
-                let result = respondent();
-
-                if let Some(callback) = callback.as_ref() {
-                    callback(result);
                 }
+                Some(poll) if poll.on && poll.timer_seq == seq => {
+                    poll.set_wake_up(true);
+                    return;
+                }
+                _ => return,
             };
 
-            sleep(Duration::from_millis(sleep_time));
+        let manager = self.clone();
+        self.execute_job(move || {
+            let clock = Arc::clone(&manager.state.clock);
+            // Delays are counted from the respondent call, so they don't drift by its duration.
+            let start = clock.now();
+            let result = respondent.as_ref().map(|respondent| respondent());
+            let succeeded = result.as_ref().map(Result::is_ok);
+            let sleep_time = schedule.next_delay(
+                result.as_ref().and_then(|result| result.as_ref().ok()),
+                succeeded == Some(false),
+                &mut failures,
+                clock.as_ref(),
+            );
 
-            if let Some(poll) = self.lock_poll_map().get_mut(&poll_id) {
-                if poll.should_store_callback_after_sleep {
-                    poll.callback = callback;
+            let mut debounced = None;
+            let mut reverted = false;
+            match result {
+                Some(Ok(result)) => {
+                    let filtered = match change_filter.as_ref() {
+                        Some(change_filter) => change_filter.lock().unwrap().filter(result),
+                        None => Filtered::Changed(result),
+                    };
+                    match filtered {
+                        Filtered::Changed(result) => {
+                            if let Some(callback) = callback.as_ref() {
+                                callback(result);
+                            }
+                        }
+                        Filtered::Debounced(result, delay) => debounced = Some((result, delay)),
+                        Filtered::Reverted => reverted = true,
+                        Filtered::Unchanged => {}
+                    }
+                }
+                Some(Err(err)) => {
+                    warn!("Poll ID {} respondent failed: {}", poll_id, err);
+                    if let Some(error_callback) = error_callback.as_ref() {
+                        error_callback(err);
+                    }
                 }
-                if poll.should_store_respondent_after_sleep {
-                    poll.respondent = respondent;
+                None => {}
+            }
+
+            let mut poll_map = manager.lock_poll_map();
+            let Some(poll) = poll_map.get_mut(&poll_id) else {
+                return;
+            };
+            if poll.should_store_callback_after_sleep {
+                poll.callback = callback;
+            }
+            if poll.should_store_error_callback_after_sleep {
+                poll.error_callback = error_callback;
+            }
+            if poll.should_store_respondent_after_sleep {
+                poll.respondent = respondent;
+            }
+            poll.running = false;
+            poll.failures = failures;
+            if let Some(succeeded) = succeeded {
+                if poll.record_result(succeeded, clock.system_time()) {
+                    debug!("Poll ID {} stopped by {:?}", poll_id, poll.failure_policy);
                 }
+            }
+            if let Some((result, delay)) = debounced {
+                poll.debounced = Some(result);
+                poll.deliver_after_run = false;
+                manager.schedule_delivery(poll, start, delay);
+            } else if reverted {
+                // The result changed back to the called back one before the debounced one was due.
+                poll.debounced = None;
+                poll.deliver_after_run = false;
+            } else if poll.deliver_after_run {
+                poll.deliver_after_run = false;
+                manager.schedule_delivery(poll, clock.now(), Duration::ZERO);
+            }
 
-                if !poll.on {
-                    poll.running = false;
-                    break;
+            if poll.remove {
+                poll_map.remove(&poll_id);
+                debug!("Removed poll ID: {}", poll_id);
+            } else if poll.on {
+                if poll.wake_up {
+                    poll.set_wake_up(false);
+                    let delay = poll.start_delay(clock.as_ref());
+                    manager.schedule(poll, delay);
+                } else {
+                    manager.schedule_from(poll, start, Duration::from_millis(sleep_time));
                 }
             }
-        }
+        });
     }
 
-    fn set_pool_count(&self, count: usize) {
-        if count < num_worker_threads() {
-            return;
-        }
-        match self.state.thread_pool.lock() {
-            Ok(mut pool) => {
-                pool.set_num_threads(count);
-                debug!("poll pool active count is: {}", pool.active_count());
-                debug!("poll pool queued count is: {}", pool.queued_count());
+    /// Calls back the debounced result of the poll on the thread pool.
+    /// A running poll calls it back after the respondent returns.
+    fn deliver(&self, poll_id: u32, seq: u64) {
+        let (change_filter, callback, result) = match self.lock_poll_map().get_mut(&poll_id) {
+            Some(poll) if poll.debounce_seq == seq && poll.debounced.is_some() => {
+                if poll.running {
+                    poll.deliver_after_run = true;
+                    return;
+                }
+                poll.running = true;
+                poll.should_store_callback_after_sleep = true;
+                (
+                    poll.change_filter.clone(),
+                    poll.callback.take(),
+                    poll.debounced.take(),
+                )
             }
-            Err(err) => error!("Error getting lock on thread-pool: {err}"),
-        }
+            _ => return,
+        };
+
+        let manager = self.clone();
+        self.execute_job(move || {
+            if let Some(change_filter) = change_filter.as_ref() {
+                change_filter.lock().unwrap().delivered();
+            }
+            if let (Some(callback), Some(result)) = (callback.as_ref(), result) {
+                callback(result());
+            }
+
+            let mut poll_map = manager.lock_poll_map();
+            let Some(poll) = poll_map.get_mut(&poll_id) else {
+                return;
+            };
+            if poll.should_store_callback_after_sleep {
+                poll.callback = callback;
+            }
+            poll.running = false;
+
+            if poll.remove {
+                poll_map.remove(&poll_id);
+                debug!("Removed poll ID: {}", poll_id);
+            } else if poll.wake_up {
+                // The run timer was due while the result was called back.
+                poll.set_wake_up(false);
+                let delay = poll.start_delay(manager.state.clock.as_ref());
+                manager.schedule(poll, delay);
+            } else {
+                manager.apply_pause(poll);
+            }
+        });
     }
 
     pub fn execute_job<F>(&self, job: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        match self.state.thread_pool.lock() {
-            Ok(pool) => {
-                pool.execute(job);
-            }
-            Err(err) => error!("Error getting lock on thread-pool: {err}"),
-        }
+        let running_jobs = Arc::clone(&self.state.running_jobs);
+        *running_jobs.count.lock().unwrap() += 1;
+        let guard = JobGuard(running_jobs);
+        // A job the pool rejects drops its guard with it, so it isn't counted as running.
+        threadpool::execute_job_in(POLL_POOL, move || {
+            let _guard = guard;
+            job();
+        });
     }
 }
 
@@ -239,3 +564,9 @@ impl<T: 'static> Default for PollManager<T> {
         PollManager::new()
     }
 }
+
+impl<T> Drop for State<T> {
+    fn drop(&mut self) {
+        self.timers.shut_down();
+    }
+}
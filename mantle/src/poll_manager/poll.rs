@@ -1,33 +1,80 @@
-use crate::poll_manager::PollConfig;
+use crate::clock::Clock;
+use crate::poll_manager::change_filter::Debounced;
+use crate::poll_manager::ChangeFilter;
+use crate::poll_manager::{
+    FailurePolicy, FallibleRespondent, PollConfig, PollErrorCallback, PollSchedule, PollStats,
+};
 use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 const DEFAULT_SLEEP_TIME_MILLIS: u64 = 5000;
 
 pub struct Poll<T> {
     pub id: u32,
     pub on: bool,
+    /// The respondent and the callback are running on the thread pool.
     pub running: bool,
-    pub sleep_time: u64,
+    /// Shared with the running job, so it can be replaced while the respondent runs.
+    pub schedule: Arc<PollSchedule<T>>,
     pub remove: bool,
+    /// Reschedules the poll with its start delay right after the running respondent returns.
+    pub wake_up: bool,
+    /// Identifies the valid timer of the poll. Incremented when the poll is scheduled.
+    pub timer_seq: u64,
+    /// Consecutive failed results, see [PollSchedule::ExponentialBackoff].
+    pub failures: u32,
+    pub stats: PollStats,
+    pub failure_policy: Option<FailurePolicy>,
+    pub run_in_background: bool,
+    /// When the scheduled run is due.
+    pub due: Option<Instant>,
+    /// The time left until the next run when the manager was paused.
+    pub paused_delay: Option<Duration>,
+    /// Shared with the running job, like the `schedule`.
+    pub change_filter: Option<Arc<Mutex<ChangeFilter<T>>>>,
+    /// The last changed result, called back when the debounce window ends.
+    pub debounced: Option<Debounced<T>>,
+    /// Identifies the valid delivery timer of the `debounced` result.
+    pub debounce_seq: u64,
+    /// The debounce window ended while the poll was running.
+    pub deliver_after_run: bool,
     pub callback: Option<Box<dyn Fn(T) + Send>>,
     pub should_store_callback_after_sleep: bool,
-    pub respondent: Option<Box<dyn Fn() -> T + Send>>,
+    pub error_callback: Option<PollErrorCallback>,
+    pub should_store_error_callback_after_sleep: bool,
+    /// Infallible respondents are wrapped, so they always return Ok.
+    pub respondent: Option<FallibleRespondent<T>>,
     pub should_store_respondent_after_sleep: bool,
 }
 
-impl<T> Poll<T> {
+impl<T: 'static> Poll<T> {
     pub fn new(new_id: u32, config: PollConfig<T>) -> Self {
         let mut poll = Self {
             id: new_id,
             on: true,
             running: false,
             remove: false,
-            sleep_time: DEFAULT_SLEEP_TIME_MILLIS,
+            wake_up: false,
+            timer_seq: 0,
+            failures: 0,
+            stats: PollStats::default(),
+            failure_policy: None,
+            run_in_background: false,
+            due: None,
+            paused_delay: None,
+            change_filter: None,
+            debounced: None,
+            debounce_seq: 0,
+            deliver_after_run: false,
+            schedule: Arc::new(PollSchedule::Fixed(DEFAULT_SLEEP_TIME_MILLIS)),
             callback: None,
-            // Indicates whether the poll callback has changed between the map locks in the poll loop. So we won't rewrite the new callback.
+            // Indicates whether the poll callback has changed while the poll job runs. So we won't rewrite the new callback.
             should_store_callback_after_sleep: true,
+            error_callback: None,
+            should_store_error_callback_after_sleep: true,
             respondent: None,
-            // Indicates whether the poll respondent has changed between the map locks in the poll loop.
+            // Indicates whether the poll respondent has changed while the poll job runs.
             should_store_respondent_after_sleep: true,
         };
         poll.update(config);
@@ -40,37 +87,72 @@ impl<T> Poll<T> {
             self.set_on(on)
         }
         if let Some(sleep_time) = config.sleep_time {
-            self.sleep_time = sleep_time
+            self.schedule = Arc::new(PollSchedule::Fixed(sleep_time))
+        }
+        if let Some(schedule) = config.schedule {
+            self.schedule = Arc::new(schedule)
         }
         if let Some(callback) = config.callback {
             self.should_store_callback_after_sleep = false;
             self.callback = Some(callback);
         }
-        if let Some(respondent) = config.respondent {
+        if let Some(error_callback) = config.error_callback {
+            self.should_store_error_callback_after_sleep = false;
+            self.error_callback = Some(error_callback);
+        }
+        if let Some(respondent) = respondent(config.respondent, config.fallible_respondent) {
             self.should_store_respondent_after_sleep = false;
             self.respondent = Some(respondent);
         }
+        if let Some(failure_policy) = config.failure_policy {
+            self.failure_policy = Some(failure_policy);
+        }
+        if let Some(run_in_background) = config.run_in_background {
+            self.run_in_background = run_in_background;
+        }
+        if let Some(change_filter) = config.change_filter {
+            self.change_filter = Some(Arc::new(Mutex::new(change_filter)));
+        }
     }
 
     /// Replaces config of the `Poll`. Uses default values in place of None in `config`.
     /// Default values are:
     /// on - true
     /// sleep_time - 5000 milliseconds
+    /// schedule - fixed `sleep_time`
     /// callback - None
     /// respondent - None
+    /// error_callback - None
+    /// failure_policy - None
+    /// run_in_background - false
+    /// change_filter - None
     pub fn replace(&mut self, config: PollConfig<T>) {
         self.set_on(config.on.unwrap_or(true));
-        self.sleep_time = config.sleep_time.unwrap_or(DEFAULT_SLEEP_TIME_MILLIS);
+        let sleep_time = config.sleep_time.unwrap_or(DEFAULT_SLEEP_TIME_MILLIS);
+        self.schedule = Arc::new(config.schedule.unwrap_or(PollSchedule::Fixed(sleep_time)));
         self.callback = config.callback;
         self.should_store_callback_after_sleep = false;
-        self.respondent = config.respondent;
+        self.error_callback = config.error_callback;
+        self.should_store_error_callback_after_sleep = false;
+        self.respondent = respondent(config.respondent, config.fallible_respondent);
         self.should_store_respondent_after_sleep = false;
+        self.failure_policy = config.failure_policy;
+        self.run_in_background = config.run_in_background.unwrap_or(false);
+        self.change_filter = config
+            .change_filter
+            .map(|change_filter| Arc::new(Mutex::new(change_filter)));
+    }
+
+    /// The delay before the first run, see [PollSchedule::WallClock].
+    pub fn start_delay(&self, clock: &dyn Clock) -> Duration {
+        Duration::from_millis(self.schedule.start_delay(clock))
     }
 
     pub fn set_on(&mut self, on: bool) {
         self.on = on;
         if !on {
-            self.running = false;
+            self.wake_up = false;
+            self.paused_delay = None;
         }
     }
 
@@ -78,15 +160,56 @@ impl<T> Poll<T> {
         self.remove = remove;
     }
 
+    pub fn set_wake_up(&mut self, wake_up: bool) {
+        self.wake_up = wake_up;
+    }
+
     pub fn set_callback(&mut self, callback: Option<Box<dyn Fn(T) + Send>>) {
         self.callback = callback;
         self.should_store_callback_after_sleep = false;
     }
 
-    pub fn set_respondent(&mut self, respondent: Option<Box<dyn Fn() -> T + Send>>) {
+    pub fn set_error_callback(&mut self, error_callback: Option<PollErrorCallback>) {
+        self.error_callback = error_callback;
+        self.should_store_error_callback_after_sleep = false;
+    }
+
+    pub fn set_respondent(&mut self, respondent: Option<FallibleRespondent<T>>) {
         self.respondent = respondent;
         self.should_store_respondent_after_sleep = false;
     }
+
+    /// Records the result of a respondent call. Returns true if the `failure_policy` paused or removed the poll.
+    pub fn record_result(&mut self, succeeded: bool, now: SystemTime) -> bool {
+        if succeeded {
+            self.stats.record_success(now);
+            return false;
+        }
+        self.stats.record_failure(now);
+        let failures = self.stats.consecutive_failures;
+        match self.failure_policy {
+            Some(FailurePolicy::PauseAfter(max)) if failures.checked_rem(max) == Some(0) => {
+                self.set_on(false);
+                true
+            }
+            Some(FailurePolicy::RemoveAfter(max)) if failures >= max => {
+                self.set_on(false);
+                self.set_remove(true);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Returns the `fallible` respondent, or wraps the infallible one.
+fn respondent<T: 'static>(
+    respondent: Option<Box<dyn Fn() -> T + Send>>,
+    fallible: Option<FallibleRespondent<T>>,
+) -> Option<FallibleRespondent<T>> {
+    fallible.or_else(|| {
+        respondent.map(|respondent| -> FallibleRespondent<T> { Box::new(move || Ok(respondent())) })
+    })
 }
 
 impl<T> Debug for Poll<T> {
@@ -95,13 +218,30 @@ impl<T> Debug for Poll<T> {
             .field("id", &self.id)
             .field("on", &self.on)
             .field("running", &self.running)
-            .field("sleep_time", &self.sleep_time)
+            .field("schedule", &self.schedule)
             .field("remove", &self.remove)
+            .field("wake_up", &self.wake_up)
+            .field("timer_seq", &self.timer_seq)
+            .field("failures", &self.failures)
             .field("has callback", &self.callback.is_some())
             .field(
                 "should_store_callback_after_sleep",
                 &self.should_store_callback_after_sleep,
             )
+            .field("stats", &self.stats)
+            .field("failure_policy", &self.failure_policy)
+            .field("run_in_background", &self.run_in_background)
+            .field("due", &self.due)
+            .field("paused_delay", &self.paused_delay)
+            .field("change_filter", &self.change_filter)
+            .field("has debounced", &self.debounced.is_some())
+            .field("debounce_seq", &self.debounce_seq)
+            .field("deliver_after_run", &self.deliver_after_run)
+            .field("has error_callback", &self.error_callback.is_some())
+            .field(
+                "should_store_error_callback_after_sleep",
+                &self.should_store_error_callback_after_sleep,
+            )
             .field("has respondent", &self.respondent.is_some())
             .field(
                 "should_store_respondent_after_sleep",
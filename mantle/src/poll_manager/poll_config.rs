@@ -1,9 +1,37 @@
+use crate::error::MantleResultError;
+use crate::poll_manager::{ChangeFilter, PollSchedule};
+
+/// A result of a fallible respondent.
+pub type PollResult<T> = Result<T, Box<dyn MantleResultError>>;
+pub type FallibleRespondent<T> = Box<dyn Fn() -> PollResult<T> + Send>;
+pub type PollErrorCallback = Box<dyn Fn(Box<dyn MantleResultError>) + Send>;
+
 pub struct PollConfig<T> {
     pub on: Option<bool>,
-    /// In milliseconds
+    /// In milliseconds. The same as a [PollSchedule::Fixed] schedule.
     pub sleep_time: Option<u64>,
+    /// Replaces the `sleep_time` if both are set.
+    pub schedule: Option<PollSchedule<T>>,
     pub callback: Option<Box<dyn Fn(T) + Send>>,
     pub respondent: Option<Box<dyn Fn() -> T + Send>>,
+    /// Replaces the `respondent` if both are set. Errors go to the `error_callback` instead of the `callback`.
+    pub fallible_respondent: Option<FallibleRespondent<T>>,
+    pub error_callback: Option<PollErrorCallback>,
+    pub failure_policy: Option<FailurePolicy>,
+    /// Keeps the poll running while the manager is paused, see [crate::poll_manager::PollManager::pause].
+    pub run_in_background: Option<bool>,
+    /// Suppresses callbacks of unchanged results. Errors are always called back.
+    pub change_filter: Option<ChangeFilter<T>>,
+}
+
+/// What to do with a poll after consecutive errors of its fallible respondent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Turns the poll off after every given number of consecutive errors.
+    /// [crate::poll_manager::PollManager::update_poll] with `on` turns it back on.
+    PauseAfter(u32),
+    /// Removes the poll after the given number of consecutive errors.
+    RemoveAfter(u32),
 }
 
 // Separate impl block to remove the T: Default bound
@@ -12,8 +40,14 @@ impl<T> Default for PollConfig<T> {
         PollConfig {
             on: None,
             sleep_time: None,
+            schedule: None,
             callback: None,
             respondent: None,
+            fallible_respondent: None,
+            error_callback: None,
+            failure_policy: None,
+            run_in_background: None,
+            change_filter: None,
         }
     }
 }
@@ -0,0 +1,166 @@
+use crate::clock::Clock;
+use crate::poll_manager::WallClock;
+use rand::Rng;
+use std::fmt::{Debug, Formatter};
+
+/// Chooses the delay between respondent calls of a `Poll`. Delays are in milliseconds.
+pub enum PollSchedule<T> {
+    /// Waits the same time after every call.
+    Fixed(u64),
+    /// Waits `delay` after successful results. Every consecutive error or result that `is_failure`
+    /// multiplies the delay by `multiplier`, up to `max_delay`.
+    ExponentialBackoff {
+        delay: u64,
+        max_delay: u64,
+        multiplier: u32,
+        is_failure: Box<dyn Fn(&T) -> bool + Send + Sync>,
+    },
+    /// Waits `delay` plus or minus a random time up to `jitter`, so polls of many devices don't line up.
+    Jittered { delay: u64, jitter: u64 },
+    /// The respondent result chooses the delay, e.g. a short one while a device is cleaning
+    /// and a long one while it's idle. Waits `fallback_delay` if there's no respondent or it fails.
+    RespondentDriven {
+        delay: Box<dyn Fn(&T) -> u64 + Send + Sync>,
+        fallback_delay: u64,
+    },
+    /// Runs at wall-clock times, e.g. daily at 03:00 local time. The first run waits for the next time too.
+    WallClock(WallClock),
+}
+
+impl<T> PollSchedule<T> {
+    /// Returns the delay after a respondent call with the `result`, None if there's no respondent
+    /// or it `failed`. `failures` counts consecutive failed results. The `clock` is read by wall-clock schedules.
+    pub(crate) fn next_delay(
+        &self,
+        result: Option<&T>,
+        failed: bool,
+        failures: &mut u32,
+        clock: &dyn Clock,
+    ) -> u64 {
+        match self {
+            PollSchedule::Fixed(delay) => *delay,
+            PollSchedule::ExponentialBackoff {
+                delay,
+                max_delay,
+                multiplier,
+                is_failure,
+            } => {
+                if failed || result.is_some_and(is_failure) {
+                    *failures = failures.saturating_add(1);
+                } else {
+                    *failures = 0;
+                }
+                let factor = u64::from(*multiplier).saturating_pow(*failures);
+                delay.saturating_mul(factor).min(*max_delay)
+            }
+            PollSchedule::Jittered { delay, jitter } => {
+                let offset = rand::thread_rng().gen_range(0..=jitter.saturating_mul(2));
+                delay.saturating_add(offset).saturating_sub(*jitter)
+            }
+            PollSchedule::RespondentDriven {
+                delay,
+                fallback_delay,
+            } => result.map(delay).unwrap_or(*fallback_delay),
+            PollSchedule::WallClock(wall_clock) => {
+                wall_clock.delay_after_run(clock.system_time().into())
+            }
+        }
+    }
+
+    /// Returns the delay before the first call. Only wall-clock schedules wait.
+    pub(crate) fn start_delay(&self, clock: &dyn Clock) -> u64 {
+        match self {
+            PollSchedule::WallClock(wall_clock) => {
+                wall_clock.start_delay(clock.system_time().into())
+            }
+            _ => 0,
+        }
+    }
+}
+
+impl<T> Debug for PollSchedule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PollSchedule::Fixed(delay) => f.debug_tuple("Fixed").field(delay).finish(),
+            PollSchedule::ExponentialBackoff {
+                delay,
+                max_delay,
+                multiplier,
+                ..
+            } => f
+                .debug_struct("ExponentialBackoff")
+                .field("delay", delay)
+                .field("max_delay", max_delay)
+                .field("multiplier", multiplier)
+                .finish(),
+            PollSchedule::Jittered { delay, jitter } => f
+                .debug_struct("Jittered")
+                .field("delay", delay)
+                .field("jitter", jitter)
+                .finish(),
+            PollSchedule::RespondentDriven { fallback_delay, .. } => f
+                .debug_struct("RespondentDriven")
+                .field("fallback_delay", fallback_delay)
+                .finish(),
+            PollSchedule::WallClock(clock) => f.debug_tuple("WallClock").field(clock).finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::clock::SystemClock;
+
+#[test]
+fn backoff_grows_until_max_delay_and_resets_after_success() {
+    let schedule = PollSchedule::ExponentialBackoff {
+        delay: 100,
+        max_delay: 500,
+        multiplier: 2,
+        is_failure: Box::new(|ok: &bool| !ok),
+    };
+    let mut failures = 0;
+
+    let delays: Vec<_> = [false, false, false, false, true, false]
+        .iter()
+        .map(|ok| schedule.next_delay(Some(ok), false, &mut failures, &SystemClock))
+        .collect();
+
+    assert_eq!(delays, [200, 400, 500, 500, 100, 200]);
+}
+
+#[test]
+fn jitter_stays_in_range() {
+    let schedule = PollSchedule::<()>::Jittered {
+        delay: 100,
+        jitter: 10,
+    };
+
+    for _ in 0..100 {
+        let delay = schedule.next_delay(Some(&()), false, &mut 0, &SystemClock);
+        assert!((90..=110).contains(&delay), "{delay}");
+    }
+}
+
+#[test]
+fn backoff_counts_respondent_errors_as_failures() {
+    let schedule = PollSchedule::ExponentialBackoff {
+        delay: 100,
+        max_delay: 500,
+        multiplier: 2,
+        is_failure: Box::new(|_: &()| false),
+    };
+    let mut failures = 0;
+
+    assert_eq!(
+        schedule.next_delay(None, true, &mut failures, &SystemClock),
+        200
+    );
+    assert_eq!(
+        schedule.next_delay(None, true, &mut failures, &SystemClock),
+        400
+    );
+    assert_eq!(
+        schedule.next_delay(Some(&()), false, &mut failures, &SystemClock),
+        100
+    );
+}
@@ -0,0 +1,26 @@
+use std::time::SystemTime;
+
+/// Results of the respondent of a poll, see [crate::poll_manager::PollManager::poll_stats].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PollStats {
+    pub successes: u64,
+    pub failures: u64,
+    /// Errors since the last successful result.
+    pub consecutive_failures: u32,
+    pub last_success: Option<SystemTime>,
+    pub last_failure: Option<SystemTime>,
+}
+
+impl PollStats {
+    pub(crate) fn record_success(&mut self, now: SystemTime) {
+        self.successes += 1;
+        self.consecutive_failures = 0;
+        self.last_success = Some(now);
+    }
+
+    pub(crate) fn record_failure(&mut self, now: SystemTime) {
+        self.failures += 1;
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        self.last_failure = Some(now);
+    }
+}
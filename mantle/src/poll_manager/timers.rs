@@ -0,0 +1,137 @@
+use crate::clock::Clock;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+
+/// Due times of polls, ordered by the time. Read by the scheduler thread of a `PollManager`.
+#[derive(Debug)]
+pub(crate) struct Timers {
+    heap: Mutex<TimerHeap>,
+    changed: Condvar,
+    /// Notified whenever the scheduler is done with a due timer, for [Timers::wait_until_idle].
+    idle: Condvar,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug, Default)]
+struct TimerHeap {
+    timers: BinaryHeap<Reverse<Timer>>,
+    shut_down: bool,
+    /// The last timer returned by [Timers::next_due] may still be handled, until the scheduler asks for the next one.
+    dispatching: bool,
+}
+
+/// What to do with the poll when its timer is due.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum TimerKind {
+    /// Run the respondent.
+    Run,
+    /// Call back the debounced result.
+    Deliver,
+}
+
+/// A poll run or delivery due at `due`. Only the timer with the latest `seq` of the poll and kind
+/// is valid, so rescheduled polls don't need their old timers removed from the heap.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Timer {
+    due: Instant,
+    poll_id: u32,
+    seq: u64,
+    kind: TimerKind,
+}
+
+impl Timers {
+    /// Timers due by the `clock`. Waits for virtual clocks end when they're advanced.
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Arc<Self> {
+        let timers = Arc::new(Timers {
+            heap: Default::default(),
+            changed: Condvar::new(),
+            idle: Condvar::new(),
+            clock: Arc::clone(&clock),
+        });
+        let weak = Arc::downgrade(&timers);
+        clock.listen(Box::new(move || match weak.upgrade() {
+            Some(timers) => {
+                let _heap = timers.heap.lock().unwrap();
+                timers.changed.notify_one();
+                true
+            }
+            None => false,
+        }));
+        timers
+    }
+
+    pub(crate) fn schedule(&self, due: Instant, poll_id: u32, seq: u64, kind: TimerKind) {
+        let mut heap = self.heap.lock().unwrap();
+        let is_first = match heap.timers.peek() {
+            Some(Reverse(first)) => due < first.due,
+            None => true,
+        };
+        heap.timers.push(Reverse(Timer {
+            due,
+            poll_id,
+            seq,
+            kind,
+        }));
+        // The scheduler only needs to wait less if the new timer is the first one.
+        if is_first {
+            self.changed.notify_one();
+        }
+    }
+
+    /// Makes [Timers::next_due] return None.
+    pub(crate) fn shut_down(&self) {
+        self.heap.lock().unwrap().shut_down = true;
+        self.changed.notify_one();
+        self.idle.notify_all();
+    }
+
+    /// Returns true if no timer is due by the clock and the last due one has been handled.
+    pub(crate) fn is_idle(&self) -> bool {
+        self.heap_is_idle(&self.heap.lock().unwrap())
+    }
+
+    /// Blocks until [Timers::is_idle].
+    pub(crate) fn wait_until_idle(&self) {
+        let mut heap = self.heap.lock().unwrap();
+        while !self.heap_is_idle(&heap) {
+            heap = self.idle.wait(heap).unwrap();
+        }
+    }
+
+    fn heap_is_idle(&self, heap: &TimerHeap) -> bool {
+        if heap.shut_down {
+            return true;
+        }
+        let now = self.clock.now();
+        !heap.dispatching && !matches!(heap.timers.peek(), Some(Reverse(first)) if first.due <= now)
+    }
+
+    /// Blocks until the first timer is due and returns its poll id, seq and kind. Returns None after [Timers::shut_down].
+    pub(crate) fn next_due(&self) -> Option<(u32, u64, TimerKind)> {
+        let mut heap = self.heap.lock().unwrap();
+        heap.dispatching = false;
+        self.idle.notify_all();
+        loop {
+            if heap.shut_down {
+                return None;
+            }
+            let now = self.clock.now();
+            match heap.timers.peek() {
+                Some(Reverse(timer)) if timer.due <= now => {
+                    let Reverse(timer) = heap.timers.pop().unwrap();
+                    heap.dispatching = true;
+                    return Some((timer.poll_id, timer.seq, timer.kind));
+                }
+                Some(Reverse(timer)) => {
+                    heap = match self.clock.real_duration(timer.due - now) {
+                        Some(timeout) => self.changed.wait_timeout(heap, timeout).unwrap().0,
+                        None => self.changed.wait(heap).unwrap(),
+                    };
+                }
+                None => heap = self.changed.wait(heap).unwrap(),
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::db::{Bucket, Db, DbResult};
+use crate::poll_manager::{CronError, CronExpr};
+use chrono::{DateTime, NaiveTime, TimeZone, Utc};
+use log::warn;
+use std::fmt::{Debug, Formatter};
+use std::sync::Mutex;
+
+/// Bucket with the last run of every [WallClock] that persists its runs, by name.
+const WALL_CLOCK_RUNS_BUCKET: &str = "poll_manager/wall_clock_runs";
+
+type NextRun = Box<dyn Fn(DateTime<Utc>) -> Option<DateTime<Utc>> + Send + Sync>;
+
+/// Runs a poll at wall-clock times, see [crate::poll_manager::PollSchedule::WallClock].
+/// The delay is computed from the clock before every run, so runs don't drift like intervals do.
+///
+/// # Examples
+///
+/// ```
+/// use chrono::{Local, NaiveTime, Utc};
+/// use mantle_utilities::poll_manager::{PollConfig, PollSchedule, WallClock};
+///
+/// let refresh_announcements = PollConfig {
+///     schedule: Some(PollSchedule::WallClock(WallClock::daily_at(
+///         NaiveTime::from_hms_opt(3, 0, 0).unwrap(),
+///         Local,
+///     ))),
+///     respondent: Some(Box::new(|| "refreshed")),
+///     ..Default::default()
+/// };
+/// let sync_schedules = PollConfig {
+///     schedule: Some(PollSchedule::WallClock(WallClock::cron("0 * * * *", Utc).unwrap())),
+///     respondent: Some(Box::new(|| "synced")),
+///     ..Default::default()
+/// };
+/// ```
+pub struct WallClock {
+    next: NextRun,
+    /// The time of the scheduled run. A timer can end a bit early, so the run after it is found from this time.
+    target: Mutex<Option<DateTime<Utc>>>,
+    runs: Option<RunLog>,
+}
+
+/// Last runs persisted in the [Db].
+struct RunLog {
+    bucket: Bucket<String, i64>,
+    name: String,
+}
+
+impl WallClock {
+    /// Runs at the first time after the given one that `next` returns. None stops the runs.
+    pub fn new(
+        next: impl Fn(DateTime<Utc>) -> Option<DateTime<Utc>> + Send + Sync + 'static,
+    ) -> Self {
+        WallClock {
+            next: Box::new(next),
+            target: Mutex::new(None),
+            runs: None,
+        }
+    }
+
+    /// Runs at the times of the cron `expression` in the `tz`, see [CronExpr].
+    pub fn cron<Tz>(expression: &str, tz: Tz) -> Result<Self, CronError>
+    where
+        Tz: TimeZone + Send + Sync + 'static,
+    {
+        let cron: CronExpr = expression.parse()?;
+        Ok(WallClock::new(move |time| {
+            cron.next_after(&time.with_timezone(&tz))
+                .map(|next| next.with_timezone(&Utc))
+        }))
+    }
+
+    /// Runs every day at the `time` in the `tz`. Skips days when the time doesn't exist because of
+    /// a daylight saving change.
+    pub fn daily_at<Tz>(time: NaiveTime, tz: Tz) -> Self
+    where
+        Tz: TimeZone + Send + Sync + 'static,
+    {
+        WallClock::new(move |after| {
+            let mut date = after.with_timezone(&tz).naive_local().date();
+            for _ in 0..3 {
+                if let Some(next) = tz.from_local_datetime(&date.and_time(time)).earliest() {
+                    let next = next.with_timezone(&Utc);
+                    if next > after {
+                        return Some(next);
+                    }
+                }
+                date = date.succ_opt()?;
+            }
+            None
+        })
+    }
+
+    /// Persists the last run in the `db` under the `name`. If a run was missed while the app wasn't
+    /// running, the poll runs right away when it starts.
+    pub fn persist_runs(mut self, db: &Db, name: impl Into<String>) -> DbResult<Self> {
+        self.runs = Some(RunLog {
+            bucket: db.open_bucket(WALL_CLOCK_RUNS_BUCKET)?,
+            name: name.into(),
+        });
+        Ok(self)
+    }
+
+    /// Returns milliseconds until the first run. Zero if a persisted run was missed.
+    pub(crate) fn start_delay(&self, now: DateTime<Utc>) -> u64 {
+        let last_run = self.runs.as_ref().and_then(RunLog::last_run);
+        if let Some(last_run) = last_run {
+            if (self.next)(last_run).is_some_and(|missed| missed <= now) {
+                return 0;
+            }
+        }
+        self.delay_until_next(now, now)
+    }
+
+    /// Records the run and returns milliseconds until the next one.
+    pub(crate) fn delay_after_run(&self, now: DateTime<Utc>) -> u64 {
+        if let Some(runs) = self.runs.as_ref() {
+            runs.record(now);
+        }
+        let target = *self.target.lock().unwrap();
+        self.delay_until_next(target.map_or(now, |target| target.max(now)), now)
+    }
+
+    fn delay_until_next(&self, after: DateTime<Utc>, now: DateTime<Utc>) -> u64 {
+        let next = (self.next)(after);
+        *self.target.lock().unwrap() = next;
+        next.map_or(u64::MAX, |next| {
+            let delay = next.signed_duration_since(now);
+            // Rounded up, so the run isn't early.
+            let millis = delay
+                .num_microseconds()
+                .map_or(delay.num_milliseconds(), |micros| (micros + 999) / 1000);
+            millis.max(0) as u64
+        })
+    }
+}
+
+impl RunLog {
+    fn last_run(&self) -> Option<DateTime<Utc>> {
+        match self.bucket.get(&self.name) {
+            Ok(last_run) => last_run.and_then(|millis| Utc.timestamp_millis_opt(millis).single()),
+            Err(err) => {
+                warn!("Couldn't read the last run of '{}': {}", self.name, err);
+                None
+            }
+        }
+    }
+
+    fn record(&self, time: DateTime<Utc>) {
+        if let Err(err) = self.bucket.insert(&self.name, &time.timestamp_millis()) {
+            warn!("Couldn't persist the run of '{}': {}", self.name, err);
+        }
+    }
+}
+
+impl Debug for WallClock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WallClock")
+            .field("target", &self.target)
+            .field("runs", &self.runs.as_ref().map(|runs| &runs.name))
+            .finish()
+    }
+}
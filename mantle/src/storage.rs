@@ -1,9 +1,19 @@
+mod db_storage;
+mod encryption;
 mod interface;
 mod json_storage;
+mod scope;
+pub mod storage_ffi_wrapper;
+mod subscription;
+mod usage;
 
-pub use interface::{StorageDataValue, StorageDir, StorageInteract};
+pub use encryption::StorageEncryptionKey;
+pub use interface::{StorageDataValue, StorageDir, StorageInteract, StorageKey};
 pub use json_storage::{
-    Storage, StorageInteractError, StorageInteractResult, SELECTED_REGION_STORAGE_KEY,
-    STORAGE_APP_DIR, STORAGE_LOGGING_DIR, STORAGE_USER_DIR, STORAGE_USER_SESSION_KEY,
-    STORAGE_USER_USER_SESSION_KEY,
+    Storage, StorageInteractError, StorageInteractResult, SELECTED_REGION,
+    SELECTED_REGION_STORAGE_KEY, STORAGE_APP_DIR, STORAGE_LOGGING_DIR, STORAGE_USER_DIR,
+    STORAGE_USER_SESSION_KEY, STORAGE_USER_USER_SESSION_KEY,
 };
+pub use scope::{Scope, ScopeId, STORAGE_ACCOUNTS_DIR};
+pub use subscription::{StorageChange, SubscriptionId};
+pub use usage::StorageUsage;
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+use log::debug;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::db::{Bincode, Bucket, Db, DbResult, Json, ValueCodec};
+use crate::storage::encryption::{self, StorageEncryptionKey};
+use crate::storage::json_storage::{StorageInteractError, StorageInteractResult};
+
+/// Buckets of plaintext values are named with this prefix and the dir path, e.g. `storage/user`.
+const STORAGE_BUCKET_PREFIX: &str = "storage/";
+/// Buckets of encrypted values are named with this prefix and the dir path, e.g. `storage_encrypted/user`.
+const ENCRYPTED_BUCKET_PREFIX: &str = "storage_encrypted/";
+
+/// Values of the [crate::storage::StorageInteract] of [crate::storage::Storage], kept in its [Db]
+/// with one `Bucket<String, Value, Json>` per dir.
+///
+/// Values of dirs with an encryption key are encrypted with AES-GCM and kept in a `Bucket<String, Vec<u8>>`
+/// of the dir instead. Keys aren't encrypted. Plaintext values of such dirs are encrypted by the first read.
+#[derive(Debug, Clone)]
+pub(crate) struct DbStorage {
+    db: Db,
+}
+
+impl DbStorage {
+    pub(crate) fn new(db: Db) -> Self {
+        DbStorage { db }
+    }
+
+    /// Reads the values of the `dir`, decrypting them with the `key`.
+    /// Fails with [StorageInteractError::EncryptionKeyMissing] if the dir has encrypted values, but no key.
+    pub(crate) fn read_dir(
+        &self,
+        dir: &Path,
+        key: Option<&StorageEncryptionKey>,
+    ) -> StorageInteractResult<HashMap<String, Value>> {
+        let plaintext: HashMap<String, Value> =
+            match self.existing_bucket::<Value, Json>(&plain_id(dir))? {
+                Some(bucket) => bucket.iter()?.collect::<DbResult<_>>()?,
+                None => HashMap::new(),
+            };
+        let encrypted = self.existing_bucket::<Vec<u8>, Bincode>(&encrypted_id(dir))?;
+        let Some(key) = key else {
+            return match encrypted {
+                Some(bucket) if bucket.keys()?.next().is_some() => {
+                    Err(StorageInteractError::EncryptionKeyMissing)
+                }
+                _ => Ok(plaintext),
+            };
+        };
+
+        let mut values = HashMap::new();
+        if let Some(bucket) = encrypted {
+            for entry in bucket.iter()? {
+                let (name, bytes) = entry?;
+                values.insert(name, decrypt_value(key, &bytes)?);
+            }
+        }
+        if !plaintext.is_empty() {
+            debug!("Encrypting plaintext values of {:?}", dir);
+            let bucket: Bucket<String, Vec<u8>> = self.bucket(&encrypted_id(dir))?;
+            for (name, value) in plaintext {
+                bucket.insert(&name, &encrypt_value(key, &value)?)?;
+                values.insert(name, value);
+            }
+            // Removed after all values are encrypted, so an interrupted read is redone by the next one.
+            self.db.delete_bucket(plain_id(dir))?;
+        }
+        Ok(values)
+    }
+
+    /// Stores the `value` for the `name` in the `dir`, encrypted if there is a `key`.
+    /// `check_size` gets the bytes taken by the old and the new entry before anything is written.
+    pub(crate) fn insert_value(
+        &self,
+        dir: &Path,
+        key: Option<&StorageEncryptionKey>,
+        name: &str,
+        value: &Value,
+        check_size: impl FnOnce(u64, u64) -> StorageInteractResult<()>,
+    ) -> StorageInteractResult<()> {
+        let name = name.to_string();
+        match key {
+            Some(key) => {
+                let bucket: Bucket<String, Vec<u8>> = self.bucket(&encrypted_id(dir))?;
+                let bytes = encrypt_value(key, value)?;
+                let old_size = entry_size::<_, Bincode>(&name, bucket.get(&name)?.as_ref())?;
+                check_size(old_size, entry_size::<_, Bincode>(&name, Some(&bytes))?)?;
+                bucket.insert(&name, &bytes)?;
+            }
+            None => {
+                let bucket: Bucket<String, Value, Json> = self.bucket(&plain_id(dir))?;
+                let old_size = entry_size::<_, Json>(&name, bucket.get(&name)?.as_ref())?;
+                check_size(old_size, entry_size::<_, Json>(&name, Some(value))?)?;
+                bucket.insert(&name, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the value for the `name` in the `dir`. Returns the bytes the entry took.
+    pub(crate) fn remove_value(
+        &self,
+        dir: &Path,
+        key: Option<&StorageEncryptionKey>,
+        name: &str,
+    ) -> StorageInteractResult<u64> {
+        let name = name.to_string();
+        if key.is_some() {
+            if let Some(bucket) = self.existing_bucket::<Vec<u8>, Bincode>(&encrypted_id(dir))? {
+                return entry_size::<_, Bincode>(&name, bucket.remove(&name)?.as_ref());
+            }
+        } else if let Some(bucket) = self.existing_bucket::<Value, Json>(&plain_id(dir))? {
+            return entry_size::<_, Json>(&name, bucket.remove(&name)?.as_ref());
+        }
+        Ok(0)
+    }
+
+    /// Removes the values of the dirs for which `is_removed` returns true.
+    pub(crate) fn remove_dirs(
+        &self,
+        is_removed: impl Fn(&Path) -> bool,
+    ) -> StorageInteractResult<()> {
+        for (id, dir) in self.bucket_dirs()? {
+            if is_removed(&dir) {
+                self.db.delete_bucket(id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the bytes taken by the values of each dir.
+    pub(crate) fn dir_sizes(&self) -> StorageInteractResult<HashMap<PathBuf, u64>> {
+        let mut sizes = HashMap::new();
+        for (id, dir) in self.bucket_dirs()? {
+            let stats = self.db.bucket_stats(id)?;
+            *sizes.entry(dir).or_default() += stats.key_bytes + stats.value_bytes;
+        }
+        Ok(sizes)
+    }
+
+    /// Returns the bytes taken by the values of the `dir` without its subdirs.
+    pub(crate) fn dir_size(&self, dir: &Path) -> StorageInteractResult<u64> {
+        let mut size = 0;
+        for id in [plain_id(dir), encrypted_id(dir)] {
+            if self.db.bucket_ids()?.contains(&id) {
+                let stats = self.db.bucket_stats(id)?;
+                size += stats.key_bytes + stats.value_bytes;
+            }
+        }
+        Ok(size)
+    }
+
+    fn bucket<V, C>(&self, id: &str) -> StorageInteractResult<Bucket<String, V, C>>
+    where
+        V: Serialize + DeserializeOwned,
+        C: ValueCodec,
+    {
+        Ok(self.db.open_bucket_with_codec(id)?)
+    }
+
+    /// Opens the bucket only if it exists, so reads don't create empty buckets.
+    fn existing_bucket<V, C>(&self, id: &str) -> StorageInteractResult<Option<Bucket<String, V, C>>>
+    where
+        V: Serialize + DeserializeOwned,
+        C: ValueCodec,
+    {
+        if !self
+            .db
+            .bucket_ids()?
+            .iter()
+            .any(|bucket_id| bucket_id == id)
+        {
+            return Ok(None);
+        }
+        self.bucket(id).map(Some)
+    }
+
+    /// Returns ids of the storage buckets with their dirs.
+    fn bucket_dirs(&self) -> StorageInteractResult<Vec<(String, PathBuf)>> {
+        Ok(self
+            .db
+            .bucket_ids()?
+            .into_iter()
+            .filter_map(|id| {
+                let dir = id
+                    .strip_prefix(STORAGE_BUCKET_PREFIX)
+                    .or_else(|| id.strip_prefix(ENCRYPTED_BUCKET_PREFIX))?;
+                let dir = PathBuf::from(dir);
+                Some((id, dir))
+            })
+            .collect())
+    }
+}
+
+fn plain_id(dir: &Path) -> String {
+    bucket_id(STORAGE_BUCKET_PREFIX, dir)
+}
+
+fn encrypted_id(dir: &Path) -> String {
+    bucket_id(ENCRYPTED_BUCKET_PREFIX, dir)
+}
+
+/// Joins the components of the `dir` with `/`, so bucket ids are the same on every platform.
+fn bucket_id(prefix: &str, dir: &Path) -> String {
+    let components: Vec<_> = dir
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    format!("{prefix}{}", components.join("/"))
+}
+
+/// Returns the bytes taken by an entry of a bucket with the codec `C` in the db, the same way as [Db::bucket_stats].
+/// A missing entry takes none.
+fn entry_size<V: Serialize, C: ValueCodec>(
+    name: &String,
+    value: Option<&V>,
+) -> StorageInteractResult<u64> {
+    let Some(value) = value else {
+        return Ok(0);
+    };
+    Ok((Bincode::encode(name)?.len() + C::encode(value)?.len()) as u64)
+}
+
+fn encrypt_value(key: &StorageEncryptionKey, value: &Value) -> StorageInteractResult<Vec<u8>> {
+    encryption::encrypt(key, &serde_json::to_string(value)?)
+}
+
+fn decrypt_value(key: &StorageEncryptionKey, bytes: &[u8]) -> StorageInteractResult<Value> {
+    if !encryption::is_encrypted(bytes) {
+        return Err(Error::new(ErrorKind::InvalidData, "encrypted value is corrupted").into());
+    }
+    Ok(serde_json::from_str(&encryption::decrypt(key, bytes)?)?)
+}
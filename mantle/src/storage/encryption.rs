@@ -0,0 +1,35 @@
+use std::io::{Error, ErrorKind};
+
+use crate::storage::json_storage::StorageInteractResult;
+
+/// AES-256 key of an encrypted storage directory.
+pub type StorageEncryptionKey = [u8; 32];
+
+/// Marks an encrypted store file. Plaintext stores are JSON, so they never start with it.
+/// Encrypted store layout: magic bytes, nonce, AES-GCM ciphertext with the authentication tag.
+const ENCRYPTED_STORE_MAGIC: &[u8] = b"MTENC1";
+const NONCE_LEN: usize = 12;
+
+pub(crate) fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(ENCRYPTED_STORE_MAGIC)
+}
+
+pub(crate) fn encrypt(key: &StorageEncryptionKey, text: &str) -> StorageInteractResult<Vec<u8>> {
+    let encrypted = confenc::encrypt_with_key(key, text.as_bytes())?;
+    let mut bytes =
+        Vec::with_capacity(ENCRYPTED_STORE_MAGIC.len() + NONCE_LEN + encrypted.data.len());
+    bytes.extend_from_slice(ENCRYPTED_STORE_MAGIC);
+    bytes.extend_from_slice(&encrypted.nonce);
+    bytes.extend(encrypted.data);
+    Ok(bytes)
+}
+
+/// Decrypts a store written by [encrypt].
+pub(crate) fn decrypt(key: &StorageEncryptionKey, bytes: &[u8]) -> StorageInteractResult<String> {
+    let bytes = &bytes[ENCRYPTED_STORE_MAGIC.len()..];
+    if bytes.len() < NONCE_LEN {
+        return Err(Error::new(ErrorKind::InvalidData, "encrypted store is truncated").into());
+    }
+    let (nonce, data) = bytes.split_at(NONCE_LEN);
+    Ok(confenc::decrypt(key, nonce.try_into().unwrap(), data)?)
+}
@@ -1,9 +1,12 @@
+use std::fmt::{Debug, Formatter};
 use std::io::Result;
+use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
 
+use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use crate::storage::json_storage::StorageInteractResult;
+use crate::storage::json_storage::{merge_patch, StorageInteractError, StorageInteractResult};
 
 pub trait StorageInteract {
     /// Retrieve data contents and serde_json::Value which returns
@@ -43,6 +46,68 @@ pub trait StorageInteract {
         key: &str,
     ) -> StorageInteractResult<StorageDataValue>;
 
+    /// Retrieve the value for the key and deserialize it to `T`.
+    /// Returns None if there is no value. Fails with [StorageInteractError::TypeMismatch]
+    /// if the stored value can't be deserialized to `T`.
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use mantle_utilities::storage::{StorageInteract, StorageInteractResult, StorageDataValue};
+    /// # use std::path::Path;
+    /// # struct MockStorage;
+    /// # impl StorageInteract for MockStorage {
+    /// #     fn get_value(&self, path: impl AsRef<Path>, key: &str) -> StorageInteractResult<StorageDataValue> {
+    /// #         Ok(StorageDataValue::from(42))
+    /// #     }
+    /// #     fn set_value<T>(&self, path: impl AsRef<Path>, key: &str, value: T) -> StorageInteractResult<()> {
+    /// #         Ok(())
+    /// #     }
+    /// #     fn remove_value(&self, path: impl AsRef<Path>, key: &str) -> StorageInteractResult<()> {
+    /// #         Ok(())
+    /// #     }
+    /// #     fn init_device_storage(&self, dsn: &str) -> StorageInteractResult<()> {
+    /// #         Ok(())
+    /// #     }
+    /// #     fn clear_device_storage(&self, dsn: &str) -> StorageInteractResult<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// # let storage = MockStorage;
+    /// let value: Option<u32> = storage.get("path", "key")?;
+    /// assert_eq!(value, Some(42));
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn get<T>(&self, path: impl AsRef<Path>, key: &str) -> StorageInteractResult<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        match self.get_value(path, key)? {
+            StorageDataValue::Null => Ok(None),
+            value => serde_json::from_value(value).map(Some).map_err(|source| {
+                StorageInteractError::TypeMismatch {
+                    key: key.to_string(),
+                    source,
+                }
+            }),
+        }
+    }
+
+    /// Retrieve a value nested in the stored values. `key_path` is either a JSON Pointer
+    /// (`/profile/units`) or a dotted path (`profile.units`). The first segment is the key.
+    /// Returns [StorageDataValue::Null] if nothing is found at the path.
+    fn get_path(
+        &self,
+        path: impl AsRef<Path>,
+        key_path: &str,
+    ) -> StorageInteractResult<StorageDataValue> {
+        let (key, pointer) = split_key_path(key_path);
+        let value = self.get_value(path, &key)?;
+        Ok(value
+            .pointer(&pointer)
+            .cloned()
+            .unwrap_or(StorageDataValue::Null))
+    }
+
     /// Set data to the mutable content. You can pass in any type which can be
     /// serde::Serialize since it needs to be converted at some point to
     /// serde_json::Value to be stored.
@@ -59,6 +124,47 @@ pub trait StorageInteract {
     /// an error is returned. If the key does not exist it is a NoOp
     fn remove_value(&self, path: impl AsRef<Path>, key: &str) -> StorageInteractResult<()>;
 
+    /// Apply `patch` to the value for the key with JSON Merge Patch (RFC 7386) semantics:
+    /// objects are merged recursively, nulls remove fields and anything else replaces the value.
+    /// A null patch removes the key.
+    ///
+    /// The default implementation isn't atomic: it reads the value by [StorageInteract::get_value]
+    /// and writes the result by [StorageInteract::set_value] or [StorageInteract::remove_value].
+    fn merge_value<T>(
+        &self,
+        path: impl AsRef<Path>,
+        key: &str,
+        patch: T,
+    ) -> StorageInteractResult<()>
+    where
+        T: Serialize,
+    {
+        let path = path.as_ref();
+        let patch = serde_json::to_value(patch)?;
+        let mut value = self.get_value(path, key)?;
+        merge_patch(&mut value, patch);
+        if value.is_null() {
+            self.remove_value(path, key)
+        } else {
+            self.set_value(path, key, value)
+        }
+    }
+
+    /// Replace the value for the key with the result of `f` as a single atomic operation.
+    /// `f` gets [StorageDataValue::Null] if there is no value. No other storage operation
+    /// can run until `f` returns, so it must not use the storage itself.
+    ///
+    /// The default implementation isn't atomic: it reads the value by [StorageInteract::get_value]
+    /// and writes the result by [StorageInteract::set_value]. Implementations should override it.
+    fn update<F>(&self, path: impl AsRef<Path>, key: &str, f: F) -> StorageInteractResult<()>
+    where
+        F: FnOnce(StorageDataValue) -> StorageDataValue,
+    {
+        let path = path.as_ref();
+        let value = f(self.get_value(path, key)?);
+        self.set_value(path, key, value)
+    }
+
     /// Checks if storage directory exists for the given DSN and creates it if
     /// it does not exist.
     fn init_device_storage(&self, dsn: &str) -> StorageInteractResult<()>;
@@ -67,6 +173,23 @@ pub trait StorageInteract {
     fn clear_device_storage(&self, dsn: &str) -> StorageInteractResult<()>;
 }
 
+/// Splits a JSON Pointer or a dotted path into the storage key and a JSON Pointer into its value.
+fn split_key_path(key_path: &str) -> (String, String) {
+    let pointer = if key_path.starts_with('/') {
+        key_path.to_string()
+    } else {
+        key_path
+            .split('.')
+            .map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1")))
+            .collect()
+    };
+    let (key, rest) = match pointer[1..].split_once('/') {
+        Some((key, rest)) => (key, format!("/{rest}")),
+        None => (&pointer[1..], String::new()),
+    };
+    (key.replace("~1", "/").replace("~0", "~"), rest)
+}
+
 pub trait StorageDir {
     /// If the parent_dir exist and its been given
     /// by the consuming OS we can then make the
@@ -97,3 +220,75 @@ pub trait StorageDir {
 }
 
 pub type StorageDataValue = serde_json::Value;
+
+/// A key with a value type, so reads and writes of the value are checked by the compiler.
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let os_dir = std::env::temp_dir().join("storage_key_example");
+/// # std::fs::create_dir_all(&os_dir)?;
+/// use mantle_utilities::storage::{Storage, StorageKey, STORAGE_APP_DIR};
+///
+/// const LAUNCH_COUNT: StorageKey<u32> = StorageKey::new(STORAGE_APP_DIR, "launchCount");
+///
+/// let storage = Storage::new(&os_dir)?;
+/// LAUNCH_COUNT.set(&storage, &1)?;
+/// assert_eq!(LAUNCH_COUNT.get(&storage)?, Some(1));
+/// # drop(storage);
+/// # std::fs::remove_dir_all(&os_dir)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct StorageKey<T> {
+    /// Storage directory of the value.
+    pub dir: &'static str,
+    pub key: &'static str,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> StorageKey<T> {
+    pub const fn new(dir: &'static str, key: &'static str) -> Self {
+        StorageKey {
+            dir,
+            key,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> StorageKey<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Retrieve the value. See [StorageInteract::get].
+    pub fn get(&self, storage: &impl StorageInteract) -> StorageInteractResult<Option<T>> {
+        storage.get(self.dir, self.key)
+    }
+
+    /// Set the value. See [StorageInteract::set_value].
+    pub fn set(&self, storage: &impl StorageInteract, value: &T) -> StorageInteractResult<()> {
+        storage.set_value(self.dir, self.key, value)
+    }
+
+    /// Remove the value. See [StorageInteract::remove_value].
+    pub fn remove(&self, storage: &impl StorageInteract) -> StorageInteractResult<()> {
+        storage.remove_value(self.dir, self.key)
+    }
+}
+
+// Derives would require T to implement the traits.
+impl<T> Clone for StorageKey<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for StorageKey<T> {}
+
+impl<T> Debug for StorageKey<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StorageKey")
+            .field("dir", &self.dir)
+            .field("key", &self.key)
+            .finish()
+    }
+}
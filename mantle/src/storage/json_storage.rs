@@ -1,37 +1,49 @@
 use std::collections::HashMap;
 use std::ffi::OsString;
 use std::fs;
-use std::fs::{create_dir_all, read_dir, File, OpenOptions};
-use std::io::{Cursor, Error, ErrorKind, Read, Result, Write};
-use std::path::{Path, PathBuf};
+use std::fs::{create_dir_all, read_dir, File};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::{Component, Path, PathBuf};
 use std::sync::{Arc, Mutex, MutexGuard};
 
 use crate::db::sled_db::SledDb;
-use crate::db::{Db, DbError};
-use crate::storage::interface::{StorageDataValue, StorageDir, StorageInteract};
-
+use crate::db::{Bucket, Db, DbError, ImportMode};
+use crate::error::MantleResultError;
+use crate::storage::db_storage::DbStorage;
+use crate::storage::encryption::{self, StorageEncryptionKey};
+use crate::storage::interface::{StorageDataValue, StorageDir, StorageInteract, StorageKey};
+use crate::storage::scope::{Scope, ScopeId};
+use crate::storage::subscription::{StorageChange, SubscriptionId, Subscriptions};
+use crate::storage::usage::{self, DirSizes, StorageUsage};
+
+use confenc::error::CrydecError;
 use log::{debug, error, warn};
-use serde::Deserialize;
 use serde::Serialize;
 use serde_json::Value;
 
+mod migrations;
+
 pub const STORAGE_USER_DIR: &str = "user";
 pub const STORAGE_USER_SESSION_KEY: &str = "session";
 pub const STORAGE_USER_USER_SESSION_KEY: &str = "user_session";
 pub const STORAGE_APP_DIR: &str = "app";
 pub const SELECTED_REGION_STORAGE_KEY: &str = "countryRegionSelection";
+pub const SELECTED_REGION: StorageKey<String> =
+    StorageKey::new(STORAGE_APP_DIR, SELECTED_REGION_STORAGE_KEY);
 pub const STORAGE_LOGGING_DIR: &str = "logging";
 
 const STORAGE_HIDDEN_FILE_NAME: &str = ".store";
-const CRATE_WORKSPACE: &str = "cloudcore"; //env!("CARGO_PKG_NAME"); // TODO: Using pkg name breaks backward compatibility. Need to figure out migration method
+/// New content is written to a file with this suffix first and then renamed over the original.
+const TEMP_FILE_SUFFIX: &str = ".tmp";
+const CRATE_WORKSPACE: &str = "mantle-utilities";
+/// Workspace used before [CRATE_WORKSPACE]. It's moved by a migration.
+const LEGACY_CRATE_WORKSPACE: &str = "cloudcore";
 const DB_DIR: &str = "store";
+/// Child dirs whose stores have been encrypted, so their plaintext stores are rejected.
+const ENCRYPTED_DIRS_BUCKET: &str = "storage_encrypted_dirs";
 
 #[derive(Debug, Clone)]
 pub struct Storage {
-    /// Contains the path constructed with
-    /// the OS File directory path with our module path.
-    /// Returns a reference of the Path -> Slice [u8]
-    parent_path: PathBuf,
     /// Contains actual implementation. Uses Mutex to ensure atomic storage modifications.
     inner: Arc<Mutex<InnerStorage>>,
 }
@@ -44,6 +56,25 @@ pub enum StorageInteractError {
     Io(#[from] Error),
     #[error("a key needs to be provided to retrieve the storage")]
     EmptyKey,
+    #[error("value for '{key}' has unexpected type: {source}")]
+    TypeMismatch {
+        key: String,
+        source: serde_json::Error,
+    },
+    #[error("encryption error: {0}")]
+    Encryption(#[from] CrydecError),
+    #[error("the store is encrypted, but no encryption key is set for its directory")]
+    EncryptionKeyMissing,
+    #[error("the store of an encrypted directory isn't encrypted")]
+    PlaintextStore,
+    #[error("encryption key has {0} bytes, 32 are required")]
+    InvalidEncryptionKey(usize),
+    #[error("db error: {0}")]
+    Db(#[from] DbError),
+    #[error("writing {size} bytes to {dir:?} exceeds its quota of {quota} bytes")]
+    QuotaExceeded { dir: PathBuf, quota: u64, size: u64 },
+    #[error("the shared storage isn't initialized")]
+    NotInitialized,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -56,23 +87,60 @@ pub enum CreateStorageError {
 
 pub type StorageInteractResult<T> = std::result::Result<T, StorageInteractError>;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct StorageData {
-    /// Data will hold all the persisted objects in storage. Its the entry point to our storage.
-    /// ```
-    /// {
-    ///   "data"; {
-    ///     // ... storage.
-    ///   }
-    /// }
-    /// ```
-    data: HashMap<String, Value>,
+impl MantleResultError for StorageInteractError {
+    fn error_type(&self) -> String {
+        match self {
+            StorageInteractError::Serde(_) => "Serde",
+            StorageInteractError::Io(_) => "Io",
+            StorageInteractError::EmptyKey => "EmptyKey",
+            StorageInteractError::TypeMismatch { .. } => "TypeMismatch",
+            StorageInteractError::Encryption(_) => "Encryption",
+            StorageInteractError::EncryptionKeyMissing => "EncryptionKeyMissing",
+            StorageInteractError::PlaintextStore => "PlaintextStore",
+            StorageInteractError::InvalidEncryptionKey(_) => "InvalidEncryptionKey",
+            StorageInteractError::Db(_) => "Db",
+            StorageInteractError::QuotaExceeded { .. } => "QuotaExceeded",
+            StorageInteractError::NotInitialized => "NotInitialized",
+        }
+        .to_owned()
+    }
+
+    fn error_description(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl MantleResultError for CreateStorageError {
+    fn error_type(&self) -> String {
+        match self {
+            CreateStorageError::Db(_) => "Db",
+            CreateStorageError::Io(_) => "Io",
+        }
+        .to_owned()
+    }
+
+    fn error_description(&self) -> String {
+        self.to_string()
+    }
 }
 
 #[derive(Debug)]
 struct InnerStorage {
+    /// The OS file dir joined with [CRATE_WORKSPACE]. Only kept here, since a migration can move it.
     parent_path: PathBuf,
     db: Db,
+    /// Key-value data of the child dirs in the `db`.
+    db_storage: DbStorage,
+    /// Values of child dirs by normalized dir, so reads don't hit the db. Writes go through to the db.
+    cache: HashMap<PathBuf, HashMap<String, Value>>,
+    /// Keys of encrypted child dirs by normalized dir. Provided by the platform, never written to the disk.
+    encryption_keys: HashMap<PathBuf, StorageEncryptionKey>,
+    /// Max bytes of child dirs by normalized dir, including their subdirs.
+    /// Checked against the `dir_sizes` when their values or `.store` files are written.
+    quotas: HashMap<PathBuf, u64>,
+    dir_sizes: DirSizes,
+    /// Changes of subscribed keys are delivered by [Storage::with_storage] after the lock is released.
+    subscriptions: Subscriptions,
 }
 
 impl Storage {
@@ -87,7 +155,6 @@ impl Storage {
         let storage = InnerStorage::new(os_dir)?;
 
         Ok(Storage {
-            parent_path: storage.parent_path.clone(),
             inner: Arc::new(Mutex::new(storage)),
         })
     }
@@ -97,13 +164,110 @@ impl Storage {
         remove_app_folder: bool,
         remove_selected_region: bool,
     ) -> Result<()> {
-        self.lock_storage()
-            .wipe_storage(remove_app_folder, remove_selected_region)
+        self.with_storage(|storage| storage.wipe_storage(remove_app_folder, remove_selected_region))
     }
 
-    /// Get a reference to the parent path which is the OS file dir.
-    pub fn parent_path(&self) -> &Path {
-        self.parent_path.as_path()
+    /// Removes the data of one user account with all its devices, e.g. when they log out.
+    /// Like [Storage::wipe_storage], but other accounts and the app and user dirs are kept.
+    pub fn wipe_account_storage(&self, account: &str) -> Result<()> {
+        self.wipe_scope(&ScopeId::Account(account.to_string()))
+            .map_err(|err| match err {
+                StorageInteractError::Io(err) => err,
+                err => Error::other(err),
+            })
+    }
+
+    /// Calls the `callback` when the value for the `key` in the `dir` changes,
+    /// including removals by [Storage::wipe_storage] and [Storage::wipe_scope].
+    /// The callback is called on the thread that made the change, after the storage is unlocked,
+    /// so it can use the storage.
+    pub fn subscribe<F>(&self, dir: impl AsRef<Path>, key: &str, callback: F) -> SubscriptionId
+    where
+        F: Fn(&StorageChange) + Send + Sync + 'static,
+    {
+        self.lock_storage().subscriptions.subscribe(
+            dir.as_ref().to_path_buf(),
+            key.to_string(),
+            Arc::new(callback),
+        )
+    }
+
+    /// Removes the subscription. Returns false if it was already removed.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.lock_storage().subscriptions.unsubscribe(id)
+    }
+
+    /// Returns a handle to the data of the scope, creating its dir if needed.
+    pub fn scope(&self, id: ScopeId) -> StorageInteractResult<Scope> {
+        self.lock_storage().init_child_dir(id.path())?;
+        Ok(Scope::new(self.clone(), id))
+    }
+
+    /// Removes the data of the scope and its child scopes, e.g. all data of one account when they log out.
+    /// Other scopes are kept. Removed scopes have to be opened again with [Storage::scope] before use.
+    ///
+    /// Wiping [ScopeId::App] keeps the accounts, the storage version and the selected region.
+    pub fn wipe_scope(&self, id: &ScopeId) -> StorageInteractResult<()> {
+        self.with_storage(|storage| match id {
+            ScopeId::App => storage.wipe_app_scope(),
+            id => storage.clear_child_dir(id.path()),
+        })
+    }
+
+    /// Encrypts the values and the `.store` file of the `child_dir` at rest with AES-GCM using the `key`,
+    /// which the platform keeps in its secure storage (Android Keystore, iOS Keychain).
+    /// The key isn't persisted, so it has to be set every time the storage is created, before the dir is used.
+    /// Existing plaintext values are encrypted the first time they're read, `.store` files the next time they're written.
+    /// After that, the dir is marked as encrypted in the db and plaintext stores found in it are rejected.
+    pub fn set_encryption_key(&self, child_dir: impl AsRef<Path>, key: StorageEncryptionKey) {
+        self.with_storage(|storage| storage.set_encryption_key(child_dir, key))
+    }
+
+    /// Limits the bytes taken by the values and files of the `child_dir` and its subdirs, e.g. to cap a device cache.
+    /// Writes that would exceed it fail with [StorageInteractError::QuotaExceeded]. None removes the quota.
+    /// Quotas aren't persisted, so they have to be set every time the storage is created.
+    pub fn set_quota(&self, child_dir: impl AsRef<Path>, quota: Option<u64>) {
+        let child_dir = normalize_dir(child_dir.as_ref());
+        let mut storage = self.lock_storage();
+        match quota {
+            Some(quota) => storage.quotas.insert(child_dir, quota),
+            None => storage.quotas.remove(&child_dir),
+        };
+    }
+
+    /// Returns the bytes taken by the files and the values of each child dir, and by the db.
+    pub fn usage(&self) -> StorageInteractResult<StorageUsage> {
+        let db = self.db();
+        let mut usage = StorageUsage {
+            db: db.size_on_disk()?,
+            ..Default::default()
+        };
+        // Nothing is written while the dirs are measured.
+        let storage = self.lock_storage();
+        for entry in read_dir(storage.parent_path())? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                let size = usage::dir_size(&entry.path())?;
+                usage.dirs.insert(PathBuf::from(entry.file_name()), size);
+            }
+        }
+        for (dir, size) in storage.db_storage.dir_sizes()? {
+            if let Some(child_dir) = dir.components().next() {
+                *usage.values.entry(PathBuf::from(&child_dir)).or_default() += size;
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Returns the parent path, which is the OS file dir joined with the storage workspace.
+    pub fn parent_path(&self) -> PathBuf {
+        self.lock_storage().parent_path.clone()
+    }
+
+    /// Restores the [Storage::db] from the archive file at `path`, see [Db::import_from_file].
+    /// Cached values are dropped and subscribers are notified about the values the import changed.
+    pub fn import_db(&self, path: impl AsRef<Path>, mode: ImportMode) -> StorageInteractResult<()> {
+        self.with_storage(|storage| storage.import_db(path.as_ref(), mode))
     }
 
     /// Returns a cloned db [SledDb].
@@ -111,8 +275,21 @@ impl Storage {
         self.lock_storage().db.clone()
     }
 
+    /// Locks the storage even if a panic poisoned the lock, e.g. in an [StorageInteract::update] closure.
+    /// The cache is only changed after the db, so it stays consistent with it.
     fn lock_storage(&self) -> MutexGuard<InnerStorage> {
-        self.inner.lock().unwrap()
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Runs `f` on the locked storage and then notifies subscribers about the changes it made.
+    fn with_storage<R>(&self, f: impl FnOnce(&mut InnerStorage) -> R) -> R {
+        let (result, notifications) = {
+            let mut storage = self.lock_storage();
+            let result = f(&mut storage);
+            (result, storage.subscriptions.take_pending())
+        };
+        notifications.deliver();
+        result
     }
 }
 
@@ -128,30 +305,42 @@ impl InnerStorage {
 
         let mut parent_path = os_dir.to_path_buf();
         parent_path.push(CRATE_WORKSPACE);
+        let legacy_path = os_dir.join(LEGACY_CRATE_WORKSPACE);
+        // The legacy workspace is used until the migration moves it.
+        if !parent_path.try_exists()? && legacy_path.try_exists()? {
+            parent_path = legacy_path;
+        }
         create_dir_all(&parent_path)?;
 
         let mut db_path = os_dir.to_path_buf();
         db_path.push(DB_DIR);
         create_dir_all(&db_path)?;
 
+        let db = Db::new(Box::new(SledDb::open(db_path)?));
         let mut storage = InnerStorage {
             parent_path,
-            db: Db::new(Box::new(SledDb::open(db_path)?)),
+            db: db.clone(),
+            db_storage: DbStorage::new(db),
+            cache: HashMap::new(),
+            encryption_keys: HashMap::new(),
+            quotas: HashMap::new(),
+            dir_sizes: DirSizes::default(),
+            subscriptions: Subscriptions::default(),
         };
         debug!("Parent Path: {:?}", storage.parent_path.as_path());
 
-        if !storage.parent_path.join(STORAGE_USER_DIR).try_exists()? {
-            match storage.make_dir_for_child(STORAGE_USER_DIR) {
-                Ok(_) => debug!("storage made for user data"),
-                Err(err) => error!("Could not create user data storage: {}", err),
-            }
+        match storage.init_child_dir(STORAGE_USER_DIR) {
+            Ok(_) => debug!("storage made for user data"),
+            Err(err) => error!("Could not create user data storage: {}", err),
         }
 
-        if !storage.parent_path.join(STORAGE_APP_DIR).try_exists()? {
-            match storage.make_dir_for_child(STORAGE_APP_DIR) {
-                Ok(_) => debug!("storage made for app data"),
-                Err(err) => error!("Could not create app data storage: {}", err),
-            }
+        match storage.init_child_dir(STORAGE_APP_DIR) {
+            Ok(_) => debug!("storage made for app data"),
+            Err(err) => error!("Could not create app data storage: {}", err),
+        }
+
+        if let Err(err) = migrations::run(&mut storage) {
+            error!("Could not migrate storage: {}", err);
         }
 
         // Keep this for now so it is deleted for existing users
@@ -159,17 +348,36 @@ impl InnerStorage {
             let _ = storage.remove_dir_for_child(STORAGE_LOGGING_DIR);
         }
 
+        if let Err(err) = storage.measure_dirs() {
+            error!("Could not measure storage dirs: {}", err);
+        }
+
         Ok(storage)
     }
 
+    /// Measures the files and values of all dirs. Writes keep the sizes up to date afterwards.
+    fn measure_dirs(&mut self) -> StorageInteractResult<()> {
+        self.dir_sizes = DirSizes::measure(&self.parent_path, self.db_storage.dir_sizes()?)?;
+        Ok(())
+    }
+
     fn wipe_storage(
         &mut self,
         remove_app_folder: bool,
         remove_selected_region: bool,
     ) -> Result<()> {
-        // TODO: Move country region selection to 'app' folder
-        let country_region_selection =
-            self.get_value(STORAGE_USER_DIR, SELECTED_REGION_STORAGE_KEY);
+        self.track_dir_changes(Path::new(""), |storage| {
+            storage.wipe_storage_dirs(remove_app_folder, remove_selected_region)
+        })
+    }
+
+    fn wipe_storage_dirs(
+        &mut self,
+        remove_app_folder: bool,
+        remove_selected_region: bool,
+    ) -> Result<()> {
+        let country_region_selection = self.get_value(STORAGE_APP_DIR, SELECTED_REGION_STORAGE_KEY);
+        let version = self.get_value(STORAGE_APP_DIR, migrations::STORAGE_VERSION_KEY);
 
         let child_paths = read_dir(self.parent_path())?.filter_map(|path| match path {
             Ok(entry) => Some(entry),
@@ -179,6 +387,7 @@ impl InnerStorage {
             }
         });
 
+        self.cache.clear();
         for entry in child_paths {
             let file_name = entry.file_name();
             let path = entry.path();
@@ -192,10 +401,54 @@ impl InnerStorage {
             }
         }
 
-        if let (Ok(StorageDataValue::String(string)), false) =
-            (country_region_selection, remove_selected_region)
+        let result =
+            self.unmark_removed_dirs(|dir| !dir.starts_with(STORAGE_APP_DIR) || remove_app_folder);
+        if let Err(err) = result {
+            error!("Error removing encryption marks: {err}");
+        }
+
+        let result = self
+            .db_storage
+            .remove_dirs(|dir| !dir.starts_with(STORAGE_APP_DIR) || remove_app_folder);
+        if let Err(err) = result {
+            error!("Error wiping db storage: {err}");
+        }
+        // Files directly inside the workspace are kept.
+        self.dir_sizes.remove_dirs(|dir| {
+            dir.parent().is_some() && (!dir.starts_with(STORAGE_APP_DIR) || remove_app_folder)
+        });
+
+        // The version is kept even without the app dir, so migrations don't import `.store` files made later.
+        if let (Ok(version), true) = (version, remove_app_folder) {
+            let app_dir = Path::new(STORAGE_APP_DIR);
+            let mut new_size = 0;
+            let result = self.db_storage.insert_value(
+                app_dir,
+                self.encryption_key(app_dir),
+                migrations::STORAGE_VERSION_KEY,
+                &version,
+                |_, size| {
+                    new_size = size;
+                    Ok(())
+                },
+            );
+            match result {
+                Ok(()) => self.dir_sizes.resize_value(app_dir.into(), 0, new_size),
+                Err(err) => warn!("Couldn't keep the storage version on wipe: {err}"),
+            }
+        }
+
+        if remove_selected_region {
+            if !remove_app_folder {
+                let result = self.remove_value(STORAGE_APP_DIR, SELECTED_REGION_STORAGE_KEY);
+                if let Err(err) = result {
+                    warn!("Couldn't remove selected region on wipe: {err}");
+                }
+            }
+        } else if let (Ok(StorageDataValue::String(string)), true) =
+            (country_region_selection, remove_app_folder)
         {
-            let result = self.set_value(STORAGE_USER_DIR, SELECTED_REGION_STORAGE_KEY, string);
+            let result = self.set_value(STORAGE_APP_DIR, SELECTED_REGION_STORAGE_KEY, string);
             if let Err(err) = result {
                 warn!("Couldn't set selected region after wipe: {err}");
             }
@@ -204,6 +457,82 @@ impl InnerStorage {
         Ok(())
     }
 
+    /// Clears the app dir except for the values that aren't app data: the storage version,
+    /// so migrations don't run again, and the selected region, which [Storage::wipe_storage] also keeps.
+    fn wipe_app_scope(&mut self) -> StorageInteractResult<()> {
+        let app_dir = Path::new(STORAGE_APP_DIR);
+        self.track_dir_changes(app_dir, |storage| {
+            let kept_values = [migrations::STORAGE_VERSION_KEY, SELECTED_REGION_STORAGE_KEY]
+                .map(|key| storage.get_value(app_dir, key).map(|value| (key, value)))
+                .into_iter()
+                .collect::<StorageInteractResult<Vec<_>>>()?;
+            storage.clear_child_dir(app_dir)?;
+            storage.init_child_dir(app_dir)?;
+            for (key, value) in kept_values {
+                if !value.is_null() {
+                    storage.set_value(app_dir, key, value)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn import_db(&mut self, path: &Path, mode: ImportMode) -> StorageInteractResult<()> {
+        self.track_dir_changes(Path::new(""), |storage| {
+            let result = storage.db.import_from_file(path, mode);
+            // A failed import can have written some of the archive.
+            storage.cache.clear();
+            let value_sizes = storage.db_storage.dir_sizes()?;
+            storage.dir_sizes.replace_values(value_sizes);
+            Ok(result?)
+        })
+    }
+
+    fn set_encryption_key(&mut self, child_dir: impl AsRef<Path>, key: StorageEncryptionKey) {
+        let child_dir = child_dir.as_ref();
+        // The next read encrypts plaintext values.
+        self.invalidate_cache(child_dir);
+        self.encryption_keys.insert(normalize_dir(child_dir), key);
+        // Failed migrations are retried and stores that needed the key are imported.
+        if let Err(err) = migrations::run(self) {
+            warn!("Could not migrate storage: {}", err);
+        }
+        if let Err(err) = migrations::import_pending_stores(self) {
+            warn!("Could not import encrypted stores: {}", err);
+        }
+    }
+
+    fn encryption_key(&self, child_dir: &Path) -> Option<&StorageEncryptionKey> {
+        self.encryption_keys.get(&normalize_dir(child_dir))
+    }
+
+    fn encrypted_dirs(&self) -> StorageInteractResult<Bucket<String, bool>> {
+        Ok(self.db.open_bucket(ENCRYPTED_DIRS_BUCKET)?)
+    }
+
+    /// Returns true if the store of the `child_dir` has been encrypted, so it must not be plaintext.
+    fn is_marked_encrypted(&self, child_dir: &Path) -> StorageInteractResult<bool> {
+        let marked = self.encrypted_dirs()?.get(&dir_key(child_dir))?;
+        Ok(marked.unwrap_or_default())
+    }
+
+    fn mark_encrypted(&self, child_dir: &Path) -> StorageInteractResult<()> {
+        self.encrypted_dirs()?.insert(&dir_key(child_dir), &true)?;
+        Ok(())
+    }
+
+    /// Drops the encryption marks of the removed dirs, so new stores in their place start unencrypted.
+    fn unmark_removed_dirs(&self, is_removed: impl Fn(&Path) -> bool) -> StorageInteractResult<()> {
+        let encrypted_dirs = self.encrypted_dirs()?;
+        for dir in encrypted_dirs.keys()? {
+            let dir = dir?;
+            if is_removed(Path::new(&dir)) {
+                encrypted_dirs.remove(&dir)?;
+            }
+        }
+        Ok(())
+    }
+
     fn make_dir_for_child(&mut self, child_dir: impl AsRef<Path>) -> Result<PathBuf> {
         let mut full_path = self.parent_path().to_path_buf();
         full_path.push(&child_dir);
@@ -216,9 +545,58 @@ impl InnerStorage {
     }
 
     fn remove_dir_for_child(&mut self, child_dir: impl AsRef<Path>) -> Result<()> {
-        let mut full_path = self.parent_path().to_path_buf();
-        full_path.push(child_dir);
-        fs::remove_dir_all(full_path)
+        let child_dir = child_dir.as_ref();
+        self.track_dir_changes(child_dir, |storage| {
+            storage.invalidate_cache(child_dir);
+            fs::remove_dir_all(storage.parent_path().join(child_dir))?;
+            storage.remove_db_values(child_dir).map_err(Error::other)
+        })
+    }
+
+    /// Runs `op`, which changes the `dir` as a whole, and records changes of subscribed keys in it
+    /// by comparing their values before and after. Changes recorded by `op` itself are replaced.
+    fn track_dir_changes<R>(&mut self, dir: &Path, op: impl FnOnce(&mut Self) -> R) -> R {
+        let keys = self.subscriptions.keys_in(dir);
+        if keys.is_empty() {
+            return op(self);
+        }
+
+        let old_values: Vec<_> = keys
+            .iter()
+            .map(|(dir, key)| self.get_value(dir, key).unwrap_or(Value::Null))
+            .collect();
+        let pending_count = self.subscriptions.pending_count();
+        let result = op(self);
+        self.subscriptions.discard_pending(pending_count);
+        for ((dir, key), old_value) in keys.into_iter().zip(old_values) {
+            // Removed dirs can't be read.
+            let new_value = self.get_value(&dir, &key).unwrap_or(Value::Null);
+            if new_value != old_value {
+                self.record_change(&dir, &key, old_value, new_value);
+            }
+        }
+        result
+    }
+
+    /// Removes the values of the `child_dir` and its subdirs and their encryption marks.
+    fn remove_db_values(&mut self, child_dir: &Path) -> StorageInteractResult<()> {
+        let child_dir = normalize_dir(child_dir);
+        self.invalidate_cache(&child_dir);
+        self.db_storage
+            .remove_dirs(|dir| dir.starts_with(&child_dir))?;
+        // The files of the dir are gone too, if it was removed from the disk.
+        self.dir_sizes
+            .remove_dirs(|dir| dir.starts_with(&child_dir));
+        self.unmark_removed_dirs(|dir| dir.starts_with(&child_dir))
+    }
+
+    fn record_change(&mut self, dir: &Path, key: &str, old_value: Value, new_value: Value) {
+        self.subscriptions.record(StorageChange {
+            dir: dir.to_path_buf(),
+            key: key.to_string(),
+            old_value,
+            new_value,
+        });
     }
 
     fn touch_file_for_child(
@@ -226,18 +604,127 @@ impl InnerStorage {
         child_dir: impl AsRef<Path>,
         bytes: Option<&str>,
     ) -> Result<()> {
-        let mut full_path = self.parent_path().to_path_buf();
-        full_path.push(child_dir);
-        full_path.push(STORAGE_HIDDEN_FILE_NAME);
+        self.write_store(child_dir.as_ref(), bytes.unwrap_or(r#"{"data":{}}"#))
+            .map_err(|err| match err {
+                StorageInteractError::Io(err) => err,
+                err => Error::other(err),
+            })
+    }
 
-        self.write_bytes_to_disk(&full_path, bytes.unwrap_or(r#"{"data":{}}"#).as_bytes())
+    /// Encodes and writes the store `content` of the `child_dir` if it fits into the quotas.
+    /// The file is replaced atomically, so it's never left half-written or missing.
+    fn write_store(&mut self, child_dir: &Path, content: &str) -> StorageInteractResult<()> {
+        let full_path = self.store_path(child_dir);
+        let bytes = self.encode_store(child_dir, content)?;
+        let old_size = usage::file_size(&full_path)?;
+        let new_size = bytes.len() as u64;
+        self.check_quotas(child_dir, old_size, new_size)?;
+        create_dir_all(self.parent_path().join(child_dir))?;
+        replace_file(&full_path, &bytes)?;
+        self.dir_sizes
+            .resize_file(normalize_dir(child_dir), old_size, new_size);
+        if self.encryption_key(child_dir).is_some() {
+            self.mark_encrypted(child_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Fails if replacing `old_size` bytes with `new_size` bytes in the `child_dir`
+    /// exceeds the quota of the dir or its parents.
+    fn check_quotas(
+        &self,
+        child_dir: &Path,
+        old_size: u64,
+        new_size: u64,
+    ) -> StorageInteractResult<()> {
+        let child_dir = normalize_dir(child_dir);
+        let quotas = self
+            .quotas
+            .iter()
+            .filter(|(dir, _)| child_dir.starts_with(dir))
+            .collect::<Vec<_>>();
+        if quotas.is_empty() {
+            return Ok(());
+        }
+
+        for (dir, &quota) in quotas {
+            let size = self.dir_sizes.size(dir).saturating_sub(old_size) + new_size;
+            if size > quota {
+                return Err(StorageInteractError::QuotaExceeded {
+                    dir: dir.clone(),
+                    quota,
+                    size,
+                });
+            }
+        }
+        Ok(())
     }
 
     fn stream_buffer_from_child(&mut self, child_dir: impl AsRef<Path>) -> Result<String> {
-        let mut full_path = self.parent_path().to_path_buf();
-        full_path.push(child_dir);
-        full_path.push(STORAGE_HIDDEN_FILE_NAME);
-        self.read_from_disk_to_string(full_path)
+        let child_dir = child_dir.as_ref();
+        let full_path = self.store_path(child_dir);
+        self.read_store(child_dir, &full_path)
+            .map_err(|err| match err {
+                StorageInteractError::Io(err) => err,
+                err => Error::new(ErrorKind::InvalidData, err),
+            })
+    }
+
+    /// Encrypts the store content if the `child_dir` has an encryption key.
+    fn encode_store(&self, child_dir: &Path, content: &str) -> StorageInteractResult<Vec<u8>> {
+        match self.encryption_key(child_dir) {
+            Some(key) => encryption::encrypt(key, content),
+            None => Ok(content.as_bytes().to_vec()),
+        }
+    }
+
+    /// Reads the store file at `path` of the `child_dir`, decrypting it if needed.
+    /// Fails with [StorageInteractError::PlaintextStore] for a plaintext store of a dir marked as encrypted.
+    fn read_store(&self, child_dir: &Path, path: &Path) -> StorageInteractResult<String> {
+        let bytes = fs::read(path)?;
+        if !encryption::is_encrypted(&bytes) {
+            if self.is_marked_encrypted(child_dir)? {
+                return Err(StorageInteractError::PlaintextStore);
+            }
+            let content =
+                String::from_utf8(bytes).map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+            return Ok(content);
+        }
+        match self.encryption_key(child_dir) {
+            Some(key) => encryption::decrypt(key, &bytes),
+            None => Err(StorageInteractError::EncryptionKeyMissing),
+        }
+    }
+
+    /// Returns the cached values of the `child_dir`, reading them from the db on a cache miss.
+    /// Fails if the dir doesn't exist.
+    fn cached_values(
+        &mut self,
+        child_dir: &Path,
+    ) -> StorageInteractResult<&mut HashMap<String, Value>> {
+        let cache_key = normalize_dir(child_dir);
+        if !self.cache.contains_key(&cache_key) {
+            let full_path = self.parent_path().join(&cache_key);
+            if !full_path.try_exists()? {
+                let message = format!("storage dir {} doesn't exist", full_path.display());
+                return Err(Error::new(ErrorKind::NotFound, message).into());
+            }
+            let key = self.encryption_key(&cache_key);
+            let values = self.db_storage.read_dir(&cache_key, key)?;
+            if key.is_some() {
+                // Plaintext values were encrypted by the read, which changes their size.
+                let size = self.db_storage.dir_size(&cache_key)?;
+                self.dir_sizes.set_values(cache_key.clone(), size);
+            }
+            self.cache.insert(cache_key.clone(), values);
+        }
+        Ok(self.cache.get_mut(&cache_key).unwrap())
+    }
+
+    /// Drops cached values of the `child_dir` and its subdirectories.
+    fn invalidate_cache(&mut self, child_dir: &Path) {
+        let child_dir = normalize_dir(child_dir);
+        self.cache.retain(|path, _| !path.starts_with(&child_dir));
     }
 
     fn get_value(
@@ -249,13 +736,8 @@ impl InnerStorage {
             return Err(StorageInteractError::EmptyKey);
         }
 
-        let path = path.as_ref();
-        let io_buffer = self.stream_buffer_from_child(path)?;
-        let mut full_path = self.parent_path().to_path_buf();
-        full_path.push(path);
-
-        let storage: StorageData = serde_json::from_str(&io_buffer)?;
-        let value = storage.data.get(key).unwrap_or(&Value::Null).clone();
+        let values = self.cached_values(path.as_ref())?;
+        let value = values.get(key).unwrap_or(&Value::Null).clone();
 
         Ok(value)
     }
@@ -269,58 +751,125 @@ impl InnerStorage {
     where
         T: Serialize,
     {
+        let value = serde_json::to_value(&value)?;
+        self.modify_value(path, key, |_| Some(value))
+    }
+
+    fn merge_value<T>(
+        &mut self,
+        path: impl AsRef<Path>,
+        key: &str,
+        patch: T,
+    ) -> StorageInteractResult<()>
+    where
+        T: Serialize,
+    {
+        let patch = serde_json::to_value(&patch)?;
+        self.modify_value(path, key, |value| {
+            let mut value = value.unwrap_or(Value::Null);
+            merge_patch(&mut value, patch);
+            Some(value).filter(|value| !value.is_null())
+        })
+    }
+
+    fn update<F>(&mut self, path: impl AsRef<Path>, key: &str, f: F) -> StorageInteractResult<()>
+    where
+        F: FnOnce(StorageDataValue) -> StorageDataValue,
+    {
+        self.modify_value(path, key, |value| Some(f(value.unwrap_or(Value::Null))))
+    }
+
+    /// Replaces the value for the key with the result of `f`, creating the `path` dir if needed.
+    /// None removes the key.
+    fn modify_value(
+        &mut self,
+        path: impl AsRef<Path>,
+        key: &str,
+        f: impl FnOnce(Option<Value>) -> Option<Value>,
+    ) -> StorageInteractResult<()> {
         let path = path.as_ref();
+        if !self.cache.contains_key(&normalize_dir(path)) {
+            self.init_child_dir(path)?;
+        }
 
-        let mut full_path = self.parent_path().to_path_buf();
-        full_path.push(path);
-        if !full_path.try_exists()? {
-            self.make_dir_for_child(path)?;
+        // Values are only cloned for subscribed keys.
+        let subscribed = self.subscriptions.is_subscribed(path, key);
+        let old_value = self.cached_values(path)?.get(key).cloned();
+        let change_old_value = subscribed.then(|| old_value.clone().unwrap_or(Value::Null));
+        let new_value = f(old_value);
+        let change_new_value = subscribed.then(|| new_value.clone().unwrap_or(Value::Null));
+        match new_value {
+            Some(value) => {
+                self.write_value(path, key, &value)?;
+                self.cached_values(path)?.insert(key.to_string(), value);
+            }
+            None => {
+                debug!("Removing '{}' from storage", key);
+                self.remove_db_value(path, key)?;
+                self.cached_values(path)?.remove(key);
+            }
+        }
+        if let (Some(old_value), Some(new_value)) = (change_old_value, change_new_value) {
+            self.record_change(path, key, old_value, new_value);
         }
+        Ok(())
+    }
 
-        let io_buffer = self.stream_buffer_from_child(path)?;
-        let mut cd: StorageData = serde_json::from_str(&io_buffer)?;
-        let value = serde_json::to_value(&value)?;
-        cd.data.insert(key.to_string(), value);
-        let out_buffer = serde_json::to_string(&cd)?;
-        self.touch_file_for_child(path, Some(&out_buffer))?;
+    /// Writes the value to the db if it fits into the quotas.
+    fn write_value(&mut self, path: &Path, key: &str, value: &Value) -> StorageInteractResult<()> {
+        let mut sizes = (0, 0);
+        self.db_storage.insert_value(
+            path,
+            self.encryption_key(path),
+            key,
+            value,
+            |old_size, new_size| {
+                sizes = (old_size, new_size);
+                self.check_quotas(path, old_size, new_size)
+            },
+        )?;
+        self.dir_sizes
+            .resize_value(normalize_dir(path), sizes.0, sizes.1);
+        Ok(())
+    }
 
+    fn remove_db_value(&mut self, path: &Path, key: &str) -> StorageInteractResult<()> {
+        let size = self
+            .db_storage
+            .remove_value(path, self.encryption_key(path), key)?;
+        self.dir_sizes.resize_value(normalize_dir(path), size, 0);
         Ok(())
     }
 
     fn remove_value(&mut self, path: impl AsRef<Path>, key: &str) -> StorageInteractResult<()> {
         let path = path.as_ref();
 
-        let io_buffer = self.stream_buffer_from_child(path)?;
-
-        let mut cd: StorageData = serde_json::from_str(&io_buffer)?;
-        if cd.data.remove(key).is_none() {
+        if !self.cached_values(path)?.contains_key(key) {
             debug!("No value for '{}' in storage", key);
-        } else {
-            let out_buffer = serde_json::to_string(&cd)?;
-            self.touch_file_for_child(path, Some(&out_buffer))?;
+            return Ok(());
+        }
+        self.remove_db_value(path, key)?;
+        if let Some(old_value) = self.cached_values(path)?.remove(key) {
+            self.record_change(path, key, old_value, Value::Null);
         }
-
         Ok(())
     }
 
-    fn init_device_storage(&mut self, dsn: &str) -> StorageInteractResult<()> {
-        let mut full_dsn_path = self.parent_path().to_path_buf();
-        full_dsn_path.push(dsn);
-        if !full_dsn_path.try_exists()? {
-            self.make_dir_for_child(dsn)?;
-        }
-
+    fn init_child_dir(&mut self, child_dir: impl AsRef<Path>) -> StorageInteractResult<()> {
+        create_dir_all(self.parent_path().join(child_dir))?;
         Ok(())
     }
 
-    fn clear_device_storage(&mut self, dsn: &str) -> StorageInteractResult<()> {
-        let mut full_dsn_path = self.parent_path().to_path_buf();
-        full_dsn_path.push(dsn);
-        if !full_dsn_path.try_exists()? {
-            return Ok(());
+    fn clear_child_dir(&mut self, child_dir: impl AsRef<Path>) -> StorageInteractResult<()> {
+        let child_dir = child_dir.as_ref();
+        let mut full_path = self.parent_path().to_path_buf();
+        full_path.push(child_dir);
+        if !full_path.try_exists()? {
+            // Values of a dir removed from the disk would come back with it.
+            return self.remove_db_values(child_dir);
         }
 
-        self.remove_dir_for_child(dsn)?;
+        self.remove_dir_for_child(child_dir)?;
         Ok(())
     }
 
@@ -328,22 +877,87 @@ impl InnerStorage {
         self.parent_path.as_path()
     }
 
-    fn write_bytes_to_disk(&mut self, path: &Path, bytes: &[u8]) -> Result<()> {
-        if let Some(dir) = path.parent() {
-            create_dir_all(dir)?;
+    fn store_path(&self, child_dir: impl AsRef<Path>) -> PathBuf {
+        let mut full_path = self.parent_path().to_path_buf();
+        full_path.push(child_dir);
+        full_path.push(STORAGE_HIDDEN_FILE_NAME);
+        full_path
+    }
+}
+
+/// Replaces the file at `path` atomically.
+fn replace_file(path: &Path, bytes: &[u8]) -> Result<()> {
+    let temp_path = write_temp_file(path, bytes)?;
+    fs::rename(temp_path, path)?;
+    sync_parent_dir(path)
+}
+
+/// Writes and syncs `bytes` to a temp file next to `path`. Returns the temp file path.
+fn write_temp_file(path: &Path, bytes: &[u8]) -> Result<PathBuf> {
+    let temp_path = path_with_suffix(path, TEMP_FILE_SUFFIX);
+    let mut file = File::create(&temp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    Ok(temp_path)
+}
+
+/// Makes renames in the parent directory of `path` durable.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    match path.parent() {
+        Some(dir) => File::open(dir)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Lexically normalizes the `child_dir`, so e.g. `user`, `user/` and `./user` share a cache entry.
+pub(super) fn normalize_dir(child_dir: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in child_dir.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
         }
-        let mut file = File::create(path)?;
-        let mut content = Cursor::new(bytes);
-        std::io::copy(&mut content, &mut file)?;
-        file.flush()?;
-        Ok(())
     }
+    normalized
+}
+
+/// Key of the `child_dir` in db buckets.
+fn dir_key(child_dir: &Path) -> String {
+    normalize_dir(child_dir).to_string_lossy().into_owned()
+}
 
-    fn read_from_disk_to_string(&mut self, path: impl AsRef<Path>) -> Result<String> {
-        let mut file = OpenOptions::new().read(true).open(path)?;
-        let mut input_buffer = String::new();
-        file.read_to_string(&mut input_buffer)?;
-        Ok(input_buffer)
+fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Applies a JSON Merge Patch (RFC 7386) to the `target`.
+pub(crate) fn merge_patch(target: &mut Value, patch: Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(&key);
+            } else {
+                merge_patch(target.entry(key).or_insert(Value::Null), value);
+            }
+        }
     }
 }
 
@@ -354,7 +968,7 @@ impl StorageDir for Storage {
     }
 
     fn remove_dir_for_child(&self, child_dir: impl AsRef<Path>) -> Result<()> {
-        self.lock_storage().remove_dir_for_child(child_dir)
+        self.with_storage(|storage| storage.remove_dir_for_child(child_dir))
     }
 
     fn touch_file_for_child(&self, child_dir: impl AsRef<Path>, bytes: Option<&str>) -> Result<()> {
@@ -367,6 +981,8 @@ impl StorageDir for Storage {
 }
 
 // User Facing Handlers
+/// Values are kept in the [Storage::db] with one bucket per child dir, which has to exist to be read.
+/// `.store` files from before are imported by a migration.
 impl StorageInteract for Storage {
     fn get_value(
         &self,
@@ -380,18 +996,37 @@ impl StorageInteract for Storage {
     where
         T: Serialize,
     {
-        self.lock_storage().set_value(path, key, value)
+        self.with_storage(|storage| storage.set_value(path, key, value))
     }
 
     fn remove_value(&self, path: impl AsRef<Path>, key: &str) -> StorageInteractResult<()> {
-        self.lock_storage().remove_value(path, key)
+        self.with_storage(|storage| storage.remove_value(path, key))
+    }
+
+    fn merge_value<T>(
+        &self,
+        path: impl AsRef<Path>,
+        key: &str,
+        patch: T,
+    ) -> StorageInteractResult<()>
+    where
+        T: Serialize,
+    {
+        self.with_storage(|storage| storage.merge_value(path, key, patch))
+    }
+
+    fn update<F>(&self, path: impl AsRef<Path>, key: &str, f: F) -> StorageInteractResult<()>
+    where
+        F: FnOnce(StorageDataValue) -> StorageDataValue,
+    {
+        self.with_storage(|storage| storage.update(path, key, f))
     }
 
     fn init_device_storage(&self, dsn: &str) -> StorageInteractResult<()> {
-        self.lock_storage().init_device_storage(dsn)
+        self.lock_storage().init_child_dir(dsn)
     }
 
     fn clear_device_storage(&self, dsn: &str) -> StorageInteractResult<()> {
-        self.lock_storage().clear_device_storage(dsn)
+        self.with_storage(|storage| storage.clear_child_dir(dsn))
     }
 }
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{
+    dir_key, normalize_dir, path_with_suffix, InnerStorage, StorageInteractError,
+    StorageInteractResult, CRATE_WORKSPACE, LEGACY_CRATE_WORKSPACE, SELECTED_REGION_STORAGE_KEY,
+    STORAGE_APP_DIR, STORAGE_HIDDEN_FILE_NAME, STORAGE_USER_DIR,
+};
+use crate::db::Bucket;
+use crate::storage::usage;
+use crate::storage::StorageDataValue;
+
+/// Key of the last applied migration version in the app dir.
+/// Before the stores were imported into the db, it was kept in the app `.store` file.
+pub(super) const STORAGE_VERSION_KEY: &str = "storageVersion";
+/// Child dirs whose encrypted `.store` files are imported once their keys are set.
+const PENDING_IMPORTS_BUCKET: &str = "storage_pending_imports";
+/// Stores written before the import kept their previous version with this suffix.
+const BACKUP_FILE_SUFFIX: &str = ".bak";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StorageData {
+    /// Data will hold all the persisted objects in storage. Its the entry point to our storage.
+    /// ```
+    /// {
+    ///   "data"; {
+    ///     // ... storage.
+    ///   }
+    /// }
+    /// ```
+    data: HashMap<String, Value>,
+}
+
+/// A change of the storage layout.
+/// Every migration runs on a new storage too, so it has to do nothing if there's nothing to migrate.
+struct Migration {
+    version: u64,
+    name: &'static str,
+    run: fn(&mut InnerStorage) -> StorageInteractResult<()>,
+}
+
+/// Ordered by version. New migrations are appended with the next version.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "rename workspace",
+        run: rename_workspace,
+    },
+    Migration {
+        version: 2,
+        name: "move selected region to the app dir",
+        run: move_selected_region,
+    },
+    Migration {
+        version: 3,
+        name: "import stores into the db",
+        run: import_stores_into_db,
+    },
+];
+
+/// Runs migrations newer than the version recorded in the app dir.
+/// The version is recorded after every migration, so a failed migration is retried by the next run.
+/// Runs happen when the storage is created and when an encryption key is set.
+pub(super) fn run(storage: &mut InnerStorage) -> StorageInteractResult<()> {
+    let version = match storage.get_value(STORAGE_APP_DIR, STORAGE_VERSION_KEY)? {
+        StorageDataValue::Null => legacy_version(storage)?,
+        version => version,
+    }
+    .as_u64()
+    .unwrap_or_default();
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        info!(
+            "Running storage migration {}: {}",
+            migration.version, migration.name
+        );
+        (migration.run)(storage)?;
+        storage.set_value(STORAGE_APP_DIR, STORAGE_VERSION_KEY, migration.version)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the version recorded in the app `.store` file by migrations that ran before the import.
+fn legacy_version(storage: &mut InnerStorage) -> StorageInteractResult<StorageDataValue> {
+    if !storage.store_path(STORAGE_APP_DIR).try_exists()? {
+        return Ok(StorageDataValue::Null);
+    }
+    let mut app_store = read_store_data(storage, Path::new(STORAGE_APP_DIR))?;
+    Ok(app_store
+        .data
+        .remove(STORAGE_VERSION_KEY)
+        .unwrap_or_default())
+}
+
+fn rename_workspace(storage: &mut InnerStorage) -> StorageInteractResult<()> {
+    if storage.parent_path.file_name() != Some(LEGACY_CRATE_WORKSPACE.as_ref()) {
+        return Ok(());
+    }
+
+    let workspace_path = storage.parent_path.with_file_name(CRATE_WORKSPACE);
+    fs::rename(&storage.parent_path, &workspace_path)?;
+    storage.parent_path = workspace_path;
+    Ok(())
+}
+
+/// Runs before the stores are imported into the db, so the region is moved between the `.store` files.
+/// If the user store was encrypted before the region was moved, it's moved when the store is imported
+/// once its key is set, see [import_store]. Later migrations don't wait for the key.
+fn move_selected_region(storage: &mut InnerStorage) -> StorageInteractResult<()> {
+    if !storage.store_path(STORAGE_USER_DIR).try_exists()? {
+        return Ok(());
+    }
+
+    let mut user_store = match read_store_data(storage, Path::new(STORAGE_USER_DIR)) {
+        Err(StorageInteractError::EncryptionKeyMissing) => return Ok(()),
+        user_store => user_store?,
+    };
+    let Some(region) = user_store.data.remove(SELECTED_REGION_STORAGE_KEY) else {
+        return Ok(());
+    };
+    let mut app_store = if storage.store_path(STORAGE_APP_DIR).try_exists()? {
+        read_store_data(storage, Path::new(STORAGE_APP_DIR))?
+    } else {
+        StorageData::default()
+    };
+    app_store
+        .data
+        .insert(SELECTED_REGION_STORAGE_KEY.to_string(), region);
+    storage.write_store(
+        Path::new(STORAGE_APP_DIR),
+        &serde_json::to_string(&app_store)?,
+    )?;
+    storage.write_store(
+        Path::new(STORAGE_USER_DIR),
+        &serde_json::to_string(&user_store)?,
+    )
+}
+
+/// Moves the values of every `.store` file into the db. The files and their backups are removed,
+/// so no stale or plaintext copies are left. Encrypted stores are imported once their keys are set.
+fn import_stores_into_db(storage: &mut InnerStorage) -> StorageInteractResult<()> {
+    let mut dirs = Vec::new();
+    find_store_dirs(&storage.parent_path, Path::new(""), &mut dirs)?;
+
+    for dir in dirs {
+        import_store(storage, &dir)?;
+    }
+    Ok(())
+}
+
+/// Imports the encrypted stores skipped by [import_stores_into_db] whose keys have been set since.
+pub(super) fn import_pending_stores(storage: &mut InnerStorage) -> StorageInteractResult<()> {
+    for dir in pending_imports(storage)?.keys()? {
+        let dir = PathBuf::from(dir?);
+        if storage.encryption_key(&dir).is_some() {
+            import_store(storage, &dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies the values of the `.store` file of the `dir` into the db and removes the file.
+/// Values already in the db are newer, so they're kept.
+/// A region left in the user store by [move_selected_region] goes to the app dir.
+fn import_store(storage: &mut InnerStorage, dir: &Path) -> StorageInteractResult<()> {
+    let pending_imports = pending_imports(storage)?;
+    // The dir was removed since the import was put off.
+    if !has_store(&storage.parent_path.join(dir))? {
+        pending_imports.remove(&dir_key(dir))?;
+        return Ok(());
+    }
+    let data = match read_store_data(storage, dir) {
+        Ok(store) => store.data,
+        Err(StorageInteractError::EncryptionKeyMissing) => {
+            info!(
+                "Importing the encrypted store of {:?} once its key is set",
+                dir
+            );
+            pending_imports.insert(&dir_key(dir), &true)?;
+            return Ok(());
+        }
+        Err(err) => {
+            warn!("Couldn't import the store of {:?}: {err}", dir);
+            return Ok(());
+        }
+    };
+
+    for (key, value) in data {
+        let is_version = dir == Path::new(STORAGE_APP_DIR) && key == STORAGE_VERSION_KEY;
+        let is_region = dir == Path::new(STORAGE_USER_DIR) && key == SELECTED_REGION_STORAGE_KEY;
+        let target_dir = if is_region {
+            Path::new(STORAGE_APP_DIR)
+        } else {
+            dir
+        };
+        if !is_version && storage.get_value(target_dir, &key)?.is_null() {
+            storage.set_value(target_dir, &key, value)?;
+        }
+    }
+
+    let store_path = storage.store_path(dir);
+    for path in [
+        path_with_suffix(&store_path, BACKUP_FILE_SUFFIX),
+        store_path,
+    ] {
+        if path.try_exists()? {
+            let size = usage::file_size(&path)?;
+            fs::remove_file(path)?;
+            storage.dir_sizes.resize_file(normalize_dir(dir), size, 0);
+        }
+    }
+    pending_imports.remove(&dir_key(dir))?;
+    Ok(())
+}
+
+/// Reads and parses the `.store` file of the `dir`.
+/// If it's missing or corrupted, it's read from the backup made by the write before it.
+fn read_store_data(storage: &InnerStorage, dir: &Path) -> StorageInteractResult<StorageData> {
+    let store_path = storage.store_path(dir);
+    match parse_store(storage, dir, &store_path) {
+        Err(err @ StorageInteractError::EncryptionKeyMissing) => Err(err),
+        Err(err) => {
+            let backup_path = path_with_suffix(&store_path, BACKUP_FILE_SUFFIX);
+            let backup = parse_store(storage, dir, &backup_path).map_err(|_| err)?;
+            warn!("Reading the store of {:?} from the backup", dir);
+            Ok(backup)
+        }
+        store => store,
+    }
+}
+
+fn parse_store(
+    storage: &InnerStorage,
+    dir: &Path,
+    path: &Path,
+) -> StorageInteractResult<StorageData> {
+    Ok(serde_json::from_str(&storage.read_store(dir, path)?)?)
+}
+
+fn pending_imports(storage: &InnerStorage) -> StorageInteractResult<Bucket<String, bool>> {
+    Ok(storage.db.open_bucket(PENDING_IMPORTS_BUCKET)?)
+}
+
+/// Collects dirs with a store inside the `dir` of the `parent_path`, relative to the `parent_path`.
+fn find_store_dirs(
+    parent_path: &Path,
+    dir: &Path,
+    dirs: &mut Vec<PathBuf>,
+) -> StorageInteractResult<()> {
+    if has_store(&parent_path.join(dir))? {
+        dirs.push(dir.to_path_buf());
+    }
+    for entry in fs::read_dir(parent_path.join(dir))? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            find_store_dirs(parent_path, &dir.join(entry.file_name()), dirs)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the dir at `path` has a `.store` file or a backup to restore it from.
+fn has_store(path: &Path) -> StorageInteractResult<bool> {
+    let store_path = path.join(STORAGE_HIDDEN_FILE_NAME);
+    Ok(store_path.try_exists()?
+        || path_with_suffix(&store_path, BACKUP_FILE_SUFFIX).try_exists()?)
+}
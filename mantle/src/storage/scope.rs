@@ -0,0 +1,179 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::storage::json_storage::{Storage, StorageInteractResult, STORAGE_APP_DIR};
+use crate::storage::{StorageDataValue, StorageInteract};
+
+/// Dir of the account scopes.
+pub const STORAGE_ACCOUNTS_DIR: &str = "accounts";
+/// Dir of the device scopes inside an account scope.
+const DEVICES_DIR: &str = "devices";
+
+/// A level of the storage hierarchy: app → user account → device.
+/// Data of an account is removed together with its devices.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ScopeId {
+    /// Data shared by all accounts. Stored in the `app` dir, next to the `accounts` dir rather than above it,
+    /// so wiping it keeps the accounts. The storage version and the selected region are kept as well.
+    App,
+    /// Data of a user account.
+    Account(String),
+    /// Data of a device in a user account, so it isn't shared with other accounts using the same device.
+    Device { account: String, dsn: String },
+}
+
+/// A handle to the data of a [ScopeId]. Cloned scopes point to the same storage.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let os_dir = std::env::temp_dir().join("storage_scope_example");
+/// # std::fs::create_dir_all(&os_dir)?;
+/// use mantle_utilities::storage::{ScopeId, Storage};
+///
+/// let storage = Storage::new(&os_dir)?;
+/// let device = storage.scope(ScopeId::Device {
+///     account: "user@example.com".to_string(),
+///     dsn: "AC000W000000001".to_string(),
+/// })?;
+/// device.set_value("name", "Kitchen")?;
+/// assert_eq!(device.get::<String>("name")?, Some("Kitchen".to_string()));
+///
+/// // Removes the account data with all its devices.
+/// storage.wipe_scope(&ScopeId::Account("user@example.com".to_string()))?;
+/// # drop(device);
+/// # drop(storage);
+/// # std::fs::remove_dir_all(&os_dir)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Scope {
+    storage: Storage,
+    id: ScopeId,
+    path: PathBuf,
+}
+
+impl ScopeId {
+    /// Returns the enclosing scope. [ScopeId::App] is the root, though its dir doesn't contain the accounts.
+    pub fn parent(&self) -> Option<ScopeId> {
+        match self {
+            ScopeId::App => None,
+            ScopeId::Account(_) => Some(ScopeId::App),
+            ScopeId::Device { account, .. } => Some(ScopeId::Account(account.clone())),
+        }
+    }
+
+    /// Returns the dir of the scope relative to the storage parent path.
+    /// Ids are escaped, so they can't point outside of the scope.
+    pub fn path(&self) -> PathBuf {
+        match self {
+            ScopeId::App => PathBuf::from(STORAGE_APP_DIR),
+            ScopeId::Account(account) => {
+                Path::new(STORAGE_ACCOUNTS_DIR).join(escape_path_segment(account))
+            }
+            ScopeId::Device { account, dsn } => ScopeId::Account(account.clone())
+                .path()
+                .join(DEVICES_DIR)
+                .join(escape_path_segment(dsn)),
+        }
+    }
+}
+
+impl Scope {
+    pub(crate) fn new(storage: Storage, id: ScopeId) -> Self {
+        Scope {
+            storage,
+            path: id.path(),
+            id,
+        }
+    }
+
+    pub fn id(&self) -> &ScopeId {
+        &self.id
+    }
+
+    /// See [StorageInteract::get_value].
+    pub fn get_value(&self, key: &str) -> StorageInteractResult<StorageDataValue> {
+        self.storage.get_value(&self.path, key)
+    }
+
+    /// See [StorageInteract::get].
+    pub fn get<T>(&self, key: &str) -> StorageInteractResult<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        self.storage.get(&self.path, key)
+    }
+
+    /// See [StorageInteract::get_path].
+    pub fn get_path(&self, key_path: &str) -> StorageInteractResult<StorageDataValue> {
+        self.storage.get_path(&self.path, key_path)
+    }
+
+    /// See [StorageInteract::set_value].
+    pub fn set_value<T>(&self, key: &str, value: T) -> StorageInteractResult<()>
+    where
+        T: Serialize,
+    {
+        self.storage.set_value(&self.path, key, value)
+    }
+
+    /// See [StorageInteract::merge_value].
+    pub fn merge_value<T>(&self, key: &str, patch: T) -> StorageInteractResult<()>
+    where
+        T: Serialize,
+    {
+        self.storage.merge_value(&self.path, key, patch)
+    }
+
+    /// See [StorageInteract::update].
+    pub fn update<F>(&self, key: &str, f: F) -> StorageInteractResult<()>
+    where
+        F: FnOnce(StorageDataValue) -> StorageDataValue,
+    {
+        self.storage.update(&self.path, key, f)
+    }
+
+    /// See [StorageInteract::remove_value].
+    pub fn remove_value(&self, key: &str) -> StorageInteractResult<()> {
+        self.storage.remove_value(&self.path, key)
+    }
+
+    /// Removes the data of the scope and its child scopes. See [Storage::wipe_scope].
+    pub fn clear(&self) -> StorageInteractResult<()> {
+        self.storage.wipe_scope(&self.id)
+    }
+}
+
+/// Percent-encodes everything except ASCII alphanumerics, `-`, `_` and `@`,
+/// so an id is always a single path segment. An empty id is encoded as `%`.
+fn escape_path_segment(id: &str) -> String {
+    if id.is_empty() {
+        return "%".to_string();
+    }
+    let mut segment = String::with_capacity(id.len());
+    for byte in id.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'@') {
+            segment.push(byte as char);
+        } else {
+            write!(segment, "%{byte:02X}").unwrap();
+        }
+    }
+    segment
+}
+
+#[test]
+fn escapes_path_segments() {
+    assert_eq!(escape_path_segment("AC000W0001"), "AC000W0001");
+    assert_eq!(
+        escape_path_segment("user@example.com"),
+        "user@example%2Ecom"
+    );
+    assert_eq!(escape_path_segment("../a/b"), "%2E%2E%2Fa%2Fb");
+    assert_eq!(escape_path_segment(""), "%");
+}
@@ -0,0 +1,72 @@
+use once_cell::sync::OnceCell;
+
+use crate::db::ImportMode;
+use crate::error::MantleResultError;
+use crate::storage::{
+    Storage, StorageChange, StorageEncryptionKey, StorageInteractError, SubscriptionId,
+};
+
+/// Storage used by the platform bindings.
+pub static SHARED_STORAGE: OnceCell<Storage> = OnceCell::new();
+
+/// Creates the shared storage in the `os_dir`. Later calls keep the first storage.
+pub fn init(os_dir: String) -> Result<(), Box<dyn MantleResultError>> {
+    SHARED_STORAGE
+        .get_or_try_init(|| Storage::new(os_dir))
+        .map(|_| ())
+        .map_err(|err| Box::new(err) as Box<dyn MantleResultError>)
+}
+
+pub fn subscribe<F>(
+    dir: String,
+    key: String,
+    callback: F,
+) -> Result<SubscriptionId, Box<dyn MantleResultError>>
+where
+    F: Fn(&StorageChange) + Send + Sync + 'static,
+{
+    Ok(shared_storage()?.subscribe(dir, &key, callback))
+}
+
+pub fn unsubscribe(id: SubscriptionId) -> Result<bool, Box<dyn MantleResultError>> {
+    Ok(shared_storage()?.unsubscribe(id))
+}
+
+/// Encrypts the `dir` of the shared storage with the `key`. See [Storage::set_encryption_key].
+/// Fails with [StorageInteractError::InvalidEncryptionKey] if the key isn't 32 bytes long.
+pub fn set_encryption_key(dir: String, key: Vec<u8>) -> Result<(), Box<dyn MantleResultError>> {
+    let key: StorageEncryptionKey = key.try_into().map_err(|key: Vec<u8>| {
+        Box::new(StorageInteractError::InvalidEncryptionKey(key.len()))
+            as Box<dyn MantleResultError>
+    })?;
+    shared_storage()?.set_encryption_key(dir, key);
+    Ok(())
+}
+
+/// Writes an archive of the shared storage's db to the file at `path`. See [crate::db::Db::export].
+pub fn export_db(path: String) -> Result<(), Box<dyn MantleResultError>> {
+    shared_storage()?
+        .db()
+        .export_to_file(path)
+        .map_err(|err| Box::new(err) as Box<dyn MantleResultError>)
+}
+
+/// Restores the shared storage's db from the archive file at `path`. See [Storage::import_db].
+/// Existing data is removed first if `replace` is set, otherwise the archive is merged into it.
+pub fn import_db(path: String, replace: bool) -> Result<(), Box<dyn MantleResultError>> {
+    let mode = if replace {
+        ImportMode::Replace
+    } else {
+        ImportMode::Merge
+    };
+    shared_storage()?
+        .import_db(path, mode)
+        .map_err(|err| Box::new(err) as Box<dyn MantleResultError>)
+}
+
+/// Fails with [StorageInteractError::NotInitialized] before [init].
+fn shared_storage() -> Result<&'static Storage, Box<dyn MantleResultError>> {
+    SHARED_STORAGE
+        .get()
+        .ok_or_else(|| Box::new(StorageInteractError::NotInitialized) as Box<dyn MantleResultError>)
+}
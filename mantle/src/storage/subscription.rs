@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::json_storage::normalize_dir;
+use crate::storage::StorageDataValue;
+
+/// A value change of a subscribed key. A removed key has a [StorageDataValue::Null] value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageChange {
+    pub dir: PathBuf,
+    pub key: String,
+    pub old_value: StorageDataValue,
+    pub new_value: StorageDataValue,
+}
+
+/// Identifies a subscription made by [crate::storage::Storage::subscribe].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(pub u64);
+
+pub(crate) type StorageChangeCallback = Arc<dyn Fn(&StorageChange) + Send + Sync>;
+
+/// Callbacks by the subscribed dir and key, and changes waiting to be delivered to them.
+/// Dirs are normalized, so e.g. `./user/` and `user` are the same dir.
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    next_id: u64,
+    callbacks: HashMap<(PathBuf, String), Vec<(SubscriptionId, StorageChangeCallback)>>,
+    pending: Vec<(StorageChangeCallback, StorageChange)>,
+}
+
+/// Delivers changes to their callbacks.
+pub(crate) struct Notifications(Vec<(StorageChangeCallback, StorageChange)>);
+
+impl Subscriptions {
+    pub(crate) fn subscribe(
+        &mut self,
+        dir: PathBuf,
+        key: String,
+        callback: StorageChangeCallback,
+    ) -> SubscriptionId {
+        self.next_id += 1;
+        let id = SubscriptionId(self.next_id);
+        self.callbacks
+            .entry((normalize_dir(&dir), key))
+            .or_default()
+            .push((id, callback));
+        id
+    }
+
+    /// Returns false if there's no subscription with the `id`.
+    pub(crate) fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let mut found = false;
+        self.callbacks.retain(|_, callbacks| {
+            callbacks.retain(|(callback_id, _)| {
+                found |= *callback_id == id;
+                *callback_id != id
+            });
+            !callbacks.is_empty()
+        });
+        found
+    }
+
+    /// Queues the change for the subscribers of its key if the value changed.
+    pub(crate) fn record(&mut self, mut change: StorageChange) {
+        if change.old_value == change.new_value {
+            return;
+        }
+        change.dir = normalize_dir(&change.dir);
+        let Some(callbacks) = self
+            .callbacks
+            .get(&(change.dir.clone(), change.key.clone()))
+        else {
+            return;
+        };
+        for (_, callback) in callbacks {
+            self.pending.push((callback.clone(), change.clone()));
+        }
+    }
+
+    pub(crate) fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Drops changes recorded after there were `count` pending changes.
+    pub(crate) fn discard_pending(&mut self, count: usize) {
+        self.pending.truncate(count);
+    }
+
+    pub(crate) fn take_pending(&mut self) -> Notifications {
+        Notifications(mem::take(&mut self.pending))
+    }
+
+    /// Returns the subscribed dirs and keys inside the `dir`.
+    pub(crate) fn keys_in(&self, dir: &Path) -> Vec<(PathBuf, String)> {
+        let dir = normalize_dir(dir);
+        self.callbacks
+            .keys()
+            .filter(|(subscribed_dir, _)| subscribed_dir.starts_with(&dir))
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn is_subscribed(&self, dir: &Path, key: &str) -> bool {
+        self.callbacks
+            .contains_key(&(normalize_dir(dir), key.to_string()))
+    }
+}
+
+impl fmt::Debug for Subscriptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscriptions")
+            .field("next_id", &self.next_id)
+            .field("keys", &self.callbacks.keys().collect::<Vec<_>>())
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+impl Notifications {
+    /// Calls the callbacks. The storage has to be unlocked, so the callbacks can use it.
+    pub(crate) fn deliver(self) {
+        for (callback, change) in self.0 {
+            callback(&change);
+        }
+    }
+}
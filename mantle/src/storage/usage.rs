@@ -0,0 +1,130 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Space taken by the storage on the disk, see [crate::storage::Storage::usage].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageUsage {
+    /// Bytes taken by the files of each child dir, e.g. `app` or a device dir, including its subdirs.
+    pub dirs: BTreeMap<PathBuf, u64>,
+    /// Bytes taken by the values of each child dir, including its subdirs. They're stored in the `db`.
+    pub values: BTreeMap<PathBuf, u64>,
+    /// Bytes taken by the sled store, or None if the db isn't stored on the disk.
+    pub db: Option<u64>,
+}
+
+impl StorageUsage {
+    /// Returns the bytes taken by the child dirs and the db, which includes the values.
+    pub fn total(&self) -> u64 {
+        self.dirs.values().sum::<u64>() + self.db.unwrap_or_default()
+    }
+}
+
+/// Bytes taken by the files and the values of each dir, by normalized dir relative to the storage.
+/// Measured when the storage is created and then kept up to date by its writes, so quotas can be checked
+/// without walking the dirs. Files written to the dirs by others are only counted from the next measurement.
+#[derive(Debug, Default)]
+pub(crate) struct DirSizes {
+    /// Bytes of the files directly inside each dir.
+    files: HashMap<PathBuf, u64>,
+    /// Bytes of the values of each dir in the db.
+    values: HashMap<PathBuf, u64>,
+}
+
+impl DirSizes {
+    /// Measures the files inside the `parent_path`. The sizes of the `values` come from the db.
+    pub(crate) fn measure(parent_path: &Path, values: HashMap<PathBuf, u64>) -> Result<Self> {
+        let mut sizes = DirSizes {
+            files: HashMap::new(),
+            values,
+        };
+        sizes.measure_files(parent_path, PathBuf::new())?;
+        Ok(sizes)
+    }
+
+    /// Returns the bytes taken by the `dir` and its subdirs.
+    pub(crate) fn size(&self, dir: &Path) -> u64 {
+        self.files
+            .iter()
+            .chain(&self.values)
+            .filter(|(sizes_dir, _)| sizes_dir.starts_with(dir))
+            .map(|(_, size)| size)
+            .sum()
+    }
+
+    /// Records that a file of the `dir` taking `old_size` bytes was replaced by one taking `new_size` bytes.
+    pub(crate) fn resize_file(&mut self, dir: PathBuf, old_size: u64, new_size: u64) {
+        resize(&mut self.files, dir, old_size, new_size);
+    }
+
+    /// Records that a value of the `dir` taking `old_size` bytes was replaced by one taking `new_size` bytes.
+    pub(crate) fn resize_value(&mut self, dir: PathBuf, old_size: u64, new_size: u64) {
+        resize(&mut self.values, dir, old_size, new_size);
+    }
+
+    /// Replaces the measured size of the values of the `dir`.
+    pub(crate) fn set_values(&mut self, dir: PathBuf, size: u64) {
+        self.values.insert(dir, size);
+    }
+
+    /// Replaces the measured sizes of the values of all dirs.
+    pub(crate) fn replace_values(&mut self, values: HashMap<PathBuf, u64>) {
+        self.values = values;
+    }
+
+    /// Forgets the files and values of the dirs for which `is_removed` returns true.
+    pub(crate) fn remove_dirs(&mut self, is_removed: impl Fn(&Path) -> bool) {
+        self.files.retain(|dir, _| !is_removed(dir));
+        self.values.retain(|dir, _| !is_removed(dir));
+    }
+
+    fn measure_files(&mut self, parent_path: &Path, dir: PathBuf) -> Result<()> {
+        let mut size = 0;
+        for entry in fs::read_dir(parent_path.join(&dir))? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                self.measure_files(parent_path, dir.join(entry.file_name()))?;
+            } else {
+                size += metadata.len();
+            }
+        }
+        self.files.insert(dir, size);
+        Ok(())
+    }
+}
+
+fn resize(sizes: &mut HashMap<PathBuf, u64>, dir: PathBuf, old_size: u64, new_size: u64) {
+    let size = sizes.entry(dir).or_default();
+    *size = size.saturating_sub(old_size) + new_size;
+}
+
+/// Returns the size of the files in the dir and its subdirs. A missing dir has no size.
+pub(crate) fn dir_size(path: &Path) -> Result<u64> {
+    if !path.try_exists()? {
+        return Ok(0);
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+/// Returns the size of the file, 0 if it's missing.
+pub(crate) fn file_size(path: &Path) -> Result<u64> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err),
+    }
+}
@@ -1,73 +1,174 @@
+mod job_handle;
+mod pool;
+mod pool_config;
+
+pub use job_handle::{CancellationToken, JobError, JobHandle, PoolError};
+pub use pool_config::{PoolConfig, RejectionPolicy};
+
+use job_handle::panic_message;
 use log::error;
 use once_cell::sync::Lazy;
-use std::string::ToString;
+use pool::Pool;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::{mpsc, Arc, Mutex};
-use threadpool::ThreadPool;
 
-static TPOOL: Lazy<Arc<Mutex<ThreadPool>>> = Lazy::new(|| {
-    Arc::new(Mutex::new(ThreadPool::with_name(
-        "mantle-thread".to_string(),
-        num_worker_threads(),
-    )))
+/// The pool of [execute_job].
+pub const DEFAULT_POOL: &str = "default";
+
+/// The pool of the respondents and callbacks of [crate::poll_manager::PollManager].
+/// Its limits and [RejectionPolicy] can be set with [configure_pool].
+pub const POLL_POOL: &str = "poll";
+
+static POOLS: Lazy<Mutex<HashMap<String, Arc<Pool>>>> = Lazy::new(|| {
+    let default_pool = Pool::new(DEFAULT_POOL, PoolConfig::default());
+    let poll_pool = Pool::new(POLL_POOL, PoolConfig::default());
+    Mutex::new(HashMap::from([
+        (DEFAULT_POOL.to_string(), default_pool),
+        (POLL_POOL.to_string(), poll_pool),
+    ]))
 });
 
+fn find_pool(name: &str) -> Option<Arc<Pool>> {
+    match POOLS.lock() {
+        Ok(pools) => pools.get(name).cloned(),
+        Err(err) => {
+            error!("Error getting lock on POOLS: {}", err);
+            None
+        }
+    }
+}
+
+/// Executes the `job` on the default pool. [spawn] returns a handle that tells if the pool accepted it,
+/// see [RejectionPolicy].
 pub fn execute_job<F>(job: F)
 where
     F: FnOnce() + Send + 'static,
 {
-    match TPOOL.clone().lock() {
-        Ok(mut pool) => {
-            let active = pool.active_count();
-            let worker_threads = num_worker_threads();
-            if active >= worker_threads {
-                pool.set_num_threads(active + 1);
-            } else {
-                pool.set_num_threads(worker_threads)
-            }
+    spawn(job);
+}
+
+/// Executes the `job` on the pool named `pool`, see [configure_pool].
+/// The handle tells if the pool accepted it.
+pub fn execute_job_in<F>(pool: &str, job: F) -> JobHandle
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_in(pool, job)
+}
+
+/// Executes the `job` on the default pool. Its result or panic can be joined through the handle.
+pub fn spawn<F, R>(job: F) -> JobHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    spawn_in(DEFAULT_POOL, job)
+}
+
+/// Executes the `job` on the pool named `pool`, see [spawn].
+pub fn spawn_in<F, R>(pool: &str, job: F) -> JobHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    spawn_cancellable_in(pool, |_| job())
+}
 
-            pool.execute(job);
+/// Executes the `job` on the default pool, passing it the token of [JobHandle::cancel].
+/// A long job should return early once the token is cancelled.
+pub fn spawn_cancellable<F, R>(job: F) -> JobHandle<R>
+where
+    F: FnOnce(&CancellationToken) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    spawn_cancellable_in(DEFAULT_POOL, job)
+}
+
+/// Executes the `job` on the pool named `pool`, see [spawn_cancellable].
+pub fn spawn_cancellable_in<F, R>(pool: &str, job: F) -> JobHandle<R>
+where
+    F: FnOnce(&CancellationToken) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let token = CancellationToken::new();
+    let (tx, rx) = mpsc::channel();
+    let job_token = token.clone();
+    let pool_name = pool.to_string();
+    let job = move || {
+        let result = if job_token.is_cancelled() {
+            Err(JobError::Cancelled)
+        } else {
+            // Catching the panic keeps the worker and hands the panic to the handle.
+            // With panic = "abort" there's nothing to catch, the process is aborted before this returns.
+            panic::catch_unwind(AssertUnwindSafe(|| job(&job_token))).map_err(|payload| {
+                let message = panic_message(payload.as_ref());
+                error!("A job of the '{}' pool panicked: {}", pool_name, message);
+                JobError::Panicked(message)
+            })
+        };
+        // The handle may be dropped already.
+        let _ = tx.send(result);
+    };
+
+    let accepted = match find_pool(pool) {
+        Some(found) => found.execute(Box::new(job)),
+        None => {
+            error!("No pool named '{}' for a job", pool);
+            Err(PoolError::UnknownPool(pool.to_string()))
         }
-        Err(err) => error!("Error getting lock on TPOOL clone: {}", err.to_string()),
+    };
+    JobHandle::new(accepted, token, rx)
+}
+
+/// Creates the pool named `name` with the `config`, or reconfigures it if it exists.
+/// Separate pools keep workloads, e.g. blocking HTTP calls, from starving each other.
+pub fn configure_pool(name: &str, config: PoolConfig) {
+    match POOLS.lock() {
+        Ok(mut pools) => match pools.get(name) {
+            Some(pool) => pool.configure(config),
+            None => {
+                pools.insert(name.to_string(), Pool::new(name, config));
+            }
+        },
+        Err(err) => error!("Error getting lock on POOLS: {}", err),
     }
 }
 
 /// Execute 'jobs' on the tread pool and wait for the 'jobs' results.
-/// 'jobs' - collection of closures.
+/// 'jobs' - collection of closures. The results are in the order of the 'jobs'.
+/// Panics if a job panicked or the pool didn't accept it, see [try_execute_and_join_jobs].
 pub fn execute_and_join_jobs<I, F, R>(jobs: I) -> Vec<R>
 where
     I: IntoIterator<Item = F>,
     F: FnOnce() -> R + Send + 'static,
     R: Send + 'static,
 {
-    let mut jobs_count = 0;
-    let (tx, rx) = mpsc::channel();
-
-    for job in jobs {
-        let tx = tx.clone();
-        execute_job(move || {
-            let result = job();
-            tx.send(result).unwrap();
-        });
-
-        jobs_count += 1;
-    }
+    try_execute_and_join_jobs(jobs)
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|err| panic!("A joined job failed: {}", err)))
+        .collect()
+}
 
-    let mut results = Vec::with_capacity(jobs_count);
-    for _ in 0..jobs_count {
-        let result = rx.recv().unwrap();
-        results.push(result);
-    }
-    results
+/// Like [execute_and_join_jobs], but with an error for each job that panicked or that the pool didn't accept.
+pub fn try_execute_and_join_jobs<I, F, R>(jobs: I) -> Vec<Result<R, JobError>>
+where
+    I: IntoIterator<Item = F>,
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let handles: Vec<_> = jobs.into_iter().map(spawn).collect();
+    handles.into_iter().map(JobHandle::join).collect()
 }
 
-// FIXME: execute_job rewrites this limit.
+/// Limits the workers of the default pool.
 pub fn set_max_threads(max: usize) {
-    match TPOOL.clone().lock() {
-        Ok(mut pool) => {
-            pool.set_num_threads(max);
-            drop(pool);
-        }
-        Err(err) => error!("Error getting lock on TPOOL clone: {}", err.to_string()),
+    if let Some(pool) = find_pool(DEFAULT_POOL) {
+        let config = pool.config();
+        pool.configure(PoolConfig {
+            max_threads: max,
+            ..config
+        });
     }
 }
 
@@ -0,0 +1,154 @@
+use std::any::Any;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::{Acquire, Release};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Why a pool didn't accept a job.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum PoolError {
+    #[error("Queue of the '{0}' pool is full")]
+    QueueFull(String),
+    #[error("Queue of the '{0}' pool is full, the job was dropped")]
+    Dropped(String),
+    #[error("No pool named '{0}'")]
+    UnknownPool(String),
+}
+
+/// Why a [JobHandle] has no result.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum JobError {
+    #[error("Job wasn't accepted: {0}")]
+    Rejected(PoolError),
+    #[error("Job was cancelled before it started")]
+    Cancelled,
+    /// The panic message of the job. Panics are only caught when they unwind: with `panic = "abort"`,
+    /// as in the release profile of this workspace, a panicking job aborts the whole process instead.
+    #[error("Job panicked: {0}")]
+    Panicked(String),
+    #[error("Job didn't finish in time")]
+    Timeout,
+    #[error("Job result was already taken")]
+    ResultTaken,
+}
+
+/// Asks a running job to stop. Jobs check it themselves, see [crate::spawn_cancellable].
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Acquire)
+    }
+}
+
+/// Returned by [crate::spawn] and [crate::execute_job_in] for a job handed to a pool.
+/// Dropping the handle doesn't stop the job. A panic of the job is joined as [JobError::Panicked],
+/// but only in builds that unwind on panic.
+#[derive(Debug)]
+pub struct JobHandle<R = ()> {
+    accepted: Result<(), PoolError>,
+    token: CancellationToken,
+    result: Receiver<Result<R, JobError>>,
+    taken: bool,
+}
+
+impl<R> JobHandle<R> {
+    pub(crate) fn new(
+        accepted: Result<(), PoolError>,
+        token: CancellationToken,
+        result: Receiver<Result<R, JobError>>,
+    ) -> Self {
+        JobHandle {
+            accepted,
+            token,
+            result,
+            taken: false,
+        }
+    }
+
+    /// True if the job is queued or running.
+    pub fn is_accepted(&self) -> bool {
+        self.accepted.is_ok()
+    }
+
+    /// Why the job wasn't accepted.
+    pub fn rejection(&self) -> Option<&PoolError> {
+        self.accepted.as_ref().err()
+    }
+
+    /// Cancels the job. A queued job won't run, and a running one sees it in its token.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Waits for the job to finish and returns its result.
+    pub fn join(self) -> Result<R, JobError> {
+        self.check_result()?;
+        // The job always sends its result, unless it was dropped without running.
+        self.result.recv().unwrap_or(Err(JobError::Cancelled))
+    }
+
+    /// Waits at most the `timeout` for the job to finish. [JobError::Timeout] leaves the result for later calls.
+    pub fn join_timeout(&mut self, timeout: Duration) -> Result<R, JobError> {
+        self.check_result()?;
+        match self.result.recv_timeout(timeout) {
+            Ok(result) => {
+                self.taken = true;
+                result
+            }
+            Err(RecvTimeoutError::Timeout) => Err(JobError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(JobError::Cancelled),
+        }
+    }
+
+    /// Returns the result if the job finished, None while it's queued or running.
+    pub fn try_result(&mut self) -> Option<Result<R, JobError>> {
+        if let Err(err) = self.check_result() {
+            return Some(Err(err));
+        }
+        match self.result.try_recv() {
+            Ok(result) => {
+                self.taken = true;
+                Some(result)
+            }
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(JobError::Cancelled)),
+        }
+    }
+
+    /// Fails if there's no result to wait for.
+    fn check_result(&self) -> Result<(), JobError> {
+        if self.taken {
+            return Err(JobError::ResultTaken);
+        }
+        self.accepted.clone().map_err(JobError::Rejected)
+    }
+}
+
+/// The message of a panic `payload`, as passed to `panic!`.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
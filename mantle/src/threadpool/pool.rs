@@ -0,0 +1,168 @@
+use crate::threadpool::{PoolConfig, PoolError, RejectionPolicy};
+use log::{debug, error};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+/// Catches its own panics, see [crate::spawn_cancellable_in].
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A thread pool that grows to `max_threads` workers on demand and queues jobs beyond that.
+#[derive(Debug)]
+pub(crate) struct Pool {
+    name: String,
+    state: Mutex<PoolState>,
+    /// Notified when a job is queued or the config changes, for idle workers.
+    job_added: Condvar,
+    /// Notified when a job is taken from the queue or a worker is free, for blocked submitters.
+    job_taken: Condvar,
+}
+
+struct PoolState {
+    config: PoolConfig,
+    queue: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+}
+
+impl std::fmt::Debug for PoolState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PoolState")
+            .field("config", &self.config)
+            .field("queue", &self.queue.len())
+            .field("threads", &self.threads)
+            .field("idle", &self.idle)
+            .finish()
+    }
+}
+
+impl Pool {
+    pub(crate) fn new(name: &str, config: PoolConfig) -> Arc<Self> {
+        Arc::new(Pool {
+            name: name.to_string(),
+            state: Mutex::new(PoolState {
+                config: config.normalized(),
+                queue: VecDeque::new(),
+                threads: 0,
+                idle: 0,
+            }),
+            job_added: Condvar::new(),
+            job_taken: Condvar::new(),
+        })
+    }
+
+    /// Queues the `job`, starting a worker for it if none is idle and there's room for one.
+    pub(crate) fn execute(self: &Arc<Self>, job: Job) -> Result<(), PoolError> {
+        let mut state = self.lock_state();
+        loop {
+            let has_idle_worker = state.idle > state.queue.len();
+            let can_grow = state.threads < state.config.max_threads;
+            let has_room = match state.config.queue_capacity {
+                Some(capacity) => state.queue.len() < capacity,
+                None => true,
+            };
+            if has_idle_worker || can_grow || has_room {
+                state.queue.push_back(job);
+                if has_idle_worker {
+                    self.job_added.notify_one();
+                } else if can_grow {
+                    self.spawn_worker(&mut state);
+                }
+                return Ok(());
+            }
+            match state.config.rejection_policy {
+                RejectionPolicy::Block => {
+                    state = self
+                        .job_taken
+                        .wait(state)
+                        .unwrap_or_else(|err| err.into_inner())
+                }
+                RejectionPolicy::Drop => {
+                    debug!("Dropped a job of the full '{}' pool", self.name);
+                    return Err(PoolError::Dropped(self.name.clone()));
+                }
+                RejectionPolicy::Error => {
+                    error!("Rejected a job of the full '{}' pool", self.name);
+                    return Err(PoolError::QueueFull(self.name.clone()));
+                }
+            }
+        }
+    }
+
+    /// Applies the `config` to the pool. Surplus workers exit after their current job.
+    pub(crate) fn configure(self: &Arc<Self>, config: PoolConfig) {
+        let mut state = self.lock_state();
+        state.config = config.normalized();
+        while state.threads < state.config.max_threads && state.queue.len() > state.idle {
+            self.spawn_worker(&mut state);
+        }
+        self.job_added.notify_all();
+        self.job_taken.notify_all();
+    }
+
+    pub(crate) fn config(&self) -> PoolConfig {
+        self.lock_state().config.clone()
+    }
+
+    fn spawn_worker(self: &Arc<Self>, state: &mut PoolState) {
+        let pool = Arc::clone(self);
+        let spawned = thread::Builder::new()
+            .name(format!("mantle-{}-thread", self.name))
+            .spawn(move || pool.work());
+        match spawned {
+            Ok(_) => state.threads += 1,
+            Err(err) => error!(
+                "Error spawning a worker of the '{}' pool: {}",
+                self.name, err
+            ),
+        }
+    }
+
+    /// Runs queued jobs until the pool has too many workers or it's idle for longer than `keep_alive`.
+    fn work(&self) {
+        let mut state = self.lock_state();
+        loop {
+            if state.threads > state.config.max_threads {
+                break;
+            }
+            if let Some(job) = state.queue.pop_front() {
+                self.job_taken.notify_one();
+                drop(state);
+                job();
+                state = self.lock_state();
+                continue;
+            }
+            state.idle += 1;
+            // A pool without a queue has room for a blocked job now.
+            self.job_taken.notify_one();
+            if state.threads > state.config.min_threads {
+                let keep_alive = state.config.keep_alive;
+                let (guard, timeout) = self
+                    .job_added
+                    .wait_timeout(state, keep_alive)
+                    .unwrap_or_else(|err| err.into_inner());
+                state = guard;
+                state.idle -= 1;
+                if timeout.timed_out()
+                    && state.queue.is_empty()
+                    && state.threads > state.config.min_threads
+                {
+                    break;
+                }
+            } else {
+                state = self
+                    .job_added
+                    .wait(state)
+                    .unwrap_or_else(|err| err.into_inner());
+                state.idle -= 1;
+            }
+        }
+        state.threads -= 1;
+        self.job_taken.notify_one();
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, PoolState> {
+        // Jobs run outside the lock, so a poisoned state is still consistent.
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
@@ -0,0 +1,49 @@
+use crate::threadpool::num_worker_threads;
+use std::time::Duration;
+
+/// Limits of a thread pool, see [crate::configure_pool].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// Workers kept alive while idle.
+    pub min_threads: usize,
+    /// Workers never outnumber this. At least one.
+    pub max_threads: usize,
+    /// Jobs waiting for a worker. None for no limit.
+    pub queue_capacity: Option<usize>,
+    /// What to do with a job when every worker is busy and the queue is full.
+    pub rejection_policy: RejectionPolicy,
+    /// How long workers above `min_threads` wait for a job before they exit.
+    pub keep_alive: Duration,
+}
+
+/// What [crate::execute_job] does with a job that doesn't fit into a full pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectionPolicy {
+    /// Waits until the queue has room.
+    Block,
+    /// Drops the job.
+    Drop,
+    /// Drops the job and logs an error.
+    Error,
+}
+
+impl PoolConfig {
+    /// Clamps the thread counts, so there's a worker and `min_threads` doesn't exceed `max_threads`.
+    pub(crate) fn normalized(mut self) -> Self {
+        self.max_threads = self.max_threads.max(1);
+        self.min_threads = self.min_threads.min(self.max_threads);
+        self
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            min_threads: num_worker_threads(),
+            max_threads: num_worker_threads(),
+            queue_capacity: None,
+            rejection_policy: RejectionPolicy::Block,
+            keep_alive: Duration::from_secs(60),
+        }
+    }
+}
@@ -0,0 +1,412 @@
+// Conformance suite that every DbEngine implementation must pass.
+// Each test takes a function that opens the engine in the given test directory.
+use crate::common::TestDir;
+use fake::{Dummy, Fake, Faker};
+use mantle_utilities::db::{BucketEngine, Db, DbEngine, DbError, ImportMode};
+use serde::{Deserialize, Serialize};
+use std::thread;
+
+pub type TestKey = String;
+type Bucket = mantle_utilities::db::Bucket<TestKey, TestValue>;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Dummy)]
+pub struct TestValue {
+    pub number: u32,
+    pub string: String,
+    pub vector: Vec<String>,
+    pub optional: Option<Vec<u8>>,
+    pub boolean: bool,
+}
+
+pub fn bucket_stores_data(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let bucket_id: String = Faker.fake();
+    let first_test_key: TestKey = Faker.fake();
+    let second_test_key: TestKey = Faker.fake();
+    let first_test_value: TestValue = Faker.fake();
+    let second_test_value: TestValue = Faker.fake();
+    let db = Db::new(open(&db_dir));
+    let bucket = db.open_bucket(bucket_id).unwrap();
+
+    bucket.insert(&first_test_key, &first_test_value).unwrap();
+    bucket.insert(&second_test_key, &second_test_value).unwrap();
+
+    let got_first_value = bucket.get(&first_test_key).unwrap().unwrap();
+    let got_second_value = bucket.get(&second_test_key).unwrap().unwrap();
+
+    assert_eq!(got_first_value, first_test_value);
+    assert_eq!(got_second_value, second_test_value);
+}
+
+pub fn bucket_returns_none_without_data(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let db = Db::new(open(&db_dir));
+    let bucket = db.open_bucket(Faker.fake::<String>()).unwrap();
+
+    let got: Option<TestValue> = bucket.get(&Faker.fake::<TestKey>()).unwrap();
+
+    assert!(got.is_none());
+}
+
+pub fn insert_returns_old_value(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let test_key: TestKey = Faker.fake();
+    let (first_test_value, second_test_value): (TestValue, TestValue) = Faker.fake();
+    let db = Db::new(open(&db_dir));
+    let bucket = db.open_bucket(Faker.fake::<String>()).unwrap();
+
+    let first_old = bucket.insert(&test_key, &first_test_value).unwrap();
+    let second_old = bucket.insert(&test_key, &second_test_value).unwrap();
+
+    assert!(first_old.is_none());
+    assert_eq!(second_old, Some(first_test_value));
+    assert_eq!(bucket.get(&test_key).unwrap(), Some(second_test_value));
+}
+
+pub fn can_open_multiple_independent_buckets(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let mut bucket_id: String = Faker.fake();
+    let test_key: TestKey = Faker.fake();
+    let first_test_value: TestValue = Faker.fake();
+    let second_test_value: TestValue = Faker.fake();
+    let db = Db::new(open(&db_dir));
+    let first_bucket = db.open_bucket(&bucket_id).unwrap();
+    bucket_id.push(Faker.fake());
+    let second_bucket = db.open_bucket(bucket_id).unwrap();
+
+    first_bucket.insert(&test_key, &first_test_value).unwrap();
+    second_bucket.insert(&test_key, &second_test_value).unwrap();
+
+    let got_first_value = first_bucket.get(&test_key).unwrap();
+    let got_second_value = second_bucket.get(&test_key).unwrap();
+
+    assert_eq!(got_first_value, Some(first_test_value));
+    assert_eq!(got_second_value, Some(second_test_value));
+}
+
+pub fn reopened_bucket_shares_data(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let bucket_id: String = Faker.fake();
+    let test_key: TestKey = Faker.fake();
+    let test_value: TestValue = Faker.fake();
+    let db = Db::new(open(&db_dir));
+    let first_bucket = db.open_bucket(&bucket_id).unwrap();
+    let second_bucket = db.open_bucket(&bucket_id).unwrap();
+
+    first_bucket.insert(&test_key, &test_value).unwrap();
+
+    assert_eq!(second_bucket.get(&test_key).unwrap(), Some(test_value));
+}
+
+pub fn deletes_bucket(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let bucket_id: String = Faker.fake();
+    let test_key: TestKey = Faker.fake();
+    let test_value: TestValue = Faker.fake();
+    let db = Db::new(open(&db_dir));
+    let bucket = db.open_bucket(&bucket_id).unwrap();
+    bucket.insert(&test_key, &test_value).unwrap();
+    drop(bucket);
+
+    assert!(db.delete_bucket(&bucket_id).unwrap());
+    let bucket = db.open_bucket(&bucket_id).unwrap();
+    let got_value: Option<TestValue> = bucket.get(&test_key).unwrap();
+
+    assert!(got_value.is_none());
+}
+
+pub fn deleting_missing_bucket_returns_false(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let db = Db::new(open(&db_dir));
+
+    assert!(!db.delete_bucket(Faker.fake::<String>()).unwrap());
+}
+
+pub fn lists_bucket_ids(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let mut bucket_ids: Vec<String> = Faker.fake();
+    let deleted_bucket_id: String = Faker.fake();
+    let db = Db::new(open(&db_dir));
+    for id in bucket_ids.iter() {
+        let _: Bucket = db.open_bucket(id).unwrap();
+    }
+    let _: Bucket = db.open_bucket(&deleted_bucket_id).unwrap();
+    db.delete_bucket(&deleted_bucket_id).unwrap();
+
+    let mut got_ids = db.bucket_ids().unwrap();
+    got_ids.sort();
+    bucket_ids.sort();
+    bucket_ids.dedup();
+
+    assert_eq!(got_ids, bucket_ids);
+}
+
+pub fn exports_and_imports_all_buckets(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let first_db_dir = TestDir::new();
+    let second_db_dir = TestDir::new();
+    let data: Vec<(String, TestKey, TestValue)> =
+        std::iter::repeat_with(|| Faker.fake()).take(10).collect();
+    let db = Db::new(open(&first_db_dir));
+    for (bucket_id, key, value) in data.iter() {
+        db.open_bucket(bucket_id)
+            .unwrap()
+            .insert(key, value)
+            .unwrap();
+    }
+
+    let mut archive = Vec::new();
+    db.export(&mut archive).unwrap();
+    let imported_db = Db::new(open(&second_db_dir));
+    imported_db
+        .import(archive.as_slice(), ImportMode::Replace)
+        .unwrap();
+
+    let mut got_ids = imported_db.bucket_ids().unwrap();
+    let mut expected_ids = db.bucket_ids().unwrap();
+    got_ids.sort();
+    expected_ids.sort();
+    assert_eq!(got_ids, expected_ids);
+    for (bucket_id, key, value) in data {
+        let bucket = imported_db.open_bucket(bucket_id).unwrap();
+        assert_eq!(bucket.get(&key).unwrap(), Some(value));
+    }
+}
+
+pub fn reports_bucket_stats(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let bucket_id: String = Faker.fake();
+    let empty_bucket_id = format!("{bucket_id}_empty");
+    let data: Vec<(TestKey, TestValue)> =
+        std::iter::repeat_with(|| Faker.fake()).take(10).collect();
+    let db = Db::new(open(&db_dir));
+    let bucket: Bucket = db.open_bucket(&bucket_id).unwrap();
+    let _: Bucket = db.open_bucket(&empty_bucket_id).unwrap();
+    // Index buckets aren't reported.
+    bucket
+        .add_index("number", |value: &TestValue| value.number)
+        .unwrap();
+    for (key, value) in data.iter() {
+        bucket.insert(key, value).unwrap();
+    }
+    db.flush().unwrap();
+
+    let stats = db.stats().unwrap();
+    let pairs: Vec<(TestKey, TestValue)> = bucket.iter().unwrap().map(Result::unwrap).collect();
+    let key_bytes: usize = pairs
+        .iter()
+        .map(|(key, _)| bincode::serialized_size(key).unwrap() as usize)
+        .sum();
+    let value_bytes: usize = pairs
+        .iter()
+        .map(|(_, value)| bincode::serialized_size(value).unwrap() as usize)
+        .sum();
+    // Stats are ordered by id.
+    let got_ids: Vec<&str> = stats.buckets.iter().map(|b| b.id.as_str()).collect();
+    assert_eq!(got_ids, [bucket_id.as_str(), empty_bucket_id.as_str()]);
+    let bucket_stats = stats.buckets.iter().find(|b| b.id == bucket_id).unwrap();
+    assert_eq!(bucket_stats.entries, pairs.len() as u64);
+    assert_eq!(bucket_stats.key_bytes, key_bytes as u64);
+    assert_eq!(bucket_stats.value_bytes, value_bytes as u64);
+    let empty_stats = stats
+        .buckets
+        .iter()
+        .find(|b| b.id == empty_bucket_id)
+        .unwrap();
+    assert_eq!(empty_stats.entries, 0);
+}
+
+pub fn transaction_applies_all_changes(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let (first_id, second_id): (String, String) = Faker.fake();
+    let (key, value): (Vec<u8>, Vec<u8>) = Faker.fake();
+    let engine = open(&db_dir);
+
+    engine
+        .transaction(&[&first_id, &second_id], &|buckets| {
+            buckets[0].insert(&key, &value)?;
+            buckets[1].insert(&key, &value)?;
+            Ok(())
+        })
+        .unwrap();
+
+    for id in [first_id, second_id] {
+        let got = engine.open_bucket(&id).unwrap().get(&key).unwrap();
+        assert_eq!(got.unwrap().as_ref().as_ref(), value.as_slice());
+    }
+}
+
+pub fn failed_transaction_changes_nothing(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let (first_id, second_id): (String, String) = Faker.fake();
+    let (key, value, existing_value): (Vec<u8>, Vec<u8>, Vec<u8>) = Faker.fake();
+    let engine = open(&db_dir);
+    engine
+        .open_bucket(&second_id)
+        .unwrap()
+        .insert(&key, &existing_value)
+        .unwrap();
+
+    let result = engine.transaction(&[&first_id, &second_id], &|buckets| {
+        buckets[0].insert(&key, &value)?;
+        buckets[1].remove(&key)?;
+        Err(DbError::IndexNotFound(String::new()))
+    });
+
+    assert!(result.is_err());
+    let first_got = engine.open_bucket(&first_id).unwrap().get(&key).unwrap();
+    let second_got = engine.open_bucket(&second_id).unwrap().get(&key).unwrap();
+    assert!(first_got.is_none());
+    assert_eq!(
+        second_got.unwrap().as_ref().as_ref(),
+        existing_value.as_slice()
+    );
+}
+
+pub fn index_follows_writes(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let (first_key, second_key): (TestKey, TestKey) = Faker.fake();
+    let (first_value, mut second_value): (TestValue, TestValue) = Faker.fake();
+    second_value.number = first_value.number;
+    let db = Db::new(open(&db_dir));
+    let bucket: Bucket = db.open_bucket(Faker.fake::<String>()).unwrap();
+    bucket
+        .add_index("by_number", |value: &TestValue| value.number)
+        .unwrap();
+    let index = bucket.index("by_number");
+
+    bucket.insert(&first_key, &first_value).unwrap();
+    bucket.insert(&second_key, &second_value).unwrap();
+    let mut got_keys = index.keys(&first_value.number).unwrap();
+    got_keys.sort();
+    let mut expected_keys = vec![first_key.clone(), second_key.clone()];
+    expected_keys.sort();
+    assert_eq!(got_keys, expected_keys);
+
+    bucket.remove(&first_key).unwrap();
+    assert_eq!(
+        index.get(&first_value.number).unwrap(),
+        vec![second_value.clone()]
+    );
+
+    let mut updated_value = second_value.clone();
+    updated_value.number = second_value.number.wrapping_add(1);
+    bucket.insert(&second_key, &updated_value).unwrap();
+    assert!(index.get(&second_value.number).unwrap().is_empty());
+    assert_eq!(
+        index.get(&updated_value.number).unwrap(),
+        vec![updated_value]
+    );
+}
+
+pub fn removes_item(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let test_key: TestKey = Faker.fake();
+    let test_value: TestValue = Faker.fake();
+    let db = Db::new(open(&db_dir));
+    let bucket = db.open_bucket(Faker.fake::<String>()).unwrap();
+
+    bucket.insert(&test_key, &test_value).unwrap();
+    let removed = bucket.remove(&test_key).unwrap();
+    let got_value = bucket.get(&test_key).unwrap();
+
+    assert_eq!(removed, Some(test_value));
+    assert!(got_value.is_none());
+}
+
+pub fn clears_bucket(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let test_key: TestKey = Faker.fake();
+    let db = Db::new(open(&db_dir));
+    let bucket = db.open_bucket(Faker.fake::<String>()).unwrap();
+
+    bucket
+        .insert(&test_key, &Faker.fake::<TestValue>())
+        .unwrap();
+    bucket.clear().unwrap();
+    let got_value = bucket.get(&test_key).unwrap();
+
+    assert!(got_value.is_none());
+}
+
+pub fn iterates(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let mut data: Vec<(TestKey, TestValue)> =
+        std::iter::repeat_with(|| Faker.fake()).take(100).collect();
+    let db = Db::new(open(&db_dir));
+    let bucket = db.open_bucket(Faker.fake::<String>()).unwrap();
+    for item in data.iter() {
+        bucket.insert(&item.0, &item.1).unwrap();
+    }
+
+    for item in bucket.iter().unwrap().map(|res| res.unwrap()) {
+        let idx = data.iter().position(|i| i == &item).unwrap();
+        data.remove(idx);
+    }
+
+    assert!(data.is_empty());
+}
+
+pub fn iterates_in_key_order(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let bucket = open(&db_dir).open_bucket(&Faker.fake::<String>()).unwrap();
+    let mut keys: Vec<Vec<u8>> = std::iter::repeat_with(|| Faker.fake()).take(100).collect();
+    for key in keys.iter() {
+        bucket.insert(key, key).unwrap();
+    }
+    keys.sort();
+    keys.dedup();
+
+    let got_pairs: Vec<(Vec<u8>, Vec<u8>)> = collect_pairs(bucket.as_ref());
+    let got_keys: Vec<Vec<u8>> = bucket
+        .keys()
+        .unwrap()
+        .map(|res| res.unwrap().as_ref().as_ref().to_vec())
+        .collect();
+    let got_values: Vec<Vec<u8>> = bucket
+        .values()
+        .unwrap()
+        .map(|res| res.unwrap().as_ref().as_ref().to_vec())
+        .collect();
+
+    assert!(got_pairs.iter().map(|(key, _)| key).eq(keys.iter()));
+    assert!(got_pairs.iter().map(|(_, value)| value).eq(keys.iter()));
+    assert_eq!(got_keys, keys);
+    assert_eq!(got_values, keys);
+}
+
+pub fn can_be_used_from_multiple_threads(open: impl Fn(&TestDir) -> Box<dyn DbEngine>) {
+    let db_dir = TestDir::new();
+    let bucket_id: String = Faker.fake();
+    let (first_key, first_value): (TestKey, TestValue) = Faker.fake();
+    let (second_key, second_value): (TestKey, TestValue) = Faker.fake();
+    let db = Db::new(open(&db_dir));
+    let cloned_db = db.clone();
+    let bucket = db.open_bucket(&bucket_id).unwrap();
+    let cloned_bucket = bucket.clone();
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let bucket = cloned_db.open_bucket(&bucket_id).unwrap();
+            bucket.insert(&first_key, &first_value).unwrap();
+        });
+        scope.spawn(|| {
+            cloned_bucket.insert(&second_key, &second_value).unwrap();
+        });
+    });
+
+    assert_eq!(Some(first_value), bucket.get(&first_key).unwrap());
+    assert_eq!(Some(second_value), bucket.get(&second_key).unwrap());
+}
+
+fn collect_pairs(bucket: &dyn BucketEngine) -> Vec<(Vec<u8>, Vec<u8>)> {
+    bucket
+        .iter()
+        .unwrap()
+        .map(|res| {
+            let (key, value) = res.unwrap();
+            (
+                key.as_ref().as_ref().to_vec(),
+                value.as_ref().as_ref().to_vec(),
+            )
+        })
+        .collect()
+}
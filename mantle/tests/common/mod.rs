@@ -3,6 +3,7 @@
 use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
+pub mod db;
 pub mod storage;
 
 // Temp dir with auto-cleanup on drop.
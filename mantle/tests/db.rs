@@ -1,54 +1,127 @@
+use crate::common::db::{TestKey, TestValue};
 use crate::common::TestDir;
-use fake::{Dummy, Fake, Faker};
-use mantle_utilities::{db::sled_db::SledDb, javascript::javascript::JavaScriptFile};
-use mantle_utilities::db::Db;
-use serde::{Deserialize, Serialize};
-use std::thread;
+use fake::{Fake, Faker};
+use mantle_utilities::db::memory_db::MemoryDb;
+use mantle_utilities::db::sled_db::{SledDb, SledDbConfig};
+use mantle_utilities::db::{
+    Bucket, BucketEngine, Db, DbEngine, DbError, DbResult, ImportMode, Json,
+};
 
 mod common;
 
-type TestKey = String;
-
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Dummy)]
-struct TestValue {
-    number: u32,
-    string: String,
-    vector: Vec<String>,
-    optional: Option<Vec<u8>>,
-    boolean: bool,
+// Runs the conformance suite against a DbEngine implementation.
+macro_rules! db_engine_conformance_tests {
+    ($engine:ident, $open:expr) => {
+        mod $engine {
+            use super::*;
+            use crate::common::db as conformance;
+
+            #[test]
+            fn bucket_stores_data() {
+                conformance::bucket_stores_data($open);
+            }
+
+            #[test]
+            fn bucket_returns_none_without_data() {
+                conformance::bucket_returns_none_without_data($open);
+            }
+
+            #[test]
+            fn insert_returns_old_value() {
+                conformance::insert_returns_old_value($open);
+            }
+
+            #[test]
+            fn can_open_multiple_independent_buckets() {
+                conformance::can_open_multiple_independent_buckets($open);
+            }
+
+            #[test]
+            fn reopened_bucket_shares_data() {
+                conformance::reopened_bucket_shares_data($open);
+            }
+
+            #[test]
+            fn deletes_bucket() {
+                conformance::deletes_bucket($open);
+            }
+
+            #[test]
+            fn deleting_missing_bucket_returns_false() {
+                conformance::deleting_missing_bucket_returns_false($open);
+            }
+
+            #[test]
+            fn lists_bucket_ids() {
+                conformance::lists_bucket_ids($open);
+            }
+
+            #[test]
+            fn exports_and_imports_all_buckets() {
+                conformance::exports_and_imports_all_buckets($open);
+            }
+
+            #[test]
+            fn reports_bucket_stats() {
+                conformance::reports_bucket_stats($open);
+            }
+
+            #[test]
+            fn transaction_applies_all_changes() {
+                conformance::transaction_applies_all_changes($open);
+            }
+
+            #[test]
+            fn failed_transaction_changes_nothing() {
+                conformance::failed_transaction_changes_nothing($open);
+            }
+
+            #[test]
+            fn index_follows_writes() {
+                conformance::index_follows_writes($open);
+            }
+
+            #[test]
+            fn removes_item() {
+                conformance::removes_item($open);
+            }
+
+            #[test]
+            fn clears_bucket() {
+                conformance::clears_bucket($open);
+            }
+
+            #[test]
+            fn iterates() {
+                conformance::iterates($open);
+            }
+
+            #[test]
+            fn iterates_in_key_order() {
+                conformance::iterates_in_key_order($open);
+            }
+
+            #[test]
+            fn can_be_used_from_multiple_threads() {
+                conformance::can_be_used_from_multiple_threads($open);
+            }
+        }
+    };
 }
 
-#[test]
-fn bucket_stores_data() {
-    let db_dir = TestDir::new();
-    let bucket_id: String = Faker.fake();
-    let first_test_key: TestKey = Faker.fake();
-    let second_test_key: TestKey = Faker.fake();
-    let first_test_value: TestValue = Faker.fake();
-    let second_test_value: TestValue = Faker.fake();
-    let db = Db::new(Box::new(SledDb::open(&db_dir).unwrap()));
-    let bucket = db.open_bucket(bucket_id).unwrap();
-
-    bucket.insert(&first_test_key, &first_test_value).unwrap();
-    bucket.insert(&second_test_key, &second_test_value).unwrap();
+db_engine_conformance_tests!(sled_db, |dir: &TestDir| {
+    Box::new(SledDb::open(dir).unwrap()) as Box<dyn DbEngine>
+});
 
-    let got_first_value = bucket.get(&first_test_key).unwrap().unwrap();
-    let got_second_value = bucket.get(&second_test_key).unwrap().unwrap();
+db_engine_conformance_tests!(memory_db, |_: &TestDir| {
+    Box::new(MemoryDb::new()) as Box<dyn DbEngine>
+});
 
-    assert_eq!(got_first_value, first_test_value);
-    assert_eq!(got_second_value, second_test_value);
-}
-
-#[test]
-fn bucket_returns_none_without_data() {
-    let db_dir = TestDir::new();
-    let db = Db::new(Box::new(SledDb::open(&db_dir).unwrap()));
-    let bucket = db.open_bucket(Faker.fake::<String>()).unwrap();
-
-    let got: Option<TestValue> = bucket.get(&Faker.fake::<TestKey>()).unwrap();
-
-    assert!(got.is_none());
-}
+#[cfg(feature = "sqlite-impl")]
+db_engine_conformance_tests!(sqlite_db, |dir: &TestDir| {
+    let path = dir.as_ref().join("db.sqlite");
+    Box::new(mantle_utilities::db::sqlite_db::SqliteDb::open(path).unwrap()) as Box<dyn DbEngine>
+});
 
 #[test]
 fn data_retains_after_db_close() {
@@ -71,126 +144,455 @@ fn data_retains_after_db_close() {
 }
 
 #[test]
-fn can_open_multiple_independent_buckets() {
+fn sled_db_reports_size_on_disk() {
     let db_dir = TestDir::new();
-    let mut bucket_id: String = Faker.fake();
-    let test_key: TestKey = Faker.fake();
-    let first_test_value: TestValue = Faker.fake();
-    let second_test_value: TestValue = Faker.fake();
     let db = Db::new(Box::new(SledDb::open(&db_dir).unwrap()));
-    let first_bucket = db.open_bucket(&bucket_id).unwrap();
-    bucket_id.push(Faker.fake());
-    let second_bucket = db.open_bucket(bucket_id).unwrap();
+    let bucket = db.open_bucket(Faker.fake::<String>()).unwrap();
+    bucket
+        .insert(&Faker.fake::<TestKey>(), &Faker.fake::<TestValue>())
+        .unwrap();
+    db.flush().unwrap();
 
-    first_bucket.insert(&test_key, &first_test_value).unwrap();
-    second_bucket.insert(&test_key, &second_test_value).unwrap();
+    assert!(db.stats().unwrap().size_on_disk.unwrap() > 0);
+}
+
+#[test]
+fn memory_db_has_no_size_on_disk() {
+    let db = Db::new(Box::new(MemoryDb::new()));
+
+    assert_eq!(db.stats().unwrap().size_on_disk, None);
+}
 
-    let got_first_value = first_bucket.get(&test_key).unwrap();
-    let got_second_value = second_bucket.get(&test_key).unwrap();
+#[test]
+fn sled_db_compaction_keeps_data() {
+    let db_dir = TestDir::new();
+    let db_path = db_dir.as_ref().join("db");
+    let config = SledDbConfig::default();
+    let data: Vec<(String, TestKey, TestValue)> =
+        std::iter::repeat_with(|| Faker.fake()).take(10).collect();
+    {
+        let db = Db::new(Box::new(
+            SledDb::open_with_config(&db_path, &config).unwrap(),
+        ));
+        for (bucket_id, key, value) in data.iter() {
+            db.open_bucket(bucket_id)
+                .unwrap()
+                .insert(key, value)
+                .unwrap();
+        }
+    }
 
-    assert_eq!(got_first_value, Some(first_test_value));
-    assert_eq!(got_second_value, Some(second_test_value));
+    SledDb::compact(&db_path, &config).unwrap();
+
+    let db = Db::new(Box::new(
+        SledDb::open_with_config(&db_path, &config).unwrap(),
+    ));
+    for (bucket_id, key, value) in data {
+        let bucket: Bucket<TestKey, TestValue> = db.open_bucket(bucket_id).unwrap();
+        assert_eq!(bucket.get(&key).unwrap(), Some(value));
+    }
 }
 
 #[test]
-fn deletes_bucket() {
+fn sled_db_opens_with_size_limit() {
     let db_dir = TestDir::new();
+    let db_path = db_dir.as_ref().join("db");
+    let mut config = SledDbConfig {
+        cache_capacity: Some(1024 * 1024),
+        flush_every_ms: Some(100),
+        low_space: true,
+        size_limit: None,
+    };
+    let (key, value): (TestKey, TestValue) = Faker.fake();
     let bucket_id: String = Faker.fake();
-    let test_key: TestKey = Faker.fake();
-    let test_value: TestValue = Faker.fake();
-    let db = Db::new(Box::new(SledDb::open(&db_dir).unwrap()));
+    {
+        let db = Db::new(Box::new(
+            SledDb::open_with_config(&db_path, &config).unwrap(),
+        ));
+        // Removed values keep taking space until the db is compacted.
+        // They fill a few segments, so the directory shrinks whenever sled flushed them.
+        let removed: Bucket<String, Vec<u8>> = db.open_bucket("removed").unwrap();
+        for i in 0..1000 {
+            removed.insert(&format!("{i}"), &vec![0; 4096]).unwrap();
+        }
+        for i in 0..1000 {
+            removed.remove(&format!("{i}")).unwrap();
+        }
+        let bucket = db.open_bucket(&bucket_id).unwrap();
+        bucket.insert(&key, &value).unwrap();
+    }
+    let size_before = dir_size(&db_path);
+
+    // The directory is above the limit, so it's compacted on open.
+    config.size_limit = Some(size_before - 1);
+    let db = Db::new(Box::new(
+        SledDb::open_with_config(&db_path, &config).unwrap(),
+    ));
+
+    assert!(dir_size(&db_path) < size_before);
+    let bucket: Bucket<TestKey, TestValue> = db.open_bucket(&bucket_id).unwrap();
+    assert_eq!(bucket.get(&key).unwrap(), Some(value));
+}
+
+#[test]
+fn sled_db_recovers_interrupted_compaction() {
+    let db_dir = TestDir::new();
+    let db_path = db_dir.as_ref().join("db");
+    let (key, value): (TestKey, TestValue) = Faker.fake();
+    let bucket_id: String = Faker.fake();
+    {
+        let db = Db::new(Box::new(SledDb::open(&db_path).unwrap()));
+        db.open_bucket(&bucket_id)
+            .unwrap()
+            .insert(&key, &value)
+            .unwrap();
+    }
+    // Compaction died after the original directory was moved away.
+    std::fs::rename(&db_path, db_dir.as_ref().join("db.old")).unwrap();
+
+    let db = Db::new(Box::new(
+        SledDb::open_with_config(&db_path, &SledDbConfig::default()).unwrap(),
+    ));
+    let bucket: Bucket<TestKey, TestValue> = db.open_bucket(&bucket_id).unwrap();
+    assert_eq!(bucket.get(&key).unwrap(), Some(value));
+}
+
+#[test]
+fn import_with_merge_mode_keeps_existing_data() {
+    let (existing_key, existing_value): (TestKey, TestValue) = Faker.fake();
+    let (imported_key, imported_value): (TestKey, TestValue) = Faker.fake();
+    let bucket_id: String = Faker.fake();
+    let archive = archive_with(&bucket_id, &imported_key, &imported_value);
+    let db = Db::new(Box::new(MemoryDb::new()));
     let bucket = db.open_bucket(&bucket_id).unwrap();
-    bucket.insert(&test_key, &test_value).unwrap();
-    drop(bucket);
+    bucket.insert(&existing_key, &existing_value).unwrap();
 
-    db.delete_bucket(&bucket_id).unwrap();
+    db.import(archive.as_slice(), ImportMode::Merge).unwrap();
+
+    assert_eq!(bucket.get(&existing_key).unwrap(), Some(existing_value));
+    assert_eq!(bucket.get(&imported_key).unwrap(), Some(imported_value));
+}
+
+#[test]
+fn import_with_replace_mode_removes_existing_data() {
+    let (existing_key, existing_value): (TestKey, TestValue) = Faker.fake();
+    let (imported_key, imported_value): (TestKey, TestValue) = Faker.fake();
+    let (bucket_id, existing_bucket_id): (String, String) = Faker.fake();
+    let archive = archive_with(&bucket_id, &imported_key, &imported_value);
+    let db = Db::new(Box::new(MemoryDb::new()));
+    db.open_bucket(&existing_bucket_id)
+        .unwrap()
+        .insert(&existing_key, &existing_value)
+        .unwrap();
+
+    db.import(archive.as_slice(), ImportMode::Replace).unwrap();
+
+    let bucket = db.open_bucket(&bucket_id).unwrap();
+    assert_eq!(db.bucket_ids().unwrap(), vec![bucket_id]);
+    assert_eq!(bucket.get(&imported_key).unwrap(), Some(imported_value));
+}
+
+#[test]
+fn import_rejects_corrupted_archive() {
+    let (key, value): (TestKey, TestValue) = Faker.fake();
+    let (existing_key, existing_value): (TestKey, TestValue) = Faker.fake();
+    let bucket_id: String = Faker.fake();
+    let mut archive = archive_with(&bucket_id, &key, &value);
+    let middle = archive.len() / 2;
+    archive[middle] = !archive[middle];
+    let db = Db::new(Box::new(MemoryDb::new()));
     let bucket = db.open_bucket(&bucket_id).unwrap();
-    let got_value: Option<TestValue> = bucket.get(&test_key).unwrap();
+    bucket.insert(&existing_key, &existing_value).unwrap();
 
-    assert!(got_value.is_none());
+    let result = db.import(archive.as_slice(), ImportMode::Replace);
+
+    assert!(matches!(result, Err(DbError::ArchiveError(_))));
+    assert_eq!(bucket.get(&existing_key).unwrap(), Some(existing_value));
 }
 
 #[test]
-fn removes_item() {
+fn exports_and_imports_via_file() {
     let db_dir = TestDir::new();
-    let test_key: TestKey = Faker.fake();
-    let db = Db::new(Box::new(SledDb::open(&db_dir).unwrap()));
-    let bucket = db.open_bucket(Faker.fake::<String>()).unwrap();
+    let archive_path = db_dir.as_ref().join("archive");
+    let (key, value): (TestKey, TestValue) = Faker.fake();
+    let bucket_id: String = Faker.fake();
+    let db = Db::new(Box::new(MemoryDb::new()));
+    db.open_bucket(&bucket_id)
+        .unwrap()
+        .insert(&key, &value)
+        .unwrap();
 
-    bucket
-        .insert(&test_key, &Faker.fake::<TestValue>())
+    db.export_to_file(&archive_path).unwrap();
+    let imported_db = Db::new(Box::new(MemoryDb::new()));
+    imported_db
+        .import_from_file(&archive_path, ImportMode::Merge)
         .unwrap();
-    bucket.remove(&test_key).unwrap();
-    let got_value = bucket.get(&test_key).unwrap();
 
-    assert!(got_value.is_none());
+    let bucket = imported_db.open_bucket(&bucket_id).unwrap();
+    assert_eq!(bucket.get(&key).unwrap(), Some(value));
 }
 
 #[test]
-fn clears_bucket() {
+fn export_fails_for_engine_without_bucket_ids() {
+    let (key, value): (TestKey, TestValue) = Faker.fake();
+    let bucket_id: String = Faker.fake();
+    let db = Db::new(Box::new(EngineWithoutBucketIds(MemoryDb::new())));
+    db.open_bucket(&bucket_id)
+        .unwrap()
+        .insert(&key, &value)
+        .unwrap();
+
+    let result = db.export(Vec::new());
+
+    assert!(matches!(result, Err(DbError::DbEngineError(_))));
+}
+
+/// Only implements the required methods of [DbEngine].
+#[derive(Debug)]
+struct EngineWithoutBucketIds(MemoryDb);
+
+impl DbEngine for EngineWithoutBucketIds {
+    fn open_bucket(&self, id: &str) -> DbResult<Box<dyn BucketEngine>> {
+        self.0.open_bucket(id)
+    }
+
+    fn delete_bucket(&self, id: &str) -> DbResult<bool> {
+        self.0.delete_bucket(id)
+    }
+}
+
+#[cfg(feature = "sqlite-impl")]
+#[test]
+fn migrates_all_buckets_from_sled_to_sqlite() {
+    use mantle_utilities::db::sqlite_db::SqliteDb;
+
     let db_dir = TestDir::new();
-    let test_key: TestKey = Faker.fake();
-    let db = Db::new(Box::new(SledDb::open(&db_dir).unwrap()));
-    let bucket = db.open_bucket(Faker.fake::<String>()).unwrap();
+    let sled_path = db_dir.as_ref().join("sled");
+    let data: Vec<(String, TestKey, TestValue)> =
+        std::iter::repeat_with(|| Faker.fake()).take(10).collect();
+    let sled_db = Db::new(Box::new(SledDb::open(&sled_path).unwrap()));
+    for (bucket_id, key, value) in data.iter() {
+        sled_db
+            .open_bucket(bucket_id)
+            .unwrap()
+            .insert(key, value)
+            .unwrap();
+    }
+    drop(sled_db);
+
+    let sqlite = SqliteDb::open(db_dir.as_ref().join("db.sqlite")).unwrap();
+    let migrated = sqlite.migrate_from_sled(&sled_path).unwrap();
+    let sqlite_db = Db::new(Box::new(sqlite));
+
+    assert_eq!(migrated, data.len());
+    for (bucket_id, key, value) in data {
+        let bucket = sqlite_db.open_bucket(bucket_id).unwrap();
+        assert_eq!(bucket.get(&key).unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn bucket_stores_json_values() {
+    let db = Db::new(Box::new(MemoryDb::new()));
+    let bucket: Bucket<String, serde_json::Value, Json> =
+        db.open_bucket_with_codec("json").unwrap();
+    let value = serde_json::json!({"name": "Kitchen", "devices": [1, 2], "on": true});
+    bucket.insert(&"room".to_string(), &value).unwrap();
+    bucket
+        .add_index("by_name", |value: &serde_json::Value| value["name"].clone())
+        .unwrap();
+
+    assert_eq!(
+        bucket.get(&"room".to_string()).unwrap(),
+        Some(value.clone())
+    );
+    assert_eq!(
+        bucket
+            .values()
+            .unwrap()
+            .collect::<DbResult<Vec<_>>>()
+            .unwrap(),
+        vec![value.clone()]
+    );
+    assert_eq!(
+        bucket
+            .index("by_name")
+            .get(&serde_json::json!("Kitchen"))
+            .unwrap(),
+        vec![value.clone()]
+    );
+    assert_eq!(bucket.remove(&"room".to_string()).unwrap(), Some(value));
+}
+
+#[test]
+fn index_is_built_from_existing_records() {
+    let (key, value): (TestKey, TestValue) = Faker.fake();
+    let db = Db::new(Box::new(MemoryDb::new()));
+    let bucket: Bucket<TestKey, TestValue> = db.open_bucket(Faker.fake::<String>()).unwrap();
+    bucket.insert(&key, &value).unwrap();
+
+    bucket
+        .add_index("by_string", |value: &TestValue| value.string.clone())
+        .unwrap();
+
+    let got = bucket.index("by_string").get(&value.string).unwrap();
+    assert_eq!(got, vec![value]);
+}
 
+#[test]
+fn index_is_shared_by_buckets_with_the_same_id() {
+    let (key, value): (TestKey, TestValue) = Faker.fake();
+    let bucket_id: String = Faker.fake();
+    let db = Db::new(Box::new(MemoryDb::new()));
+    let bucket: Bucket<TestKey, TestValue> = db.open_bucket(&bucket_id).unwrap();
     bucket
-        .insert(&test_key, &Faker.fake::<TestValue>())
+        .add_index("by_number", |value: &TestValue| value.number)
         .unwrap();
+
+    let other_bucket: Bucket<TestKey, TestValue> = db.clone().open_bucket(&bucket_id).unwrap();
+    other_bucket.insert(&key, &value).unwrap();
+
+    let got = bucket.index("by_number").keys(&value.number).unwrap();
+    assert_eq!(got, vec![key]);
+}
+
+#[test]
+fn indexes_of_buckets_with_joined_ids_are_separate() {
+    let (key, value): (TestKey, TestValue) = Faker.fake();
+    let db = Db::new(Box::new(MemoryDb::new()));
+    let bucket: Bucket<TestKey, TestValue> = db.open_bucket("a__b").unwrap();
+    bucket
+        .add_index("c", |value: &TestValue| value.number)
+        .unwrap();
+    let other_bucket: Bucket<TestKey, TestValue> = db.open_bucket("a").unwrap();
+    other_bucket
+        .add_index("b__c", |value: &TestValue| value.number)
+        .unwrap();
+
+    bucket.insert(&key, &value).unwrap();
+
+    assert_eq!(bucket.index("c").keys(&value.number).unwrap(), vec![key]);
+    assert!(other_bucket
+        .index("b__c")
+        .keys(&value.number)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn undeclared_index_returns_error() {
+    let db = Db::new(Box::new(MemoryDb::new()));
+    let bucket: Bucket<TestKey, TestValue> = db.open_bucket(Faker.fake::<String>()).unwrap();
+
+    let result = bucket.index("by_number").get(&Faker.fake::<u32>());
+
+    assert!(matches!(result, Err(DbError::IndexNotFound(name)) if name == "by_number"));
+}
+
+#[test]
+fn clear_and_delete_remove_index_data() {
+    let (key, value): (TestKey, TestValue) = Faker.fake();
+    let bucket_id: String = Faker.fake();
+    let db = Db::new(Box::new(MemoryDb::new()));
+    let bucket: Bucket<TestKey, TestValue> = db.open_bucket(&bucket_id).unwrap();
+    bucket
+        .add_index("by_number", |value: &TestValue| value.number)
+        .unwrap();
+    bucket.insert(&key, &value).unwrap();
+
     bucket.clear().unwrap();
-    let got_value = bucket.get(&test_key).unwrap();
+    assert!(bucket
+        .index("by_number")
+        .get(&value.number)
+        .unwrap()
+        .is_empty());
 
-    assert!(got_value.is_none());
+    bucket.insert(&key, &value).unwrap();
+    db.delete_bucket(&bucket_id).unwrap();
+    assert!(db.bucket_ids().unwrap().is_empty());
 }
 
 #[test]
-fn iterates() {
-    let db_dir = TestDir::new();
-    let mut data: Vec<(TestKey, TestValue)> =
-        std::iter::repeat_with(|| Faker.fake()).take(100).collect();
-    let db = Db::new(Box::new(SledDb::open(&db_dir).unwrap()));
-    let bucket = db.open_bucket(&Faker.fake::<String>()).unwrap();
-    for item in data.iter() {
-        bucket.insert(&item.0, &item.1).unwrap();
-    }
+fn index_buckets_are_reserved_and_not_exported() {
+    let (key, value): (TestKey, TestValue) = Faker.fake();
+    let bucket_id: String = Faker.fake();
+    let db = Db::new(Box::new(MemoryDb::new()));
+    let bucket: Bucket<TestKey, TestValue> = db.open_bucket(&bucket_id).unwrap();
+    bucket
+        .add_index("by_number", |value: &TestValue| value.number)
+        .unwrap();
+    bucket.insert(&key, &value).unwrap();
 
-    for item in bucket.iter().unwrap().map(|res| res.unwrap()) {
-        let idx = data.iter().position(|i| i == &item).unwrap();
-        data.remove(idx);
-    }
+    let mut archive = Vec::new();
+    db.export(&mut archive).unwrap();
+    let imported_db = Db::new(Box::new(MemoryDb::new()));
+    imported_db
+        .import(archive.as_slice(), ImportMode::Merge)
+        .unwrap();
 
-    assert!(data.is_empty());
+    assert_eq!(db.bucket_ids().unwrap(), vec![bucket_id.clone()]);
+    let imported_ids: Vec<String> = imported_db
+        .stats()
+        .unwrap()
+        .buckets
+        .into_iter()
+        .map(|stats| stats.id)
+        .collect();
+    assert_eq!(imported_ids, vec![bucket_id]);
+    let result =
+        db.open_bucket::<TestKey, TestValue>(format!("__index__{}", Faker.fake::<String>()));
+    assert!(matches!(result, Err(DbError::ReservedBucketId(_))));
 }
 
 #[test]
-#[allow(clippy::redundant_clone)]
-fn can_be_used_from_multiple_threads() {
-    let db_dir = TestDir::new();
+fn import_rebuilds_declared_indexes() {
+    let (key, value): (TestKey, TestValue) = Faker.fake();
     let bucket_id: String = Faker.fake();
-    let (first_key, first_value): (TestKey, TestValue) = Faker.fake();
-    let (second_key, second_value): (TestKey, TestValue) = Faker.fake();
-    let db = Db::new(Box::new(SledDb::open(&db_dir).unwrap()));
-    let cloned_db = db.clone();
-    let bucket = db.open_bucket(&bucket_id).unwrap();
-    let cloned_bucket = bucket.clone();
-    thread::scope(|scope| {
-        scope.spawn(|| {
-            let bucket = cloned_db.open_bucket(&bucket_id).unwrap();
-            bucket.insert(&first_key, &first_value).unwrap();
-        });
-        scope.spawn(|| {
-            cloned_bucket.insert(&second_key, &second_value).unwrap();
-        });
-    });
+    let archive = archive_with(&bucket_id, &key, &value);
+    let db = Db::new(Box::new(MemoryDb::new()));
+    let bucket: Bucket<TestKey, TestValue> = db.open_bucket(&bucket_id).unwrap();
+    bucket
+        .add_index("by_number", |value: &TestValue| value.number)
+        .unwrap();
 
-    assert_eq!(Some(first_value), bucket.get(&first_key).unwrap());
-    assert_eq!(Some(second_value), bucket.get(&second_key).unwrap());
+    db.import(archive.as_slice(), ImportMode::Merge).unwrap();
+
+    let got = bucket.index("by_number").keys(&value.number).unwrap();
+    assert_eq!(got, vec![key]);
+}
+
+fn archive_with(bucket_id: &str, key: &TestKey, value: &TestValue) -> Vec<u8> {
+    let db = Db::new(Box::new(MemoryDb::new()));
+    db.open_bucket(bucket_id)
+        .unwrap()
+        .insert(key, value)
+        .unwrap();
+    let mut archive = Vec::new();
+    db.export(&mut archive).unwrap();
+    archive
+}
+
+fn dir_size(path: &std::path::Path) -> u64 {
+    std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            let metadata = entry.metadata().unwrap();
+            if metadata.is_dir() {
+                dir_size(&entry.path())
+            } else {
+                metadata.len()
+            }
+        })
+        .sum()
 }
 
 #[cfg(feature = "http-impl")]
 #[test]
 fn javascript_file_deps_download() {
     use mantle_utilities::http::reqwest_client::ReqwestClient;
-    
+    use mantle_utilities::javascript::javascript::JavaScriptFile;
+
     ReqwestClient::set_as_global_http_callback();
     let base_path = "./src".to_string();
     let db_engine_implementation = SledDb::open("javascript").unwrap();
@@ -199,6 +601,6 @@ fn javascript_file_deps_download() {
     let db = Db::new(Box::new(db_engine_implementation));
     let bucket = db.open_bucket("files").unwrap();
     JavaScriptFile::update_db_from_manifest(&bucket, base_path, download_url, manifest_name);
-    
+
     dbg!(bucket);
-}
\ No newline at end of file
+}
@@ -0,0 +1,46 @@
+//! The app lifecycle is global, so it's tested apart from the other poll tests.
+use mantle_utilities::clock::MockClock;
+use mantle_utilities::poll_manager::lifecycle::{set_app_in_background, set_network_available};
+use mantle_utilities::poll_manager::{PollConfig, PollManager};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+#[test]
+fn app_lifecycle_pauses_polls() {
+    let clock = Arc::new(MockClock::new());
+    let manager = PollManager::with_clock(clock.clone());
+    let (tx, rx) = mpsc::channel();
+
+    manager.add_poll(PollConfig {
+        sleep_time: Some(1),
+        callback: Some(Box::new(move |_| {
+            let _ = tx.send(());
+        })),
+        respondent: Some(Box::new(|| ())),
+        ..Default::default()
+    });
+    manager.start_polling();
+    assert!(rx.recv().is_ok());
+
+    set_app_in_background(true);
+    set_network_available(false);
+    assert!(manager.is_paused());
+    manager.wait_until_idle();
+    while rx.try_recv().is_ok() {}
+
+    set_app_in_background(false);
+    clock.advance(Duration::from_secs(1));
+    manager.wait_until_idle();
+    assert!(rx.try_recv().is_err());
+
+    set_network_available(true);
+    assert!(!manager.is_paused());
+    clock.advance(Duration::from_millis(1));
+    assert!(rx.recv().is_ok());
+
+    set_app_in_background(true);
+    let paused_manager = PollManager::<()>::new();
+    assert!(paused_manager.is_paused());
+    set_app_in_background(false);
+    manager.stop_polling();
+}
@@ -1,11 +1,30 @@
-use mantle_utilities::poll_manager::{PollConfig, PollManager};
-use std::sync::mpsc;
-use std::thread::sleep;
-use std::time::Duration;
+use chrono::TimeZone;
+use mantle_utilities::clock::{Clock, MockClock};
+use mantle_utilities::db::memory_db::MemoryDb;
+use mantle_utilities::db::Db;
+use mantle_utilities::error::MantleResultError;
+use mantle_utilities::poll_manager::{
+    ChangeFilter, FailurePolicy, PollConfig, PollManager, PollResult, PollSchedule, WallClock,
+};
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering::SeqCst;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, SystemTime};
+
+fn mock_clock_manager<T: 'static>() -> (PollManager<T>, Arc<MockClock>) {
+    let clock = Arc::new(MockClock::new());
+    (PollManager::with_clock(clock.clone()), clock)
+}
+
+/// Advances the clock once the manager is idle, so polls rescheduled by their last run are due after it.
+fn advance<T: 'static>(manager: &PollManager<T>, clock: &MockClock, millis: u64) {
+    manager.wait_until_idle();
+    clock.advance(Duration::from_millis(millis));
+}
 
 #[test]
 fn send_value() {
-    let manager = PollManager::new();
+    let (manager, clock) = mock_clock_manager();
     let (tx, rx) = mpsc::channel();
 
     manager.add_poll(PollConfig {
@@ -15,16 +34,18 @@ fn send_value() {
             let _ = tx.send(value);
         })),
         respondent: Some(Box::new(|| 42)),
+        ..Default::default()
     });
     manager.start_polling();
 
     assert_eq!(42, rx.recv().unwrap());
+    advance(&manager, &clock, 1);
     assert_eq!(42, rx.recv().unwrap());
 }
 
 #[test]
 fn remove_poll() {
-    let manager = PollManager::new();
+    let (manager, _clock) = mock_clock_manager();
     let (tx, rx) = mpsc::channel();
 
     let id = manager.add_poll(PollConfig {
@@ -34,16 +55,15 @@ fn remove_poll() {
         ..Default::default()
     });
     manager.start_polling();
-    sleep(Duration::from_millis(10));
+    assert!(rx.recv().is_ok());
 
     manager.remove_poll(id);
-    assert!(rx.recv().is_ok());
     assert!(rx.recv().is_err());
 }
 
 #[test]
 fn update_poll() {
-    let manager = PollManager::new();
+    let (manager, _clock) = mock_clock_manager();
     let (tx, rx) = mpsc::channel();
     let (tx2, rx2) = mpsc::channel();
 
@@ -52,6 +72,7 @@ fn update_poll() {
         sleep_time: Some(50),
         callback: Some(Box::new(move |_| tx.send(()).unwrap())),
         respondent: Some(Box::new(|| ())),
+        ..Default::default()
     });
     manager.start_polling();
 
@@ -72,7 +93,7 @@ fn update_poll() {
 
 #[test]
 fn stop_polling() {
-    let manager = PollManager::new();
+    let (manager, clock) = mock_clock_manager();
     let (tx, rx) = mpsc::channel();
 
     manager.add_poll(PollConfig {
@@ -80,18 +101,21 @@ fn stop_polling() {
         sleep_time: Some(10),
         callback: Some(Box::new(move |_| tx.send(()).unwrap())),
         respondent: Some(Box::new(|| ())),
+        ..Default::default()
     });
     manager.start_polling();
 
     assert!(rx.recv().is_ok());
 
     manager.stop_polling();
-    assert!(rx.recv_timeout(Duration::from_millis(20)).is_err());
+    advance(&manager, &clock, 20);
+    manager.wait_until_idle();
+    assert!(rx.try_recv().is_err());
 }
 
 #[test]
 fn no_deadlock_when_manager_called_from_callback_or_respondent() {
-    let manager = PollManager::new();
+    let (manager, _clock) = mock_clock_manager();
     let (tx, rx) = mpsc::channel();
     let poll_manager_callback = manager.clone();
     let poll_manager_responder = manager.clone();
@@ -106,8 +130,608 @@ fn no_deadlock_when_manager_called_from_callback_or_respondent() {
         respondent: Some(Box::new(move || {
             poll_manager_responder.add_poll(PollConfig::default());
         })),
+        ..Default::default()
+    });
+    manager.start_polling();
+
+    assert!(rx.recv().is_ok());
+}
+
+#[test]
+fn respondent_driven_schedule_gets_respondent_results() {
+    let (manager, clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+    let (delay_tx, delay_rx) = mpsc::channel();
+    let delay_tx = std::sync::Mutex::new(delay_tx);
+
+    manager.add_poll(PollConfig {
+        schedule: Some(PollSchedule::RespondentDriven {
+            delay: Box::new(move |cleaning: &bool| {
+                let _ = delay_tx.lock().unwrap().send(*cleaning);
+                if *cleaning {
+                    1
+                } else {
+                    1000
+                }
+            }),
+            fallback_delay: 1000,
+        }),
+        callback: Some(Box::new(move |cleaning| {
+            let _ = tx.send(cleaning);
+        })),
+        respondent: Some(Box::new(|| true)),
+        ..Default::default()
+    });
+    manager.start_polling();
+
+    // The short delay for the result lets the poll run again right away.
+    for _ in 0..3 {
+        assert!(rx.recv().unwrap());
+        assert!(delay_rx.recv().unwrap());
+        advance(&manager, &clock, 1);
+    }
+    manager.stop_polling();
+}
+
+#[test]
+fn schedule_replaces_sleep_time() {
+    let (manager, clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+
+    manager.add_poll(PollConfig {
+        sleep_time: Some(60_000),
+        schedule: Some(PollSchedule::Jittered {
+            delay: 5,
+            jitter: 5,
+        }),
+        callback: Some(Box::new(move |_| {
+            let _ = tx.send(());
+        })),
+        respondent: Some(Box::new(|| ())),
+        ..Default::default()
+    });
+    manager.start_polling();
+
+    assert!(rx.recv().is_ok());
+    advance(&manager, &clock, 10);
+    assert!(rx.recv().is_ok());
+    manager.stop_polling();
+}
+
+#[test]
+fn poll_now_runs_poll_without_waiting() {
+    let (manager, _clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+
+    let id = manager.add_poll(PollConfig {
+        sleep_time: Some(60_000),
+        callback: Some(Box::new(move |_| {
+            let _ = tx.send(());
+        })),
+        respondent: Some(Box::new(|| ())),
+        ..Default::default()
+    });
+    manager.start_polling();
+    assert!(rx.recv_timeout(Duration::from_millis(500)).is_ok());
+
+    manager.poll_now(id);
+
+    assert!(rx.recv_timeout(Duration::from_millis(500)).is_ok());
+    manager.stop_polling();
+}
+
+#[test]
+fn update_poll_takes_effect_without_waiting() {
+    let (manager, _clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+    let (call_tx, call_rx) = mpsc::channel();
+    let call_tx = std::sync::Mutex::new(call_tx);
+
+    let id = manager.add_poll(PollConfig {
+        sleep_time: Some(60_000),
+        respondent: Some(Box::new(move || {
+            let _ = call_tx.lock().unwrap().send(());
+            1
+        })),
+        ..Default::default()
+    });
+    manager.start_polling();
+    call_rx.recv().unwrap();
+
+    manager.update_poll(
+        id,
+        PollConfig {
+            callback: Some(Box::new(move |value| {
+                let _ = tx.send(value);
+            })),
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(rx.recv_timeout(Duration::from_millis(500)), Ok(1));
+    manager.stop_polling();
+}
+
+#[test]
+fn stopped_poll_restarts_without_waiting() {
+    let (manager, _clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+
+    manager.add_poll(PollConfig {
+        sleep_time: Some(60_000),
+        callback: Some(Box::new(move |_| {
+            let _ = tx.send(());
+        })),
+        respondent: Some(Box::new(|| ())),
+        ..Default::default()
     });
     manager.start_polling();
+    assert!(rx.recv_timeout(Duration::from_millis(500)).is_ok());
+
+    manager.stop_polling();
+    manager.start_polling();
+
+    assert!(rx.recv_timeout(Duration::from_millis(500)).is_ok());
+    manager.stop_polling();
+}
+
+#[test]
+fn polls_outnumbering_worker_threads_all_run() {
+    let (manager, _clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+    let poll_count = 200;
 
-    assert!(rx.recv_timeout(Duration::from_millis(20)).is_ok());
+    for id in 0..poll_count {
+        let tx = tx.clone();
+        manager.add_poll(PollConfig {
+            sleep_time: Some(60_000),
+            callback: Some(Box::new(move |id| {
+                let _ = tx.send(id);
+            })),
+            respondent: Some(Box::new(move || id)),
+            ..Default::default()
+        });
+    }
+    manager.start_polling();
+
+    let mut ids: Vec<_> = (0..poll_count)
+        .map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap())
+        .collect();
+    ids.sort();
+    assert_eq!(ids, (0..poll_count).collect::<Vec<_>>());
+    manager.stop_polling();
+}
+
+fn parse_error() -> Box<dyn MantleResultError> {
+    Box::new(serde_json::from_str::<u32>("not a number").unwrap_err())
+}
+
+#[test]
+fn respondent_errors_go_to_error_callback() {
+    let (manager, clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+    let (error_tx, error_rx) = mpsc::channel();
+    let calls = AtomicU32::new(0);
+
+    let id = manager.add_poll(PollConfig {
+        sleep_time: Some(1),
+        callback: Some(Box::new(move |value| {
+            let _ = tx.send(value);
+        })),
+        fallible_respondent: Some(Box::new(move || -> PollResult<u32> {
+            match calls.fetch_add(1, SeqCst) {
+                0 => Err(parse_error()),
+                call => Ok(call),
+            }
+        })),
+        error_callback: Some(Box::new(move |err| {
+            let _ = error_tx.send(err.error_type());
+        })),
+        ..Default::default()
+    });
+    manager.start_polling();
+
+    assert_eq!(error_rx.recv().unwrap(), "syntax");
+    advance(&manager, &clock, 1);
+    assert_eq!(rx.recv().unwrap(), 1);
+    advance(&manager, &clock, 1);
+    assert_eq!(rx.recv().unwrap(), 2);
+    manager.stop_polling();
+    manager.wait_until_idle();
+
+    let stats = manager.poll_stats(id).unwrap();
+    assert_eq!(stats.failures, 1);
+    assert_eq!(stats.consecutive_failures, 0);
+    assert_eq!(stats.successes, 2);
+    assert!(stats.last_success >= stats.last_failure);
+}
+
+#[test]
+fn failure_policy_pauses_poll() {
+    let (manager, clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+
+    let id = manager.add_poll(PollConfig::<()> {
+        sleep_time: Some(1),
+        fallible_respondent: Some(Box::new(|| Err(parse_error()))),
+        error_callback: Some(Box::new(move |_| {
+            let _ = tx.send(());
+        })),
+        failure_policy: Some(FailurePolicy::PauseAfter(3)),
+        ..Default::default()
+    });
+    manager.start_polling();
+
+    for _ in 0..3 {
+        assert!(rx.recv().is_ok());
+        advance(&manager, &clock, 1);
+    }
+    advance(&manager, &clock, 1_000);
+    manager.wait_until_idle();
+    assert!(rx.try_recv().is_err());
+    assert_eq!(manager.poll_stats(id).unwrap().consecutive_failures, 3);
+
+    manager.update_poll(
+        id,
+        PollConfig {
+            on: Some(true),
+            ..Default::default()
+        },
+    );
+    assert!(rx.recv().is_ok());
+    manager.stop_polling();
+}
+
+#[test]
+fn failure_policy_removes_poll() {
+    let (manager, clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+
+    let id = manager.add_poll(PollConfig::<()> {
+        sleep_time: Some(1),
+        fallible_respondent: Some(Box::new(|| Err(parse_error()))),
+        error_callback: Some(Box::new(move |_| tx.send(()).unwrap())),
+        failure_policy: Some(FailurePolicy::RemoveAfter(2)),
+        ..Default::default()
+    });
+    manager.start_polling();
+
+    assert!(rx.recv().is_ok());
+    advance(&manager, &clock, 1);
+    assert!(rx.recv().is_ok());
+    // The error callback is dropped with the removed poll.
+    assert!(rx.recv().is_err());
+    assert!(manager.poll_stats(id).is_none());
+}
+
+#[test]
+fn paused_poll_keeps_time_left() {
+    let (manager, clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+
+    manager.add_poll(PollConfig {
+        sleep_time: Some(600),
+        callback: Some(Box::new(move |_| {
+            let _ = tx.send(());
+        })),
+        respondent: Some(Box::new(|| ())),
+        ..Default::default()
+    });
+    manager.start_polling();
+    assert!(rx.recv().is_ok());
+    advance(&manager, &clock, 100);
+
+    manager.pause();
+    assert!(manager.is_paused());
+    advance(&manager, &clock, 60_000);
+    manager.wait_until_idle();
+    assert!(rx.try_recv().is_err());
+
+    manager.resume();
+    advance(&manager, &clock, 499);
+    manager.wait_until_idle();
+    assert!(rx.try_recv().is_err());
+    clock.advance(Duration::from_millis(1));
+    assert!(rx.recv().is_ok());
+    manager.stop_polling();
+}
+
+/// Returns about how long the clock can be advanced before its Instant overflows.
+fn time_to_instant_end(clock: &MockClock) -> Duration {
+    let now = clock.now();
+    let mut time_left = Duration::ZERO;
+    let mut step = Duration::from_secs(u64::MAX / 2);
+    while step >= Duration::from_secs(1) {
+        match time_left
+            .checked_add(step)
+            .filter(|longer| now.checked_add(*longer).is_some())
+        {
+            Some(longer) => time_left = longer,
+            None => step /= 2,
+        }
+    }
+    time_left
+}
+
+#[test]
+fn resumed_poll_without_due_time_stays_unscheduled() {
+    let (manager, clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+    clock.advance(time_to_instant_end(&clock) - Duration::from_secs(10_000_000_000));
+
+    manager.add_poll(PollConfig {
+        // Overflows the Instant near its end, so the poll has no due time after its first run.
+        sleep_time: Some(u64::MAX),
+        callback: Some(Box::new(move |_| {
+            let _ = tx.send(());
+        })),
+        respondent: Some(Box::new(|| ())),
+        ..Default::default()
+    });
+    manager.start_polling();
+    assert!(rx.recv().is_ok());
+    manager.wait_until_idle();
+
+    manager.pause();
+    manager.resume();
+    advance(&manager, &clock, 60_000);
+    manager.wait_until_idle();
+    assert!(rx.try_recv().is_err());
+    manager.stop_polling();
+}
+
+#[test]
+fn background_poll_runs_while_paused() {
+    let (manager, clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+    let background_tx = tx.clone();
+
+    manager.add_poll(PollConfig {
+        sleep_time: Some(1),
+        callback: Some(Box::new(move |name| {
+            let _ = tx.send(name);
+        })),
+        respondent: Some(Box::new(|| "foreground")),
+        ..Default::default()
+    });
+    manager.add_poll(PollConfig {
+        sleep_time: Some(1),
+        callback: Some(Box::new(move |name| {
+            let _ = background_tx.send(name);
+        })),
+        respondent: Some(Box::new(|| "background")),
+        run_in_background: Some(true),
+        ..Default::default()
+    });
+    manager.pause();
+    manager.start_polling();
+
+    for _ in 0..5 {
+        assert_eq!(rx.recv().unwrap(), "background");
+        advance(&manager, &clock, 1);
+    }
+
+    manager.resume();
+    while rx.recv().unwrap() != "foreground" {
+        advance(&manager, &clock, 1);
+    }
+    manager.stop_polling();
+}
+
+fn counting_respondent(step: u32, max: u32) -> Box<dyn Fn() -> u32 + Send> {
+    let calls = AtomicU32::new(0);
+    Box::new(move || (calls.fetch_add(1, SeqCst) / step).min(max))
+}
+
+/// Runs the poll every millisecond until it called back `count` values.
+fn collect_values<T: 'static>(
+    manager: &PollManager<T>,
+    clock: &MockClock,
+    rx: &mpsc::Receiver<T>,
+    count: usize,
+) -> Vec<T> {
+    let mut values = Vec::new();
+    while values.len() < count {
+        advance(manager, clock, 1);
+        values.extend(rx.try_iter());
+    }
+    values
+}
+
+#[test]
+fn change_filter_skips_unchanged_results() {
+    let (manager, clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+
+    manager.add_poll(PollConfig {
+        sleep_time: Some(1),
+        callback: Some(Box::new(move |value| {
+            let _ = tx.send(value);
+        })),
+        respondent: Some(counting_respondent(3, u32::MAX)),
+        change_filter: Some(ChangeFilter::on_change()),
+        ..Default::default()
+    });
+    manager.start_polling();
+
+    let values = collect_values(&manager, &clock, &rx, 4);
+    manager.stop_polling();
+    assert_eq!(values, [0, 1, 2, 3]);
+}
+
+#[test]
+fn change_filter_uses_diff_function() {
+    let (manager, clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+
+    manager.add_poll(PollConfig {
+        sleep_time: Some(1),
+        callback: Some(Box::new(move |value| {
+            let _ = tx.send(value);
+        })),
+        respondent: Some(counting_respondent(1, u32::MAX)),
+        change_filter: Some(ChangeFilter::new(|previous: &u32, value| {
+            value.abs_diff(*previous) >= 5
+        })),
+        ..Default::default()
+    });
+    manager.start_polling();
+
+    let values = collect_values(&manager, &clock, &rx, 3);
+    manager.stop_polling();
+    assert_eq!(values, [0, 5, 10]);
+}
+
+#[test]
+fn debounce_calls_back_last_change_once() {
+    let (manager, clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+    let (call_tx, call_rx) = mpsc::channel();
+    let call_tx = std::sync::Mutex::new(call_tx);
+    let respondent = counting_respondent(1, 10);
+
+    manager.add_poll(PollConfig {
+        sleep_time: Some(1),
+        callback: Some(Box::new(move |value| {
+            let _ = tx.send(value);
+        })),
+        respondent: Some(Box::new(move || {
+            let _ = call_tx.lock().unwrap().send(());
+            respondent()
+        })),
+        change_filter: Some(ChangeFilter::on_change().debounce(100)),
+        ..Default::default()
+    });
+    manager.start_polling();
+
+    // Every run changes the result until it stays 10.
+    for _ in 0..=10 {
+        call_rx.recv().unwrap();
+        advance(&manager, &clock, 1);
+    }
+    manager.wait_until_idle();
+    assert!(rx.try_recv().is_err());
+
+    advance(&manager, &clock, 100);
+    assert_eq!(rx.recv().unwrap(), 10);
+    advance(&manager, &clock, 1_000);
+    manager.wait_until_idle();
+    assert!(rx.try_recv().is_err());
+    manager.stop_polling();
+}
+
+#[test]
+fn debounce_skips_result_changed_back_to_called_back_one() {
+    let (manager, clock) = mock_clock_manager();
+    let (tx, rx) = mpsc::channel();
+    let calls = AtomicU32::new(0);
+
+    manager.add_poll(PollConfig {
+        sleep_time: Some(60),
+        callback: Some(Box::new(move |value| {
+            let _ = tx.send(value);
+        })),
+        // The fourth run returns 2 and the fifth one 1 again, before 2 is called back.
+        respondent: Some(Box::new(move || match calls.fetch_add(1, SeqCst) {
+            3 => 2,
+            _ => 1,
+        })),
+        change_filter: Some(ChangeFilter::on_change().debounce(100)),
+        ..Default::default()
+    });
+    manager.start_polling();
+
+    for _ in 0..30 {
+        advance(&manager, &clock, 20);
+    }
+    manager.wait_until_idle();
+    manager.stop_polling();
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(), [1]);
+}
+
+/// Runs at every full 200 milliseconds of the wall clock.
+fn every_200_millis() -> WallClock {
+    WallClock::new(|after| {
+        let millis = after.timestamp_millis() / 200 * 200 + 200;
+        chrono::Utc.timestamp_millis_opt(millis).single()
+    })
+}
+
+fn send_on_call(tx: mpsc::Sender<()>, schedule: WallClock) -> PollConfig<()> {
+    PollConfig {
+        schedule: Some(PollSchedule::WallClock(schedule)),
+        callback: Some(Box::new(move |_| {
+            let _ = tx.send(());
+        })),
+        respondent: Some(Box::new(|| ())),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn wall_clock_poll_waits_for_its_time() {
+    let clock = Arc::new(MockClock::at(
+        SystemTime::UNIX_EPOCH + Duration::from_millis(1_000_050),
+    ));
+    let manager = PollManager::with_clock(clock.clone());
+    let (tx, rx) = mpsc::channel();
+
+    manager.add_poll(send_on_call(tx, every_200_millis()));
+    manager.start_polling();
+
+    advance(&manager, &clock, 149);
+    manager.wait_until_idle();
+    assert!(rx.try_recv().is_err());
+    for _ in 0..2 {
+        clock.advance(Duration::from_millis(1));
+        assert!(rx.recv().is_ok());
+        let time = chrono::DateTime::<chrono::Utc>::from(clock.system_time());
+        assert_eq!(time.timestamp_millis() % 200, 0);
+        advance(&manager, &clock, 199);
+        manager.wait_until_idle();
+        assert!(rx.try_recv().is_err());
+    }
+    manager.stop_polling();
+}
+
+#[test]
+fn wall_clock_poll_catches_up_missed_run() {
+    let db = Db::new(Box::new(MemoryDb::new()));
+    let hourly = || {
+        WallClock::cron("0 * * * *", chrono::Utc)
+            .unwrap()
+            .persist_runs(&db, "hourly")
+            .unwrap()
+    };
+    let (tx, rx) = mpsc::channel();
+    // 10:30 on some day.
+    let clock = Arc::new(MockClock::at(
+        SystemTime::UNIX_EPOCH + Duration::from_secs(19_000 * 86_400 + 10 * 3_600 + 1_800),
+    ));
+
+    let manager = PollManager::with_clock(clock.clone());
+    let id = manager.add_poll(send_on_call(tx.clone(), hourly()));
+    manager.start_polling();
+    manager.wait_until_idle();
+    assert!(rx.try_recv().is_err());
+    manager.poll_now(id);
+    assert!(rx.recv().is_ok());
+    manager.stop_polling();
+
+    // The last run is persisted, so the next manager waits for the next hour.
+    let manager = PollManager::with_clock(clock.clone());
+    manager.add_poll(send_on_call(tx.clone(), hourly()));
+    manager.start_polling();
+    advance(&manager, &clock, 29 * 60 * 1_000);
+    manager.wait_until_idle();
+    assert!(rx.try_recv().is_err());
+    manager.stop_polling();
+
+    // The app didn't run at 11:00 and 12:00.
+    clock.advance(Duration::from_secs(2 * 3_600));
+    let manager = PollManager::with_clock(clock);
+    manager.add_poll(send_on_call(tx, hourly()));
+    manager.start_polling();
+    assert!(rx.recv().is_ok());
+    manager.stop_polling();
 }
@@ -1,25 +1,38 @@
 use crate::common::storage::TestStorage;
 use fake::{Fake, Faker};
-use mantle_utilities::storage::{StorageDataValue, StorageDir, StorageInteract};
+use mantle_utilities::db::{Bucket, ImportMode, Json};
+use mantle_utilities::storage::{
+    ScopeId, Storage, StorageChange, StorageDataValue, StorageDir, StorageInteract,
+    StorageInteractError, StorageInteractResult, StorageKey, SELECTED_REGION, STORAGE_ACCOUNTS_DIR,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
 use std::ffi::OsString;
-use std::path::Path;
-use std::sync::{Arc, Barrier};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Barrier, Mutex};
 use std::{fs, thread};
 
 mod common;
 
 const APP_DIR: &str = "app";
 const USER_DIR: &str = "user";
-const TOP_LEVEL_DIR: &str = "cloudcore";
+const TOP_LEVEL_DIR: &str = "mantle-utilities";
+const LEGACY_TOP_LEVEL_DIR: &str = "cloudcore";
 const LOGGING_DIR: &str = "logging";
 const STORE_FILE: &str = ".store";
+const BACKUP_FILE: &str = ".store.bak";
 const DEFAULT_BYTES_IN_FILE: &str = r#"{"data":{}}"#;
 const REGION_KEY: &str = "countryRegionSelection";
+const VERSION_KEY: &str = "storageVersion";
+const LATEST_VERSION: u64 = 3;
 
 #[test]
 fn storage_dir_has_correct_layout() {
     let test_storage = TestStorage::new();
-    let path = test_storage.as_ref().parent_path();
+    let storage = test_storage.as_ref();
+    let path = storage.parent_path();
     let mut entries: Vec<OsString> = path
         .read_dir()
         .unwrap()
@@ -31,13 +44,13 @@ fn storage_dir_has_correct_layout() {
     assert_eq!(entries.len(), 2);
     assert_eq!(entries[0], APP_DIR);
     assert_eq!(entries[1], USER_DIR);
-    assert!(has_only_one_entry(path.join(APP_DIR), STORE_FILE));
-    assert!(has_only_one_entry(path.join(USER_DIR), STORE_FILE));
-
-    let app_content = fs::read_to_string(path.join(APP_DIR).join(STORE_FILE)).unwrap();
-    let user_content = fs::read_to_string(path.join(USER_DIR).join(STORE_FILE)).unwrap();
-    assert_eq!(DEFAULT_BYTES_IN_FILE, app_content);
-    assert_eq!(DEFAULT_BYTES_IN_FILE, user_content);
+    // Values are kept in the db, so no store files are written.
+    assert!(is_empty_dir(path.join(APP_DIR)));
+    assert!(is_empty_dir(path.join(USER_DIR)));
+    assert_eq!(
+        stored_values(storage, APP_DIR),
+        HashMap::from([(VERSION_KEY.to_string(), json!(LATEST_VERSION))])
+    );
 }
 
 #[test]
@@ -89,15 +102,493 @@ fn wipe_with_flag_does_not_remove_app_dir() {
 fn wipe_with_flag_retains_selected_region() {
     let test_storage = TestStorage::new();
     let storage = test_storage.as_ref();
-    storage.set_value(USER_DIR, REGION_KEY, "UK").unwrap();
+    storage.set_value(APP_DIR, REGION_KEY, "UK").unwrap();
 
     storage.wipe_storage(true, false).unwrap();
     storage.wipe_storage(false, false).unwrap();
 
-    let region = storage.get_value(USER_DIR, REGION_KEY).unwrap();
+    let region = storage.get_value(APP_DIR, REGION_KEY).unwrap();
     assert!(matches!(region, StorageDataValue::String(string) if string == "UK"));
 }
 
+#[test]
+fn wipe_with_flag_removes_selected_region_from_app_dir() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let key: String = Faker.fake();
+    storage.set_value(APP_DIR, REGION_KEY, "UK").unwrap();
+    storage.set_value(APP_DIR, &key, true).unwrap();
+
+    storage.wipe_storage(false, true).unwrap();
+
+    let region = storage.get_value(APP_DIR, REGION_KEY).unwrap();
+    assert!(matches!(region, StorageDataValue::Null));
+    let value = storage.get_value(APP_DIR, &key).unwrap();
+    assert!(matches!(value, StorageDataValue::Bool(true)));
+}
+
+#[test]
+fn migrates_legacy_workspace() {
+    let storage_path = TestStorage::gen_storage_path();
+    let legacy_path = storage_path.join(LEGACY_TOP_LEVEL_DIR);
+    let key: String = Faker.fake();
+    let value: String = Faker.fake();
+    let region: String = Faker.fake();
+    let user_store = json!({ "data": { &key: value, REGION_KEY: region } });
+    fs::create_dir_all(legacy_path.join(USER_DIR)).unwrap();
+    fs::create_dir_all(legacy_path.join(APP_DIR)).unwrap();
+    fs::write(
+        legacy_path.join(USER_DIR).join(STORE_FILE),
+        user_store.to_string(),
+    )
+    .unwrap();
+    fs::write(
+        legacy_path.join(APP_DIR).join(STORE_FILE),
+        DEFAULT_BYTES_IN_FILE,
+    )
+    .unwrap();
+
+    let test_storage = TestStorage::with_path(&storage_path);
+    let storage = test_storage.as_ref();
+
+    assert!(!legacy_path.try_exists().unwrap());
+    assert_eq!(storage.parent_path(), storage_path.join(TOP_LEVEL_DIR));
+    let got_value = storage.get_value(USER_DIR, &key).unwrap();
+    assert!(matches!(got_value, StorageDataValue::String(string) if string == value));
+    let got_region = storage.get_value(USER_DIR, REGION_KEY).unwrap();
+    assert!(matches!(got_region, StorageDataValue::Null));
+    assert_eq!(SELECTED_REGION.get(storage).unwrap(), Some(region));
+    let version = storage.get_value(APP_DIR, VERSION_KEY).unwrap();
+    assert_eq!(version, json!(LATEST_VERSION));
+}
+
+#[test]
+fn region_in_encrypted_user_store_moves_once_key_is_set() {
+    let encryption_key: [u8; 32] = Faker.fake();
+    let region: String = Faker.fake();
+    let key: String = Faker.fake();
+    let encrypted_storage = TestStorage::new();
+    encrypted_storage
+        .as_ref()
+        .set_encryption_key(USER_DIR, encryption_key);
+    encrypted_storage
+        .as_ref()
+        .touch_file_for_child(
+            USER_DIR,
+            Some(&json!({ "data": { REGION_KEY: &region } }).to_string()),
+        )
+        .unwrap();
+    let user_store = fs::read(
+        encrypted_storage
+            .as_ref()
+            .parent_path()
+            .join(USER_DIR)
+            .join(STORE_FILE),
+    )
+    .unwrap();
+    let storage_path = TestStorage::gen_storage_path();
+    let workspace_path = storage_path.join(TOP_LEVEL_DIR);
+    fs::create_dir_all(workspace_path.join(USER_DIR)).unwrap();
+    fs::create_dir_all(workspace_path.join(APP_DIR)).unwrap();
+    fs::write(workspace_path.join(USER_DIR).join(STORE_FILE), user_store).unwrap();
+    let app_store = json!({ "data": { VERSION_KEY: 1, &key: true } });
+    fs::write(
+        workspace_path.join(APP_DIR).join(STORE_FILE),
+        app_store.to_string(),
+    )
+    .unwrap();
+
+    let test_storage = TestStorage::with_path(&storage_path);
+    let storage = test_storage.as_ref();
+
+    // The missing key doesn't hold back the other migrations.
+    assert_eq!(
+        storage.get_value(APP_DIR, VERSION_KEY).unwrap(),
+        json!(LATEST_VERSION)
+    );
+    assert_eq!(storage.get_value(APP_DIR, &key).unwrap(), json!(true));
+    assert_eq!(SELECTED_REGION.get(storage).unwrap(), None);
+    storage.set_encryption_key(USER_DIR, encryption_key);
+    assert_eq!(SELECTED_REGION.get(storage).unwrap(), Some(region));
+    let got_region = storage.get_value(USER_DIR, REGION_KEY).unwrap();
+    assert!(matches!(got_region, StorageDataValue::Null));
+}
+
+#[test]
+fn same_device_in_two_accounts_is_isolated() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let dsn: String = Faker.fake();
+    let first = storage
+        .scope(ScopeId::Device {
+            account: "first@example.com".to_string(),
+            dsn: dsn.clone(),
+        })
+        .unwrap();
+    let second = storage
+        .scope(ScopeId::Device {
+            account: "second@example.com".to_string(),
+            dsn,
+        })
+        .unwrap();
+
+    first.set_value("name", "Kitchen").unwrap();
+
+    assert_eq!(
+        first.get::<String>("name").unwrap(),
+        Some("Kitchen".to_string())
+    );
+    assert_eq!(second.get::<String>("name").unwrap(), None);
+}
+
+#[test]
+fn wipe_scope_removes_only_one_account() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let dsn: String = Faker.fake();
+    let removed = ScopeId::Account("removed@example.com".to_string());
+    let kept = ScopeId::Account("kept@example.com".to_string());
+    storage
+        .scope(removed.clone())
+        .unwrap()
+        .set_value("token", "a")
+        .unwrap();
+    let removed_device = storage
+        .scope(ScopeId::Device {
+            account: "removed@example.com".to_string(),
+            dsn: dsn.clone(),
+        })
+        .unwrap();
+    removed_device.set_value("name", "Kitchen").unwrap();
+    let kept_scope = storage.scope(kept).unwrap();
+    kept_scope.set_value("token", "b").unwrap();
+    storage.set_value(APP_DIR, REGION_KEY, "UK").unwrap();
+
+    storage.wipe_scope(&removed).unwrap();
+
+    assert!(!storage.parent_path().join(removed.path()).exists());
+    let removed_device = storage.scope(removed_device.id().clone()).unwrap();
+    assert_eq!(removed_device.get::<String>("name").unwrap(), None);
+    assert_eq!(
+        kept_scope.get::<String>("token").unwrap(),
+        Some("b".to_string())
+    );
+    let region = storage.get_value(APP_DIR, REGION_KEY).unwrap();
+    assert!(matches!(region, StorageDataValue::String(region) if region == "UK"));
+}
+
+#[test]
+fn wipe_app_scope_keeps_accounts_and_storage_state() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let key: String = Faker.fake();
+    let account = storage
+        .scope(ScopeId::Account("kept@example.com".to_string()))
+        .unwrap();
+    account.set_value("token", "a").unwrap();
+    storage
+        .set_value(APP_DIR, &key, Faker.fake::<String>())
+        .unwrap();
+    storage.set_value(APP_DIR, REGION_KEY, "UK").unwrap();
+
+    storage.wipe_scope(&ScopeId::App).unwrap();
+
+    let got_value = storage.get_value(APP_DIR, &key).unwrap();
+    assert!(matches!(got_value, StorageDataValue::Null));
+    assert_eq!(
+        account.get::<String>("token").unwrap(),
+        Some("a".to_string())
+    );
+    let version = storage.get_value(APP_DIR, VERSION_KEY).unwrap();
+    assert_eq!(version, json!(LATEST_VERSION));
+    assert_eq!(
+        SELECTED_REGION.get(storage).unwrap(),
+        Some("UK".to_string())
+    );
+}
+
+#[test]
+fn wipe_account_storage_removes_only_one_account() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let removed = storage
+        .scope(ScopeId::Device {
+            account: "removed@example.com".to_string(),
+            dsn: Faker.fake(),
+        })
+        .unwrap();
+    removed.set_value("name", "Kitchen").unwrap();
+    let kept = storage
+        .scope(ScopeId::Account("kept@example.com".to_string()))
+        .unwrap();
+    kept.set_value("token", "b").unwrap();
+
+    storage.wipe_account_storage("removed@example.com").unwrap();
+
+    let removed_path = ScopeId::Account("removed@example.com".to_string()).path();
+    assert!(!storage.parent_path().join(removed_path).exists());
+    assert_eq!(kept.get::<String>("token").unwrap(), Some("b".to_string()));
+}
+
+#[test]
+fn scope_ids_are_kept_inside_accounts_dir() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let scope = ScopeId::Device {
+        account: "../..".to_string(),
+        dsn: "../app".to_string(),
+    };
+
+    storage.scope(scope.clone()).unwrap();
+
+    let path = storage.parent_path().join(scope.path());
+    assert!(path.is_dir());
+    assert!(path.starts_with(storage.parent_path().join(STORAGE_ACCOUNTS_DIR)));
+    assert_eq!(
+        path.components().count(),
+        storage.parent_path().components().count() + 4
+    );
+}
+
+#[test]
+fn values_are_stored_in_db_buckets_of_dirs() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let device_dir = ScopeId::Device {
+        account: Faker.fake(),
+        dsn: Faker.fake(),
+    }
+    .path();
+    let key: String = Faker.fake();
+
+    storage
+        .set_value(&device_dir, &key, json!({ "a": 1, "b": 2 }))
+        .unwrap();
+    storage
+        .merge_value(&device_dir, &key, json!({ "b": null, "c": 3 }))
+        .unwrap();
+
+    assert_eq!(
+        stored_values(storage, &device_dir.to_string_lossy()),
+        HashMap::from([(key.clone(), json!({ "a": 1, "c": 3 }))])
+    );
+    assert!(is_empty_dir(storage.parent_path().join(&device_dir)));
+    storage.remove_value(&device_dir, &key).unwrap();
+    assert!(stored_values(storage, &device_dir.to_string_lossy()).is_empty());
+}
+
+#[test]
+fn migration_imports_stores_into_db() {
+    let storage_path = TestStorage::gen_storage_path();
+    let workspace_path = storage_path.join(TOP_LEVEL_DIR);
+    let key: String = Faker.fake();
+    let value: String = Faker.fake();
+    let device_dir = Path::new(STORAGE_ACCOUNTS_DIR).join("account");
+    fs::create_dir_all(workspace_path.join(APP_DIR)).unwrap();
+    fs::create_dir_all(workspace_path.join(&device_dir)).unwrap();
+    fs::write(
+        workspace_path.join(APP_DIR).join(STORE_FILE),
+        json!({ "data": { VERSION_KEY: 2, REGION_KEY: "UK" } }).to_string(),
+    )
+    .unwrap();
+    fs::write(
+        workspace_path.join(&device_dir).join(STORE_FILE),
+        json!({ "data": { &key: value } }).to_string(),
+    )
+    .unwrap();
+    fs::write(
+        workspace_path.join(&device_dir).join(BACKUP_FILE),
+        DEFAULT_BYTES_IN_FILE,
+    )
+    .unwrap();
+
+    let test_storage = TestStorage::with_path(&storage_path);
+    let storage = test_storage.as_ref();
+
+    assert_eq!(storage.get_value(&device_dir, &key).unwrap(), json!(value));
+    assert_eq!(storage.get_value(APP_DIR, REGION_KEY).unwrap(), json!("UK"));
+    assert_eq!(
+        storage.get_value(APP_DIR, VERSION_KEY).unwrap(),
+        json!(LATEST_VERSION)
+    );
+    // No stale copies are left.
+    assert!(is_empty_dir(workspace_path.join(APP_DIR)));
+    assert!(is_empty_dir(workspace_path.join(&device_dir)));
+}
+
+#[test]
+fn migration_imports_encrypted_store_once_key_is_set() {
+    let encryption_key: [u8; 32] = Faker.fake();
+    // The user store is read by the region migration, so another dir is used.
+    let child_dir: String = Faker.fake();
+    let key: String = Faker.fake();
+    let value = format!("secret {}", Faker.fake::<String>());
+    let encrypted_storage = TestStorage::new();
+    encrypted_storage
+        .as_ref()
+        .set_encryption_key(&child_dir, encryption_key);
+    encrypted_storage
+        .as_ref()
+        .touch_file_for_child(
+            &child_dir,
+            Some(&json!({ "data": { &key: &value } }).to_string()),
+        )
+        .unwrap();
+    let user_store = fs::read(
+        encrypted_storage
+            .as_ref()
+            .parent_path()
+            .join(&child_dir)
+            .join(STORE_FILE),
+    )
+    .unwrap();
+
+    let test_storage = storage_with_store_files(&child_dir, &[(STORE_FILE, &user_store)]);
+    let storage = test_storage.as_ref();
+    assert_eq!(
+        storage.get_value(APP_DIR, VERSION_KEY).unwrap(),
+        json!(LATEST_VERSION)
+    );
+    assert_eq!(storage.get_value(&child_dir, &key).unwrap(), json!(null));
+
+    storage.set_encryption_key(&child_dir, encryption_key);
+
+    assert_eq!(storage.get_value(&child_dir, &key).unwrap(), json!(value));
+    assert!(is_empty_dir(storage.parent_path().join(&child_dir)));
+    assert!(!contains_bytes(&export_db(storage), value.as_bytes()));
+}
+
+#[test]
+fn wipe_removes_values_from_db() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let account = ScopeId::Account(Faker.fake());
+    storage.set_value(USER_DIR, "name", "a").unwrap();
+    storage.set_value(account.path(), "token", "b").unwrap();
+    storage.set_value(APP_DIR, "theme", "dark").unwrap();
+    storage.set_value(APP_DIR, REGION_KEY, "UK").unwrap();
+
+    storage.wipe_scope(&account).unwrap();
+    assert!(stored_values(storage, &account.path().to_string_lossy()).is_empty());
+    assert_eq!(storage.get_value(USER_DIR, "name").unwrap(), json!("a"));
+
+    storage.wipe_storage(true, false).unwrap();
+    assert!(stored_values(storage, USER_DIR).is_empty());
+    assert_eq!(
+        stored_values(storage, APP_DIR),
+        HashMap::from([
+            (VERSION_KEY.to_string(), json!(LATEST_VERSION)),
+            (REGION_KEY.to_string(), json!("UK"))
+        ])
+    );
+}
+
+#[test]
+fn usage_reports_child_dirs_and_db() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let dsn: String = Faker.fake();
+    storage.init_device_storage(&dsn).unwrap();
+    storage.set_value(&dsn, "name", "a".repeat(100)).unwrap();
+    storage.touch_file_for_child(&dsn, None).unwrap();
+    storage.db().flush().unwrap();
+
+    let usage = storage.usage().unwrap();
+
+    let dirs: Vec<_> = usage.dirs.keys().map(PathBuf::as_path).collect();
+    let mut expected = vec![Path::new(APP_DIR), Path::new(&dsn), Path::new(USER_DIR)];
+    expected.sort();
+    assert_eq!(dirs, expected);
+    assert_eq!(
+        usage.dirs[Path::new(&dsn)],
+        DEFAULT_BYTES_IN_FILE.len() as u64
+    );
+    assert!(usage.values[Path::new(&dsn)] > 100);
+    assert!(usage.db.unwrap() > 0);
+    assert_eq!(
+        usage.total(),
+        usage.dirs.values().sum::<u64>() + usage.db.unwrap()
+    );
+}
+
+#[test]
+fn set_value_over_quota_returns_error() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    storage.set_value(USER_DIR, "name", "a").unwrap();
+    storage.set_quota(USER_DIR, Some(200));
+
+    let result = storage.set_value(USER_DIR, "name", "b".repeat(200));
+
+    assert!(matches!(
+        result,
+        Err(StorageInteractError::QuotaExceeded { dir, quota: 200, .. }) if dir == Path::new(USER_DIR)
+    ));
+    assert_eq!(storage.get_value(USER_DIR, "name").unwrap(), json!("a"));
+    storage.set_value(USER_DIR, "name", "b").unwrap();
+    storage.set_quota(USER_DIR, None);
+    storage
+        .set_value(USER_DIR, "name", "b".repeat(200))
+        .unwrap();
+}
+
+#[test]
+fn quota_of_parent_dir_limits_child_dirs() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let account: String = Faker.fake();
+    let device = storage
+        .scope(ScopeId::Device {
+            account: account.clone(),
+            dsn: Faker.fake(),
+        })
+        .unwrap();
+    storage.set_quota(ScopeId::Account(account).path(), Some(500));
+
+    let result = device.set_value("cache", "a".repeat(500));
+
+    assert!(matches!(
+        result,
+        Err(StorageInteractError::QuotaExceeded { .. })
+    ));
+}
+
+#[test]
+fn quota_counts_existing_values_until_they_are_removed() {
+    let child_dir: String = Faker.fake();
+    let store = json!({ "data": { "old": "a".repeat(300) } }).to_string();
+    let test_storage = storage_with_store_files(&child_dir, &[(STORE_FILE, store.as_bytes())]);
+    let storage = test_storage.as_ref();
+    storage.set_quota(&child_dir, Some(500));
+
+    let result = storage.set_value(&child_dir, "new", "b".repeat(300));
+    assert!(matches!(
+        result,
+        Err(StorageInteractError::QuotaExceeded { .. })
+    ));
+    storage.remove_value(&child_dir, "old").unwrap();
+    storage
+        .set_value(&child_dir, "new", "b".repeat(300))
+        .unwrap();
+}
+
+#[test]
+fn touch_file_for_child_over_quota_returns_error() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    storage.set_quota(USER_DIR, Some(10));
+
+    let err = storage
+        .touch_file_for_child(USER_DIR, Some(&"a".repeat(100)))
+        .unwrap_err();
+
+    let err = err.into_inner().unwrap();
+    assert!(matches!(
+        err.downcast_ref::<StorageInteractError>(),
+        Some(StorageInteractError::QuotaExceeded { .. })
+    ));
+}
+
 #[test]
 fn make_dir_for_child_creates_dir() {
     let test_storage = TestStorage::new();
@@ -377,14 +868,703 @@ fn storage_does_not_have_data_races() {
     });
 }
 
-fn has_only_one_entry(path: impl AsRef<Path>, entry_name: &str) -> bool {
-    let mut iter = path.as_ref().read_dir().unwrap();
-    let equal_name = if let Some(entry) = iter.next() {
-        entry.unwrap().file_name() == entry_name
-    } else {
-        false
-    };
-    equal_name && iter.next().is_none()
+#[test]
+fn write_replaces_store_without_leftovers() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let child_dir: String = Faker.fake();
+    let first_bytes: String = Faker.fake();
+    let second_bytes: String = Faker.fake();
+    let child_path = storage.parent_path().join(&child_dir);
+
+    storage
+        .touch_file_for_child(&child_dir, Some(&first_bytes))
+        .unwrap();
+    storage
+        .touch_file_for_child(&child_dir, Some(&second_bytes))
+        .unwrap();
+
+    let entries: Vec<OsString> = child_path
+        .read_dir()
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect();
+    assert_eq!(entries, [STORE_FILE]);
+    let content = fs::read_to_string(child_path.join(STORE_FILE)).unwrap();
+    assert_eq!(content, second_bytes);
+}
+
+#[test]
+fn imports_missing_store_from_backup() {
+    let child_dir: String = Faker.fake();
+    let key: String = Faker.fake();
+    let value: String = Faker.fake();
+    let backup = json!({ "data": { &key: &value } }).to_string();
+
+    let test_storage = storage_with_store_files(&child_dir, &[(BACKUP_FILE, backup.as_bytes())]);
+    let storage = test_storage.as_ref();
+    let got_value = storage.get_value(&child_dir, &key).unwrap();
+
+    assert!(matches!(got_value, StorageDataValue::String(string) if string == value));
+    assert!(is_empty_dir(storage.parent_path().join(&child_dir)));
+}
+
+#[test]
+fn imports_corrupted_store_from_backup() {
+    let child_dir: String = Faker.fake();
+    let key: String = Faker.fake();
+    let value: String = Faker.fake();
+    let backup = json!({ "data": { &key: &value } }).to_string();
+    let content =
+        json!({ "data": { &key: &value, Faker.fake::<String>(): Faker.fake::<String>() } })
+            .to_string();
+
+    // Simulates a write interrupted halfway.
+    let test_storage = storage_with_store_files(
+        &child_dir,
+        &[
+            (BACKUP_FILE, backup.as_bytes()),
+            (STORE_FILE, &content.as_bytes()[..content.len() / 2]),
+        ],
+    );
+    let storage = test_storage.as_ref();
+    let got_value = storage.get_value(&child_dir, &key).unwrap();
+
+    assert!(matches!(got_value, StorageDataValue::String(string) if string == value));
+    assert!(is_empty_dir(storage.parent_path().join(&child_dir)));
+}
+
+#[test]
+fn corrupted_store_without_valid_backup_is_not_imported() {
+    let child_dir: String = Faker.fake();
+    let bytes: String = Faker.fake();
+
+    let test_storage = storage_with_store_files(&child_dir, &[(STORE_FILE, bytes.as_bytes())]);
+    let storage = test_storage.as_ref();
+
+    assert!(stored_values(storage, &child_dir).is_empty());
+    assert_eq!(storage.stream_buffer_from_child(&child_dir).unwrap(), bytes);
+}
+
+#[test]
+fn reads_values_from_cache() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let child_dir: String = Faker.fake();
+    let key: String = Faker.fake();
+    let value: String = Faker.fake();
+    storage.set_value(&child_dir, &key, &value).unwrap();
+
+    storage
+        .db()
+        .delete_bucket(format!("storage/{child_dir}"))
+        .unwrap();
+    let got_value = storage.get_value(&child_dir, &key).unwrap();
+
+    assert!(matches!(got_value, StorageDataValue::String(string) if string == value));
+}
+
+#[test]
+fn equivalent_dir_paths_share_cache() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let child_dir: String = Faker.fake();
+    let key: String = Faker.fake();
+    let value: String = Faker.fake();
+    storage
+        .set_value(&child_dir, &key, Faker.fake::<String>())
+        .unwrap();
+
+    storage
+        .set_value(format!("./{child_dir}/"), &key, &value)
+        .unwrap();
+    let got_value = storage.get_value(&child_dir, &key).unwrap();
+
+    assert!(matches!(got_value, StorageDataValue::String(string) if string == value));
+}
+
+#[test]
+fn touch_file_for_child_keeps_values() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let child_dir: String = Faker.fake();
+    let key: String = Faker.fake();
+    let value: String = Faker.fake();
+    storage.set_value(&child_dir, &key, &value).unwrap();
+
+    storage.touch_file_for_child(&child_dir, None).unwrap();
+    let got_value = storage.get_value(&child_dir, &key).unwrap();
+
+    assert!(matches!(got_value, StorageDataValue::String(string) if string == value));
+}
+
+#[test]
+fn remove_dir_for_child_invalidates_cache() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let child_dir: String = Faker.fake();
+    let key: String = Faker.fake();
+    storage
+        .set_value(&child_dir, &key, Faker.fake::<String>())
+        .unwrap();
+
+    storage.remove_dir_for_child(&child_dir).unwrap();
+    let result = storage.get_value(&child_dir, &key);
+
+    assert!(matches!(result, Err(StorageInteractError::Io(_))));
+    storage.make_dir_for_child(&child_dir).unwrap();
+    let got_value = storage.get_value(&child_dir, &key).unwrap();
+    assert!(matches!(got_value, StorageDataValue::Null));
+}
+
+#[test]
+fn clear_device_storage_invalidates_cache() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let dsn: String = Faker.fake();
+    let key: String = Faker.fake();
+    storage.init_device_storage(&dsn).unwrap();
+    storage
+        .set_value(&dsn, &key, Faker.fake::<String>())
+        .unwrap();
+
+    storage.clear_device_storage(&dsn).unwrap();
+    storage.init_device_storage(&dsn).unwrap();
+    let got_value = storage.get_value(&dsn, &key).unwrap();
+
+    assert!(matches!(got_value, StorageDataValue::Null));
+}
+
+#[test]
+fn wipe_invalidates_cache() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let key: String = Faker.fake();
+    storage
+        .set_value(USER_DIR, &key, Faker.fake::<String>())
+        .unwrap();
+
+    storage.wipe_storage(false, true).unwrap();
+    let result = storage.get_value(USER_DIR, &key);
+
+    assert!(matches!(result, Err(StorageInteractError::Io(_))));
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct TestSettings {
+    name: String,
+    volume: u8,
+}
+
+#[test]
+fn gets_typed_value() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let child_dir: String = Faker.fake();
+    let key: String = Faker.fake();
+    let settings = TestSettings {
+        name: Faker.fake(),
+        volume: Faker.fake(),
+    };
+
+    storage.set_value(&child_dir, &key, &settings).unwrap();
+    let got_settings: Option<TestSettings> = storage.get(&child_dir, &key).unwrap();
+
+    assert_eq!(got_settings, Some(settings));
+}
+
+#[test]
+fn gets_none_for_missing_typed_value() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+
+    let got_value: Option<String> = storage.get(USER_DIR, &Faker.fake::<String>()).unwrap();
+
+    assert_eq!(got_value, None);
+}
+
+#[test]
+fn typed_value_with_other_type_returns_type_mismatch() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let key: String = Faker.fake();
+
+    storage
+        .set_value(USER_DIR, &key, Faker.fake::<String>())
+        .unwrap();
+    let result: Result<Option<TestSettings>, _> = storage.get(USER_DIR, &key);
+
+    assert!(
+        matches!(result, Err(StorageInteractError::TypeMismatch { key: got_key, .. }) if got_key == key)
+    );
+}
+
+#[test]
+fn storage_key_sets_gets_and_removes_value() {
+    const SETTINGS: StorageKey<TestSettings> = StorageKey::new(APP_DIR, "settings");
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let settings = TestSettings {
+        name: Faker.fake(),
+        volume: Faker.fake(),
+    };
+
+    SETTINGS.set(storage, &settings).unwrap();
+    assert_eq!(SETTINGS.get(storage).unwrap(), Some(settings));
+
+    SETTINGS.remove(storage).unwrap();
+    assert_eq!(SETTINGS.get(storage).unwrap(), None);
+}
+
+#[test]
+fn selected_region_key_points_to_region_value() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let region: String = Faker.fake();
+
+    SELECTED_REGION.set(storage, &region).unwrap();
+    let got_value = storage.get_value(APP_DIR, REGION_KEY).unwrap();
+
+    assert!(matches!(got_value, StorageDataValue::String(string) if string == region));
+}
+
+#[test]
+fn gets_nested_value_by_json_pointer_and_dotted_path() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let units: String = Faker.fake();
+    let profile = json!({ "units": units, "a/b": { "c~d": 1 }, "list": [10, 20] });
+
+    storage.set_value(USER_DIR, "profile", &profile).unwrap();
+
+    let got_units = storage.get_path(USER_DIR, "/profile/units").unwrap();
+    assert_eq!(got_units, json!(units));
+    let got_units = storage.get_path(USER_DIR, "profile.units").unwrap();
+    assert_eq!(got_units, json!(units));
+    let got_escaped = storage.get_path(USER_DIR, "/profile/a~1b/c~0d").unwrap();
+    assert_eq!(got_escaped, json!(1));
+    let got_item = storage.get_path(USER_DIR, "profile.list.1").unwrap();
+    assert_eq!(got_item, json!(20));
+    let got_profile = storage.get_path(USER_DIR, "/profile").unwrap();
+    assert_eq!(got_profile, profile);
+    let got_missing = storage.get_path(USER_DIR, "/profile/missing").unwrap();
+    assert_eq!(got_missing, StorageDataValue::Null);
+}
+
+#[test]
+fn merges_value_with_json_merge_patch() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let key: String = Faker.fake();
+    storage
+        .set_value(
+            USER_DIR,
+            &key,
+            json!({ "a": "b", "c": { "d": "e", "f": "g" }, "list": [1, 2] }),
+        )
+        .unwrap();
+
+    storage
+        .merge_value(
+            USER_DIR,
+            &key,
+            json!({ "a": "z", "c": { "f": null }, "list": [3], "new": true }),
+        )
+        .unwrap();
+
+    let got_value = storage.get_value(USER_DIR, &key).unwrap();
+    assert_eq!(
+        got_value,
+        json!({ "a": "z", "c": { "d": "e" }, "list": [3], "new": true })
+    );
+}
+
+#[test]
+fn merge_creates_missing_value_and_null_patch_removes_it() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let child_dir: String = Faker.fake();
+    let key: String = Faker.fake();
+
+    storage
+        .merge_value(&child_dir, &key, json!({ "a": { "b": 1, "c": null } }))
+        .unwrap();
+    let got_value = storage.get_value(&child_dir, &key).unwrap();
+    assert_eq!(got_value, json!({ "a": { "b": 1 } }));
+
+    storage
+        .merge_value(&child_dir, &key, StorageDataValue::Null)
+        .unwrap();
+    assert!(stored_values(storage, &child_dir).is_empty());
+}
+
+#[test]
+fn updates_value_atomically() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.storage();
+    let key: String = Faker.fake();
+    let threads_count = 10;
+    let updates_count = 20;
+
+    thread::scope(|scope| {
+        for _ in 0..threads_count {
+            scope.spawn(|| {
+                for _ in 0..updates_count {
+                    storage
+                        .update(USER_DIR, &key, |value| {
+                            json!(value.as_u64().unwrap_or_default() + 1)
+                        })
+                        .unwrap();
+                }
+            });
+        }
+    });
+
+    let got_value = storage.get_value(USER_DIR, &key).unwrap();
+    assert_eq!(got_value, json!(threads_count * updates_count));
+}
+
+#[test]
+fn panicking_update_does_not_poison_storage() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let key: String = Faker.fake();
+    let value: String = Faker.fake();
+    storage.set_value(USER_DIR, &key, &value).unwrap();
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        storage.update(USER_DIR, &key, |_| panic!("update failed"))
+    }));
+
+    assert!(result.is_err());
+    let got_value = storage.get_value(USER_DIR, &key).unwrap();
+    assert!(matches!(got_value, StorageDataValue::String(string) if string == value));
+}
+
+#[test]
+fn default_merge_and_update_use_get_and_set() {
+    let storage = MapStorage::default();
+    let key: String = Faker.fake();
+
+    storage
+        .merge_value(USER_DIR, &key, json!({ "a": 1, "b": 2 }))
+        .unwrap();
+    storage
+        .merge_value(USER_DIR, &key, json!({ "b": null }))
+        .unwrap();
+    storage
+        .update(USER_DIR, &key, |value| json!([value]))
+        .unwrap();
+
+    let got_value = storage.get_value(USER_DIR, &key).unwrap();
+    assert_eq!(got_value, json!([{ "a": 1 }]));
+    storage
+        .merge_value(USER_DIR, &key, StorageDataValue::Null)
+        .unwrap();
+    assert!(storage.values.lock().unwrap().is_empty());
+}
+
+/// Implements only the required methods of [StorageInteract].
+#[derive(Default)]
+struct MapStorage {
+    values: Mutex<HashMap<(PathBuf, String), StorageDataValue>>,
+}
+
+impl StorageInteract for MapStorage {
+    fn get_value(
+        &self,
+        path: impl AsRef<Path>,
+        key: &str,
+    ) -> StorageInteractResult<StorageDataValue> {
+        let values = self.values.lock().unwrap();
+        let value = values.get(&(path.as_ref().to_path_buf(), key.to_string()));
+        Ok(value.cloned().unwrap_or_default())
+    }
+
+    fn set_value<T>(&self, path: impl AsRef<Path>, key: &str, value: T) -> StorageInteractResult<()>
+    where
+        T: Serialize,
+    {
+        let value = serde_json::to_value(value)?;
+        let mut values = self.values.lock().unwrap();
+        values.insert((path.as_ref().to_path_buf(), key.to_string()), value);
+        Ok(())
+    }
+
+    fn remove_value(&self, path: impl AsRef<Path>, key: &str) -> StorageInteractResult<()> {
+        let mut values = self.values.lock().unwrap();
+        values.remove(&(path.as_ref().to_path_buf(), key.to_string()));
+        Ok(())
+    }
+
+    fn init_device_storage(&self, _dsn: &str) -> StorageInteractResult<()> {
+        Ok(())
+    }
+
+    fn clear_device_storage(&self, _dsn: &str) -> StorageInteractResult<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn encrypted_dir_is_not_stored_as_plaintext() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let child_dir: String = Faker.fake();
+    let key: String = Faker.fake();
+    let value = format!("secret {}", Faker.fake::<String>());
+    storage.set_encryption_key(&child_dir, Faker.fake());
+
+    storage.set_value(&child_dir, &key, &value).unwrap();
+
+    assert!(!contains_bytes(&export_db(storage), value.as_bytes()));
+    let got_value = storage.get_value(&child_dir, &key).unwrap();
+    assert!(matches!(got_value, StorageDataValue::String(string) if string == value));
+}
+
+#[test]
+fn plaintext_values_are_encrypted_on_first_read() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let key: String = Faker.fake();
+    let value = format!("secret {}", Faker.fake::<String>());
+    storage.set_value(USER_DIR, &key, &value).unwrap();
+    assert!(contains_bytes(&export_db(storage), value.as_bytes()));
+
+    storage.set_encryption_key(USER_DIR, Faker.fake());
+    let got_value = storage.get_value(USER_DIR, &key).unwrap();
+
+    assert!(matches!(got_value, StorageDataValue::String(string) if string == value));
+    assert!(!contains_bytes(&export_db(storage), value.as_bytes()));
+}
+
+#[test]
+fn touch_file_for_child_of_encrypted_dir_leaves_no_plaintext() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let key: String = Faker.fake();
+    let value = format!("secret {}", Faker.fake::<String>());
+    let user_path = storage.parent_path().join(USER_DIR);
+    storage
+        .touch_file_for_child(
+            USER_DIR,
+            Some(&json!({ "data": { &key: &value } }).to_string()),
+        )
+        .unwrap();
+
+    storage.set_encryption_key(USER_DIR, Faker.fake());
+    storage.touch_file_for_child(USER_DIR, None).unwrap();
+
+    for entry in user_path.read_dir().unwrap() {
+        let content = fs::read(entry.unwrap().path()).unwrap();
+        assert!(!contains_bytes(&content, value.as_bytes()));
+    }
+}
+
+#[test]
+fn plaintext_store_of_encrypted_dir_is_rejected() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let child_dir: String = Faker.fake();
+    let store_path = storage.parent_path().join(&child_dir).join(STORE_FILE);
+    storage.set_encryption_key(&child_dir, Faker.fake());
+    storage.touch_file_for_child(&child_dir, None).unwrap();
+
+    let downgraded = json!({ "data": { Faker.fake::<String>(): Faker.fake::<String>() } });
+    fs::write(&store_path, downgraded.to_string()).unwrap();
+    let err = storage.stream_buffer_from_child(&child_dir).unwrap_err();
+
+    let err = err.into_inner().unwrap();
+    assert!(matches!(
+        err.downcast_ref::<StorageInteractError>(),
+        Some(StorageInteractError::PlaintextStore)
+    ));
+}
+
+#[test]
+fn encrypted_store_with_other_key_returns_error() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let child_dir: String = Faker.fake();
+    let key: String = Faker.fake();
+    storage.set_encryption_key(&child_dir, Faker.fake());
+    storage
+        .set_value(&child_dir, &key, Faker.fake::<String>())
+        .unwrap();
+
+    let mut other_key: [u8; 32] = Faker.fake();
+    other_key[0] = other_key[0].wrapping_add(1);
+    storage.set_encryption_key(&child_dir, other_key);
+    let result = storage.get_value(&child_dir, &key);
+
+    assert!(matches!(result, Err(StorageInteractError::Encryption(_))));
+}
+
+#[test]
+fn subscriber_receives_old_and_new_values() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let changes = record_changes(storage, USER_DIR, "name");
+
+    storage.set_value(USER_DIR, "name", "a").unwrap();
+    storage.set_value(USER_DIR, "name", "a").unwrap();
+    storage.merge_value(USER_DIR, "name", "b").unwrap();
+    storage.remove_value(USER_DIR, "name").unwrap();
+    storage.set_value(USER_DIR, "other", "c").unwrap();
+
+    let changes: Vec<_> = changes
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|change| (change.old_value.clone(), change.new_value.clone()))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            (json!(null), json!("a")),
+            (json!("a"), json!("b")),
+            (json!("b"), json!(null)),
+        ]
+    );
+}
+
+#[test]
+fn import_db_replaces_cached_values_and_notifies_subscribers() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let archive_path = storage.parent_path().with_file_name("archive");
+    storage.set_value(USER_DIR, "name", "a").unwrap();
+    storage.db().export_to_file(&archive_path).unwrap();
+    storage.set_value(USER_DIR, "name", "b").unwrap();
+    assert_eq!(storage.get_value(USER_DIR, "name").unwrap(), json!("b"));
+    let changes = record_changes(storage, USER_DIR, "name");
+
+    storage.import_db(&archive_path, ImportMode::Merge).unwrap();
+
+    assert_eq!(storage.get_value(USER_DIR, "name").unwrap(), json!("a"));
+    let changes = changes.lock().unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(
+        (&changes[0].old_value, &changes[0].new_value),
+        (&json!("b"), &json!("a"))
+    );
+}
+
+#[test]
+fn subscription_dir_is_normalized() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let changes = record_changes(storage, format!("./{USER_DIR}/"), "name");
+
+    storage.set_value(USER_DIR, "name", "a").unwrap();
+    storage.remove_dir_for_child(USER_DIR).unwrap();
+
+    let changes = changes.lock().unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0].dir, Path::new(USER_DIR));
+    assert_eq!(changes[1].new_value, json!(null));
+}
+
+#[test]
+fn unsubscribed_callback_is_not_called() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let callback_changes = changes.clone();
+    let id = storage.subscribe(USER_DIR, "name", move |change: &StorageChange| {
+        callback_changes.lock().unwrap().push(change.clone())
+    });
+
+    assert!(storage.unsubscribe(id));
+    storage.set_value(USER_DIR, "name", "a").unwrap();
+
+    assert!(changes.lock().unwrap().is_empty());
+    assert!(!storage.unsubscribe(id));
+}
+
+#[test]
+fn subscriber_is_notified_about_wiped_values() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref();
+    let account = ScopeId::Account(Faker.fake());
+    let scope = storage.scope(account.clone()).unwrap();
+    scope.set_value("token", "a").unwrap();
+    storage.set_value(APP_DIR, REGION_KEY, "UK").unwrap();
+    let token_changes = record_changes(storage, account.path(), "token");
+    let region_changes = record_changes(storage, APP_DIR, REGION_KEY);
+
+    storage.wipe_scope(&account).unwrap();
+    storage.wipe_storage(true, false).unwrap();
+
+    let token_changes = token_changes.lock().unwrap();
+    assert_eq!(token_changes.len(), 1);
+    assert_eq!(token_changes[0].dir, account.path());
+    assert_eq!(token_changes[0].old_value, json!("a"));
+    assert_eq!(token_changes[0].new_value, json!(null));
+    // The retained region didn't change.
+    assert!(region_changes.lock().unwrap().is_empty());
+}
+
+#[test]
+fn subscriber_can_use_storage() {
+    let test_storage = TestStorage::new();
+    let storage = test_storage.as_ref().clone();
+    let callback_storage = storage.clone();
+    storage.subscribe(USER_DIR, "name", move |change: &StorageChange| {
+        callback_storage
+            .set_value(USER_DIR, "copy", &change.new_value)
+            .unwrap();
+    });
+
+    storage.set_value(USER_DIR, "name", "a").unwrap();
+
+    let copy = storage.get_value(USER_DIR, "copy").unwrap();
+    assert_eq!(copy, json!("a"));
+}
+
+fn record_changes(
+    storage: &Storage,
+    dir: impl AsRef<Path>,
+    key: &str,
+) -> Arc<Mutex<Vec<StorageChange>>> {
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let callback_changes = changes.clone();
+    storage.subscribe(dir, key, move |change: &StorageChange| {
+        callback_changes.lock().unwrap().push(change.clone())
+    });
+    changes
+}
+
+/// Creates a storage whose `child_dir` already has the store `files`, e.g. left by a previous run.
+fn storage_with_store_files(child_dir: &str, files: &[(&str, &[u8])]) -> TestStorage {
+    let storage_path = TestStorage::gen_storage_path();
+    let child_path = storage_path.join(TOP_LEVEL_DIR).join(child_dir);
+    fs::create_dir_all(&child_path).unwrap();
+    for (file_name, content) in files {
+        fs::write(child_path.join(file_name), content).unwrap();
+    }
+    TestStorage::with_path(storage_path)
+}
+
+/// Returns the values of the `dir` as they're stored in the db.
+fn stored_values(storage: &Storage, dir: &str) -> HashMap<String, StorageDataValue> {
+    let bucket: Bucket<String, StorageDataValue, Json> = storage
+        .db()
+        .open_bucket_with_codec(format!("storage/{dir}"))
+        .unwrap();
+    bucket.iter().unwrap().map(Result::unwrap).collect()
+}
+
+fn export_db(storage: &Storage) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    storage.db().export(&mut bytes).unwrap();
+    bytes
+}
+
+fn contains_bytes(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
 }
 
 fn is_empty_dir(path: impl AsRef<Path>) -> bool {
@@ -1,4 +1,12 @@
-use mantle_utilities::execute_and_join_jobs;
+use mantle_utilities::{
+    configure_pool, execute_and_join_jobs, execute_job, execute_job_in, spawn, spawn_cancellable,
+    spawn_in, try_execute_and_join_jobs, JobError, PoolConfig, PoolError, RejectionPolicy,
+};
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::SeqCst;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
 
 #[test]
 fn test_execute_and_join_jobs() {
@@ -6,6 +14,227 @@ fn test_execute_and_join_jobs() {
     let jobs_iter = expected_results.iter().map(|&result| move || result);
     let result = execute_and_join_jobs(jobs_iter);
 
-    assert_eq!(expected_results.len(), result.len());
-    assert!(expected_results.into_iter().all(|r| result.contains(&r)));
+    assert_eq!(expected_results.to_vec(), result);
+}
+
+#[test]
+#[should_panic(expected = "A joined job failed: Job panicked: second failed")]
+fn execute_and_join_jobs_panics_if_a_job_panicked() {
+    let jobs: Vec<Box<dyn FnOnce() -> u32 + Send>> =
+        vec![Box::new(|| 1), Box::new(|| panic!("second failed"))];
+    execute_and_join_jobs(jobs);
+}
+
+#[test]
+fn try_execute_and_join_jobs_returns_panics_as_errors() {
+    let jobs: Vec<Box<dyn FnOnce() -> u32 + Send>> = vec![
+        Box::new(|| 1),
+        Box::new(|| panic!("second failed")),
+        Box::new(|| 3),
+    ];
+    let result = try_execute_and_join_jobs(jobs);
+
+    assert_eq!(
+        result,
+        [
+            Ok(1),
+            Err(JobError::Panicked("second failed".to_string())),
+            Ok(3)
+        ]
+    );
+}
+
+fn blocking_pool(name: &str, max_threads: usize, queue_capacity: usize, policy: RejectionPolicy) {
+    configure_pool(
+        name,
+        PoolConfig {
+            min_threads: 0,
+            max_threads,
+            queue_capacity: Some(queue_capacity),
+            rejection_policy: policy,
+            ..Default::default()
+        },
+    );
+}
+
+/// Occupies a worker until the returned sender is dropped.
+fn occupy(pool: &str) -> mpsc::Sender<()> {
+    let (started_tx, started_rx) = mpsc::channel();
+    let (tx, rx) = mpsc::channel::<()>();
+    let handle = execute_job_in(pool, move || {
+        started_tx.send(()).unwrap();
+        let _ = rx.recv();
+    });
+    assert!(handle.is_accepted());
+    started_rx.recv().unwrap();
+    tx
+}
+
+#[test]
+fn pool_never_exceeds_max_threads() {
+    configure_pool(
+        "bounded",
+        PoolConfig {
+            max_threads: 2,
+            ..Default::default()
+        },
+    );
+    let running = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = mpsc::channel();
+
+    for _ in 0..20 {
+        let (running, peak, tx) = (running.clone(), peak.clone(), tx.clone());
+        let handle = execute_job_in("bounded", move || {
+            let now_running = running.fetch_add(1, SeqCst) + 1;
+            peak.fetch_max(now_running, SeqCst);
+            thread::sleep(Duration::from_millis(5));
+            running.fetch_sub(1, SeqCst);
+            tx.send(()).unwrap();
+        });
+        assert!(handle.is_accepted());
+    }
+
+    for _ in 0..20 {
+        rx.recv().unwrap();
+    }
+    assert_eq!(peak.load(SeqCst), 2);
+}
+
+#[test]
+fn full_pool_rejects_or_drops_jobs() {
+    blocking_pool("rejecting", 1, 1, RejectionPolicy::Error);
+    let release = occupy("rejecting");
+    let queued = execute_job_in("rejecting", || ());
+    let rejected = execute_job_in("rejecting", || ());
+    assert!(queued.is_accepted());
+    assert_eq!(
+        rejected.rejection(),
+        Some(&PoolError::QueueFull("rejecting".to_string()))
+    );
+
+    blocking_pool("dropping", 1, 0, RejectionPolicy::Drop);
+    let _release = occupy("dropping");
+    let dropped = execute_job_in("dropping", || ());
+    assert_eq!(
+        dropped.rejection(),
+        Some(&PoolError::Dropped("dropping".to_string()))
+    );
+    drop(release);
+}
+
+#[test]
+fn full_blocking_pool_waits_for_room() {
+    blocking_pool("blocking", 1, 0, RejectionPolicy::Block);
+    let release = occupy("blocking");
+    let (tx, rx) = mpsc::channel();
+
+    let submitter = thread::spawn(move || {
+        let handle = execute_job_in("blocking", move || tx.send(()).unwrap());
+        handle.is_accepted()
+    });
+    assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+
+    drop(release);
+    assert!(submitter.join().unwrap());
+    assert!(rx.recv().is_ok());
+}
+
+#[test]
+fn unknown_pool_rejects_jobs() {
+    let handle = execute_job_in("unknown", || ());
+    assert!(!handle.is_accepted());
+    assert_eq!(
+        handle.rejection(),
+        Some(&PoolError::UnknownPool("unknown".to_string()))
+    );
+}
+
+#[test]
+fn panicking_job_keeps_the_worker() {
+    blocking_pool("panicking", 1, 1, RejectionPolicy::Block);
+    let (tx, rx) = mpsc::channel();
+
+    execute_job_in("panicking", || panic!("job panicked"));
+    execute_job_in("panicking", move || tx.send(()).unwrap());
+
+    assert!(rx.recv().is_ok());
+}
+
+#[test]
+fn execute_job_runs_on_default_pool() {
+    let (tx, rx) = mpsc::channel();
+    execute_job(move || {
+        tx.send(thread::current().name().map(str::to_string))
+            .unwrap()
+    });
+
+    let worker = rx.recv().unwrap();
+    assert_eq!(worker.as_deref(), Some("mantle-default-thread"));
+}
+
+#[test]
+fn joins_job_result() {
+    assert_eq!(spawn(|| 6 * 7).join(), Ok(42));
+    assert_eq!(
+        spawn(|| -> u32 { panic!("job failed with {}", 42) }).join(),
+        Err(JobError::Panicked("job failed with 42".to_string()))
+    );
+}
+
+#[test]
+fn join_timeout_and_try_result_leave_the_result_until_it_is_done() {
+    let (tx, rx) = mpsc::channel::<()>();
+    let mut handle = spawn(move || rx.recv().is_err());
+
+    assert_eq!(handle.try_result(), None);
+    assert_eq!(
+        handle.join_timeout(Duration::from_millis(10)),
+        Err(JobError::Timeout)
+    );
+
+    drop(tx);
+    assert_eq!(handle.join_timeout(Duration::from_secs(10)), Ok(true));
+    assert_eq!(handle.try_result(), Some(Err(JobError::ResultTaken)));
+}
+
+#[test]
+fn cancelled_job_does_not_start() {
+    blocking_pool("cancelling", 1, 1, RejectionPolicy::Block);
+    let release = occupy("cancelling");
+    let (tx, rx) = mpsc::channel();
+
+    let handle = spawn_in("cancelling", move || tx.send(()).unwrap());
+    handle.cancel();
+    drop(release);
+
+    assert_eq!(handle.join(), Err(JobError::Cancelled));
+    assert!(rx.recv().is_err());
+}
+
+#[test]
+fn running_job_stops_when_cancelled() {
+    let (started_tx, started_rx) = mpsc::channel();
+    let handle = spawn_cancellable(move |token| {
+        started_tx.send(()).unwrap();
+        while !token.is_cancelled() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        "stopped"
+    });
+
+    started_rx.recv().unwrap();
+    handle.cancel();
+    assert_eq!(handle.join(), Ok("stopped"));
+}
+
+#[test]
+fn rejected_job_has_no_result() {
+    let mut handle = spawn_in("missing", || 1);
+    let rejected = Err(JobError::Rejected(PoolError::UnknownPool(
+        "missing".to_string(),
+    )));
+
+    assert_eq!(handle.try_result(), Some(rejected.clone()));
+    assert_eq!(handle.join(), rejected);
 }